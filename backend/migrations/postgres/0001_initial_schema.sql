@@ -0,0 +1,39 @@
+-- Baseline schema, matching migrations/sqlite as of its version 1. Timestamps stay
+-- RFC 3339 text so both backends sort and compare them the same way.
+
+CREATE TABLE user_states (
+    username TEXT PRIMARY KEY,
+    text_entry TEXT NOT NULL,
+    categories JSONB NOT NULL DEFAULT '{}',
+    is_recording BOOLEAN NOT NULL,
+    last_saved TEXT,
+    last_data TEXT
+);
+
+CREATE TABLE data_logs (
+    id BIGSERIAL PRIMARY KEY,
+    username TEXT NOT NULL REFERENCES user_states(username),
+    text_entry TEXT NOT NULL,
+    categories JSONB NOT NULL DEFAULT '{}',
+    timestamp TEXT NOT NULL
+);
+
+CREATE TABLE users (
+    username TEXT PRIMARY KEY,
+    password_hash TEXT NOT NULL,
+    role TEXT NOT NULL DEFAULT 'participant',
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE auth_sessions (
+    token_hash TEXT PRIMARY KEY,
+    username TEXT NOT NULL REFERENCES users(username),
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL
+);
+
+CREATE TABLE observer_assignments (
+    observer TEXT NOT NULL REFERENCES users(username),
+    participant TEXT NOT NULL REFERENCES users(username),
+    PRIMARY KEY(observer, participant)
+);
@@ -0,0 +1,5 @@
+-- Keyset pagination in the log query API walks (timestamp, id) per user or globally
+
+CREATE INDEX idx_data_logs_username_timestamp ON data_logs(username, timestamp, id);
+
+CREATE INDEX idx_data_logs_timestamp ON data_logs(timestamp, id);
@@ -0,0 +1,19 @@
+-- A recording session spans one start/stop of recording; every sample taken
+-- while it is open points back at it.
+
+CREATE TABLE recording_sessions (
+    id BIGSERIAL PRIMARY KEY,
+    username TEXT NOT NULL REFERENCES users(username),
+    label TEXT,
+    started_at TEXT NOT NULL,
+    ended_at TEXT
+);
+
+-- At most one open session per user
+CREATE UNIQUE INDEX idx_recording_sessions_open ON recording_sessions(username) WHERE ended_at IS NULL;
+
+CREATE INDEX idx_recording_sessions_username ON recording_sessions(username, started_at);
+
+ALTER TABLE data_logs ADD COLUMN session_id BIGINT REFERENCES recording_sessions(id);
+
+CREATE INDEX idx_data_logs_session ON data_logs(session_id, timestamp, id);
@@ -0,0 +1,7 @@
+-- Samples whose CSV row hasn't been written yet. An entry is added in the same
+-- transaction as its data_logs row and removed once the row is in the CSV file,
+-- so anything left over after a failure or crash is replayed.
+
+CREATE TABLE csv_outbox (
+    log_id BIGINT PRIMARY KEY REFERENCES data_logs(id) ON DELETE CASCADE
+);
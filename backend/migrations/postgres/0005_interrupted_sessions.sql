@@ -0,0 +1,4 @@
+-- Sessions the server closed because it shut down, rather than because someone
+-- pressed stop; their ended_at is when the server went down.
+
+ALTER TABLE recording_sessions ADD COLUMN interrupted BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,43 @@
+-- Baseline schema. Uses IF NOT EXISTS so databases created before migrations
+-- existed are adopted instead of failing.
+
+CREATE TABLE IF NOT EXISTS user_states (
+    username TEXT PRIMARY KEY,
+    text_entry TEXT NOT NULL,
+    categories TEXT NOT NULL DEFAULT '{}',
+    is_recording BOOLEAN NOT NULL,
+    last_saved TEXT,
+    last_data TEXT
+);
+
+CREATE TABLE IF NOT EXISTS data_logs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    text_entry TEXT NOT NULL,
+    categories TEXT NOT NULL DEFAULT '{}',
+    timestamp TEXT NOT NULL,
+    FOREIGN KEY(username) REFERENCES user_states(username)
+);
+
+CREATE TABLE IF NOT EXISTS users (
+    username TEXT PRIMARY KEY,
+    password_hash TEXT NOT NULL,
+    role TEXT NOT NULL DEFAULT 'participant',
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS auth_sessions (
+    token_hash TEXT PRIMARY KEY,
+    username TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    FOREIGN KEY(username) REFERENCES users(username)
+);
+
+CREATE TABLE IF NOT EXISTS observer_assignments (
+    observer TEXT NOT NULL,
+    participant TEXT NOT NULL,
+    PRIMARY KEY(observer, participant),
+    FOREIGN KEY(observer) REFERENCES users(username),
+    FOREIGN KEY(participant) REFERENCES users(username)
+);
@@ -0,0 +1,30 @@
+-- A recording session spans one start/stop of recording; every sample taken
+-- while it is open points back at it.
+
+CREATE TABLE recording_sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    label TEXT,
+    started_at TEXT NOT NULL,
+    ended_at TEXT,
+    FOREIGN KEY(username) REFERENCES users(username)
+);
+
+-- At most one open session per user
+CREATE UNIQUE INDEX idx_recording_sessions_open ON recording_sessions(username) WHERE ended_at IS NULL;
+
+CREATE INDEX idx_recording_sessions_username ON recording_sessions(username, started_at);
+
+ALTER TABLE data_logs ADD COLUMN session_id INTEGER REFERENCES recording_sessions(id);
+
+CREATE INDEX idx_data_logs_session ON data_logs(session_id, timestamp, id);
+
+-- Anyone recording when this migration runs keeps recording, now inside a session
+INSERT INTO recording_sessions (username, started_at)
+SELECT username, strftime('%Y-%m-%dT%H:%M:%S+00:00', 'now')
+FROM user_states
+WHERE is_recording AND username IN (SELECT username FROM users);
+
+UPDATE user_states
+SET is_recording = FALSE
+WHERE username NOT IN (SELECT username FROM recording_sessions WHERE ended_at IS NULL);
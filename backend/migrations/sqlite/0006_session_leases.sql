@@ -0,0 +1,7 @@
+-- Which server samples an open session, and until when. Servers sharing a database
+-- renew the lease on every sample; the sessions of one that stopped renewing (it
+-- crashed, or lost the database) are picked up by another once the lease runs out.
+
+ALTER TABLE recording_sessions ADD COLUMN owner TEXT;
+
+ALTER TABLE recording_sessions ADD COLUMN lease_expires_at TEXT;
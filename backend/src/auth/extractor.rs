@@ -0,0 +1,68 @@
+use axum::{
+    extract::FromRequestParts,
+    http::{header::AUTHORIZATION, request::Parts},
+};
+use chrono::Utc;
+use std::sync::Arc;
+
+use super::token::hash_token;
+use crate::{
+    handlers::error::AppError,
+    models::{app_state::AppState, user::Role, username::Username},
+};
+
+/// The user behind a valid `Authorization: Bearer <token>` header.
+///
+/// Handlers that take this extractor never trust a username sent by the client.
+#[derive(Clone, Debug)]
+pub struct AuthUser {
+    pub username: Username,
+    pub role: Role,
+    pub token_hash: String,
+}
+
+impl AuthUser {
+    /// Resolves a raw session token, for transports that can't send headers (WebSockets).
+    pub async fn from_token(state: &AppState, token: &str) -> Result<Self, AppError> {
+        let token_hash = hash_token(token.trim());
+        let now = Utc::now().to_rfc3339();
+
+        match state.repo.find_session_user(&token_hash, &now).await {
+            Ok(Some((username, role))) => Ok(AuthUser {
+                username,
+                role,
+                token_hash,
+            }),
+            Ok(None) => Err(AppError::Unauthorized("Session is invalid or has expired".into())),
+            Err(err) => Err(AppError::internal("Failed to look up session", err)),
+        }
+    }
+
+    /// The user's role as it stands now, or `None` once the session this user
+    /// authenticated with has ended or expired.
+    pub async fn current_role(&self, state: &AppState) -> Option<Role> {
+        let now = Utc::now().to_rfc3339();
+        match state.repo.find_session_user(&self.token_hash, &now).await {
+            Ok(Some((_, role))) => Some(role),
+            _ => None,
+        }
+    }
+}
+
+impl FromRequestParts<Arc<AppState>> for AuthUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| AppError::Unauthorized("Missing bearer token".into()))?;
+
+        AuthUser::from_token(state, token).await
+    }
+}
@@ -0,0 +1,4 @@
+pub mod extractor;
+pub mod password;
+pub mod permissions;
+pub mod token;
@@ -0,0 +1,42 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use std::sync::LazyLock;
+
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// A hash no password is checked against on purpose: logins for unknown usernames verify
+/// against it, so they take as long as logins for real ones.
+static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| {
+    hash_password("not the password of any account").expect("hashing a constant cannot fail")
+});
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+    Ok(hash.to_string())
+}
+
+/// Returns `false` both for a wrong password and for a hash that cannot be parsed,
+/// so callers never reveal which of the two happened.
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// `verify_password` against the user's hash, or against a dummy one when there is no such
+/// user, so the time a failed login takes doesn't tell whether the username exists.
+pub fn verify_login(password: &str, password_hash: Option<&str>) -> bool {
+    match password_hash {
+        Some(password_hash) => verify_password(password, password_hash),
+        None => {
+            verify_password(password, &DUMMY_HASH);
+            false
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use axum::{extract::Request, middleware::Next, response::Response};
+
+use super::extractor::AuthUser;
+use crate::{
+    handlers::error::AppError,
+    models::{app_state::AppState, user::Role, username::Username},
+};
+
+/// Route layer for everything under `/api/admin`.
+pub async fn require_admin(
+    user: AuthUser,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if user.role != Role::Admin {
+        return Err(forbidden("This action requires the admin role"));
+    }
+    Ok(next.run(request).await)
+}
+
+/// Route layer for endpoints that act on another user's data.
+pub async fn require_observer(
+    user: AuthUser,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if user.role == Role::Participant {
+        return Err(forbidden("This action requires the observer or admin role"));
+    }
+    Ok(next.run(request).await)
+}
+
+/// Checks that `user` may read and write the data of `target`.
+///
+/// Everyone may access their own data, admins may access anyone's, and observers
+/// only the participants assigned to them.
+pub async fn ensure_can_access(
+    state: &AppState,
+    user: &AuthUser,
+    target: &Username,
+) -> Result<(), AppError> {
+    if user.username == *target || user.role == Role::Admin {
+        return Ok(());
+    }
+
+    if user.role == Role::Observer {
+        let assigned = state
+            .repo
+            .is_assigned(&user.username, target)
+            .await
+            .map_err(|err| AppError::internal("Failed to check assignments", err))?;
+        if assigned {
+            return Ok(());
+        }
+        return Err(forbidden(format!(
+            "Participant '{}' is not assigned to you",
+            target
+        )));
+    }
+
+    Err(forbidden("You may only access your own data"))
+}
+
+fn forbidden(message: impl Into<String>) -> AppError {
+    AppError::Forbidden(message.into())
+}
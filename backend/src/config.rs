@@ -0,0 +1,205 @@
+//! Settings of the backend binary, layered: built-in defaults, then a TOML file, then
+//! environment variables, then command line flags. Each layer only sets what it names.
+//!
+//! CSV and log writer tuning is read from its own `CSV_*` and `LOG_*` variables; see
+//! `CsvSettings::from_env` and `LogWriterSettings::from_env`.
+
+use axum::http::HeaderValue;
+use serde::Deserialize;
+use std::{
+    fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use thiserror::Error;
+use tracing_subscriber::EnvFilter;
+
+use crate::recorder::SAMPLE_INTERVAL;
+
+/// Read when no other file is named, if it exists.
+pub const DEFAULT_CONFIG_FILE: &str = "backend.toml";
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("failed to read {}: {source}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("invalid configuration in {}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("invalid CORS origin '{0}' (expected * or an origin like https://example.org)")]
+    CorsOrigin(String),
+    #[error("invalid sample interval {0} (expected a number of seconds above zero)")]
+    SampleInterval(f64),
+    #[error("invalid log level '{0}': {1}")]
+    LogLevel(String, String),
+}
+
+/// One layer of settings. Fields left `None` fall through to the layer below.
+///
+/// The same fields are read from the TOML file and, through clap, from flags and the
+/// environment variable next to each flag.
+#[derive(Clone, Debug, Default, Deserialize, clap::Args)]
+#[serde(deny_unknown_fields)]
+#[command(about = None, long_about = None, next_help_heading = "Settings")]
+pub struct ConfigLayer {
+    /// Address the HTTP server listens on [default: 127.0.0.1:3000]
+    #[arg(long, env = "BIND_ADDR", value_name = "ADDR", global = true)]
+    pub bind: Option<SocketAddr>,
+    /// Directory holding the database, codebook and CSV files [default: data]
+    #[arg(long, env = "DATA_DIR", value_name = "DIR", global = true)]
+    pub data_dir: Option<PathBuf>,
+    /// sqlite:// or postgres:// URL [default: SQLite at <data-dir>/app.db]
+    #[arg(long, env = "DATABASE_URL", value_name = "URL", global = true)]
+    pub database_url: Option<String>,
+    /// Directory of the per-user CSV files [default: <data-dir>/csv]
+    #[arg(long, env = "CSV_DIR", value_name = "DIR", global = true)]
+    pub csv_dir: Option<PathBuf>,
+    /// Origins allowed to call the API from a browser, or * for any [default: *]
+    #[arg(
+        long = "cors-origin",
+        env = "CORS_ORIGINS",
+        value_name = "ORIGIN",
+        value_delimiter = ',',
+        global = true
+    )]
+    pub cors_origins: Option<Vec<String>>,
+    /// Seconds between the samples of a recording [default: 5]
+    #[arg(long, env = "SAMPLE_INTERVAL_SECS", value_name = "SECS", global = true)]
+    pub sample_interval_secs: Option<f64>,
+    /// Directory of the compiled frontend [default: target/site, or the copy built in]
+    #[arg(long, env = "LEPTOS_SITE_ROOT", value_name = "DIR", global = true)]
+    pub site_root: Option<PathBuf>,
+    /// tracing filter, e.g. info or backend=debug,sqlx=warn [default: info]
+    #[arg(long, env = "RUST_LOG", value_name = "FILTER", global = true)]
+    pub log_level: Option<String>,
+}
+
+impl ConfigLayer {
+    /// Reads a TOML file of settings.
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        toml::from_str(&contents).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Takes whatever `self` leaves unset from `below`.
+    pub fn or(self, below: ConfigLayer) -> ConfigLayer {
+        ConfigLayer {
+            bind: self.bind.or(below.bind),
+            data_dir: self.data_dir.or(below.data_dir),
+            database_url: self.database_url.or(below.database_url),
+            csv_dir: self.csv_dir.or(below.csv_dir),
+            cors_origins: self.cors_origins.or(below.cors_origins),
+            sample_interval_secs: self.sample_interval_secs.or(below.sample_interval_secs),
+            site_root: self.site_root.or(below.site_root),
+            log_level: self.log_level.or(below.log_level),
+        }
+    }
+}
+
+/// Which origins may call the API from a browser.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CorsOrigins {
+    Any,
+    List(Vec<HeaderValue>),
+}
+
+/// Settings with every layer applied and checked.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub bind: SocketAddr,
+    pub data_dir: PathBuf,
+    pub database_url: String,
+    pub csv_dir: PathBuf,
+    pub cors_origins: CorsOrigins,
+    pub sample_interval: Duration,
+    /// Unset means the copy built into the binary, if there is one; see `Site::from_config`
+    pub site_root: Option<PathBuf>,
+    pub log_level: String,
+}
+
+impl Config {
+    /// Applies `overrides` from the command line and environment on top of the TOML file
+    /// at `file`, or `backend.toml` if there is one, on top of the defaults.
+    pub fn load(file: Option<&Path>, overrides: ConfigLayer) -> Result<Self, ConfigError> {
+        let file = match file {
+            Some(path) => ConfigLayer::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                ConfigLayer::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => ConfigLayer::default(),
+        };
+        Self::resolve(overrides.or(file))
+    }
+
+    /// Fills in the defaults for whatever `layer` leaves unset.
+    pub fn resolve(layer: ConfigLayer) -> Result<Self, ConfigError> {
+        let data_dir = layer.data_dir.unwrap_or_else(|| PathBuf::from("data"));
+        let database_url = layer
+            .database_url
+            .unwrap_or_else(|| format!("sqlite://{}", data_dir.join("app.db").display()));
+        let csv_dir = layer.csv_dir.unwrap_or_else(|| data_dir.join("csv"));
+
+        let cors_origins = match layer.cors_origins {
+            None => CorsOrigins::Any,
+            Some(origins) if origins.iter().any(|origin| origin.trim() == "*") => {
+                if origins.len() > 1 {
+                    return Err(ConfigError::CorsOrigin(origins.join(",")));
+                }
+                CorsOrigins::Any
+            }
+            Some(origins) => CorsOrigins::List(
+                origins
+                    .iter()
+                    .map(|origin| parse_origin(origin.trim()))
+                    .collect::<Result<_, _>>()?,
+            ),
+        };
+
+        let sample_interval = match layer.sample_interval_secs {
+            Some(secs) => Duration::try_from_secs_f64(secs)
+                .ok()
+                .filter(|interval| !interval.is_zero())
+                .ok_or(ConfigError::SampleInterval(secs))?,
+            None => SAMPLE_INTERVAL,
+        };
+
+        let log_level = layer.log_level.unwrap_or_else(|| "info".to_string());
+        EnvFilter::try_new(&log_level)
+            .map_err(|err| ConfigError::LogLevel(log_level.clone(), err.to_string()))?;
+
+        Ok(Self {
+            bind: layer.bind.unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 3000))),
+            data_dir,
+            database_url,
+            csv_dir,
+            cors_origins,
+            sample_interval,
+            site_root: layer.site_root,
+            log_level,
+        })
+    }
+
+    /// The filter the log level names; checked to parse when the config was resolved.
+    pub fn log_filter(&self) -> EnvFilter {
+        EnvFilter::new(&self.log_level)
+    }
+}
+
+/// `scheme://host[:port]`, as browsers send it in `Origin`.
+fn parse_origin(origin: &str) -> Result<HeaderValue, ConfigError> {
+    let invalid = || ConfigError::CorsOrigin(origin.to_string());
+    let (scheme, host) = origin.split_once("://").ok_or_else(invalid)?;
+    if !matches!(scheme, "http" | "https") || host.is_empty() || host.contains('/') {
+        return Err(invalid());
+    }
+    HeaderValue::from_str(origin).map_err(|_| invalid())
+}
@@ -0,0 +1,93 @@
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::models::username::Username;
+
+/// How well the open-writer cache is doing since the server started.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct WriterCacheStats {
+    /// Samples appended through a writer that was already open
+    pub hits: u64,
+    /// Samples that had to open or reopen their file first
+    pub misses: u64,
+    /// Writers closed to stay under the limit or because they sat idle
+    pub evictions: u64,
+    /// Writers open right now
+    pub open: usize,
+}
+
+/// Tracks which users hold an open CSV writer and picks the ones to close: the least
+/// recently used once there are more than `capacity`, and any idle for `idle_timeout`.
+pub(super) struct WriterCache {
+    capacity: usize,
+    idle_timeout: Duration,
+    last_used: HashMap<Username, Instant>,
+    stats: WriterCacheStats,
+}
+
+impl WriterCache {
+    pub(super) fn new(capacity: usize, idle_timeout: Duration) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            idle_timeout,
+            last_used: HashMap::new(),
+            stats: WriterCacheStats::default(),
+        }
+    }
+
+    /// Records a write by `username`, `hit` if their writer was already open. Returns
+    /// the users whose writers must be closed to make room.
+    pub(super) fn touch(&mut self, username: &Username, hit: bool, now: Instant) -> Vec<Username> {
+        if hit {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+        }
+        self.last_used.insert(username.clone(), now);
+
+        let mut evicted = Vec::new();
+        while self.last_used.len() > self.capacity {
+            let oldest = self
+                .last_used
+                .iter()
+                .filter(|(candidate, _)| *candidate != username)
+                .min_by_key(|(_, used)| **used)
+                .map(|(candidate, _)| candidate.clone());
+            let Some(oldest) = oldest else { break };
+            self.last_used.remove(&oldest);
+            evicted.push(oldest);
+        }
+        self.stats.evictions += evicted.len() as u64;
+        evicted
+    }
+
+    /// Forgets the writers idle for longer than the timeout and returns their users.
+    pub(super) fn take_idle(&mut self, now: Instant) -> Vec<Username> {
+        let idle: Vec<_> = self
+            .last_used
+            .iter()
+            .filter(|(_, used)| now.saturating_duration_since(**used) >= self.idle_timeout)
+            .map(|(username, _)| username.clone())
+            .collect();
+        for username in &idle {
+            self.last_used.remove(username);
+        }
+        self.stats.evictions += idle.len() as u64;
+        idle
+    }
+
+    /// Forgets `username`'s writer after it was closed for another reason, like rotation.
+    pub(super) fn forget(&mut self, username: &Username) {
+        self.last_used.remove(username);
+    }
+
+    pub(super) fn stats(&self) -> WriterCacheStats {
+        WriterCacheStats {
+            open: self.last_used.len(),
+            ..self.stats
+        }
+    }
+}
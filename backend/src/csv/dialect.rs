@@ -0,0 +1,254 @@
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, NaiveDateTime, Utc,
+};
+use serde::{Deserialize, Serialize};
+use std::{io, str::FromStr};
+use thiserror::Error;
+
+/// What Excel expects in front of a UTF-8 file to read it as UTF-8.
+pub const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+#[derive(Debug, Error)]
+pub enum DialectError {
+    #[error("'{0}' is not a delimiter (expected one ASCII character, or tab)")]
+    Delimiter(String),
+    #[error("'{0}' is not a quote style (expected necessary, always, non-numeric or never)")]
+    Quote(String),
+    #[error("'{0}' is not a line ending (expected crlf or lf)")]
+    LineEnding(String),
+    #[error("'{0}' is not a timestamp format (expected rfc3339 or a strftime pattern)")]
+    TimestampFormat(String),
+}
+
+/// How a CSV file is spelled. The default, RFC 4180 with `\n` line endings, is what files
+/// from before dialects existed were written in.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CsvDialect {
+    pub delimiter: char,
+    pub quote_style: QuoteStyle,
+    pub line_ending: LineEnding,
+    /// Whether the file starts with a UTF-8 byte order mark
+    pub bom: bool,
+    pub timestamp_format: TimestampFormat,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            quote_style: QuoteStyle::default(),
+            line_ending: LineEnding::default(),
+            bom: false,
+            timestamp_format: TimestampFormat::default(),
+        }
+    }
+}
+
+impl CsvDialect {
+    /// Parses a delimiter: a single ASCII character, or `tab`.
+    pub fn parse_delimiter(raw: &str) -> Result<char, DialectError> {
+        match raw {
+            "tab" | "\\t" | "\t" => Ok('\t'),
+            _ => {
+                let mut chars = raw.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(delimiter), None)
+                        if delimiter.is_ascii() && !matches!(delimiter, '"' | '\r' | '\n') =>
+                    {
+                        Ok(delimiter)
+                    }
+                    _ => Err(DialectError::Delimiter(raw.to_string())),
+                }
+            }
+        }
+    }
+
+    pub fn writer_builder(&self) -> csv::WriterBuilder {
+        let mut builder = csv::WriterBuilder::new();
+        builder
+            .delimiter(self.delimiter_byte())
+            .quote_style(self.quote_style.into())
+            .terminator(self.line_ending.into());
+        builder
+    }
+
+    /// Reads files of this dialect; rows may have other lengths than the header.
+    pub fn reader_builder(&self) -> csv::ReaderBuilder {
+        let mut builder = csv::ReaderBuilder::new();
+        builder.delimiter(self.delimiter_byte()).flexible(true);
+        builder
+    }
+
+    /// Writes what goes before the header: the byte order mark, if the dialect has one.
+    pub fn write_preamble(&self, out: &mut impl io::Write) -> io::Result<()> {
+        if self.bom {
+            out.write_all(UTF8_BOM)?;
+        }
+        Ok(())
+    }
+
+    /// `record` as this dialect writes it. Records end in their sample's RFC 3339
+    /// timestamp, which is spelled in `timestamp_format`.
+    pub fn record(&self, mut record: Vec<String>) -> Vec<String> {
+        if let Some(timestamp) = record.last_mut() {
+            *timestamp = self.timestamp_format.format(timestamp);
+        }
+        record
+    }
+
+    /// An index written before dialects existed may hold anything; fall back to commas.
+    fn delimiter_byte(&self) -> u8 {
+        u8::try_from(self.delimiter).unwrap_or(b',')
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuoteStyle {
+    /// Only fields holding a delimiter, quote or line break
+    #[default]
+    Necessary,
+    Always,
+    /// Every field that isn't a number
+    NonNumeric,
+    /// Never, even where the file then can't be read back
+    Never,
+}
+
+impl FromStr for QuoteStyle {
+    type Err = DialectError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "" | "necessary" => Ok(QuoteStyle::Necessary),
+            "always" => Ok(QuoteStyle::Always),
+            "non-numeric" | "nonnumeric" => Ok(QuoteStyle::NonNumeric),
+            "never" => Ok(QuoteStyle::Never),
+            _ => Err(DialectError::Quote(raw.to_string())),
+        }
+    }
+}
+
+impl From<QuoteStyle> for csv::QuoteStyle {
+    fn from(style: QuoteStyle) -> Self {
+        match style {
+            QuoteStyle::Necessary => csv::QuoteStyle::Necessary,
+            QuoteStyle::Always => csv::QuoteStyle::Always,
+            QuoteStyle::NonNumeric => csv::QuoteStyle::NonNumeric,
+            QuoteStyle::Never => csv::QuoteStyle::Never,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    Crlf,
+    #[default]
+    Lf,
+}
+
+impl FromStr for LineEnding {
+    type Err = DialectError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "crlf" | "windows" => Ok(LineEnding::Crlf),
+            "" | "lf" | "unix" => Ok(LineEnding::Lf),
+            _ => Err(DialectError::LineEnding(raw.to_string())),
+        }
+    }
+}
+
+impl From<LineEnding> for csv::Terminator {
+    fn from(ending: LineEnding) -> Self {
+        match ending {
+            LineEnding::Crlf => csv::Terminator::CRLF,
+            LineEnding::Lf => csv::Terminator::Any(b'\n'),
+        }
+    }
+}
+
+/// How timestamps are spelled, kept in an index as `rfc3339` or the strftime pattern.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum TimestampFormat {
+    /// As recorded, e.g. `2024-05-01T09:30:00.123456789+00:00`
+    #[default]
+    Rfc3339,
+    /// A strftime pattern, applied in UTC
+    Strftime(String),
+}
+
+impl TimestampFormat {
+    /// Spells an RFC 3339 timestamp in this format; one that doesn't parse stays as is.
+    pub fn format(&self, rfc3339: &str) -> String {
+        match self {
+            TimestampFormat::Rfc3339 => rfc3339.to_string(),
+            TimestampFormat::Strftime(pattern) => DateTime::parse_from_rfc3339(rfc3339)
+                .map(|timestamp| timestamp.with_timezone(&Utc).format(pattern).to_string())
+                .unwrap_or_else(|_| rfc3339.to_string()),
+        }
+    }
+
+    /// Reads a timestamp spelled in this format. Patterns without a time zone are UTC.
+    pub fn parse(&self, raw: &str) -> Option<DateTime<Utc>> {
+        match self {
+            TimestampFormat::Rfc3339 => DateTime::parse_from_rfc3339(raw)
+                .ok()
+                .map(|timestamp| timestamp.with_timezone(&Utc)),
+            TimestampFormat::Strftime(pattern) => DateTime::parse_from_str(raw, pattern)
+                .map(|timestamp| timestamp.with_timezone(&Utc))
+                .or_else(|_| {
+                    NaiveDateTime::parse_from_str(raw, pattern).map(|naive| naive.and_utc())
+                })
+                .ok(),
+        }
+    }
+
+    /// `timestamp` as precisely as this format keeps it, so it compares with timestamps
+    /// read back from a file.
+    pub fn as_written(&self, timestamp: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            TimestampFormat::Rfc3339 => Some(timestamp),
+            TimestampFormat::Strftime(pattern) => {
+                self.parse(&timestamp.format(pattern).to_string())
+            }
+        }
+    }
+}
+
+impl FromStr for TimestampFormat {
+    type Err = DialectError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let format = TimestampFormat::from(raw.to_string());
+        if let TimestampFormat::Strftime(pattern) = &format
+            && (pattern.is_empty() || StrftimeItems::new(pattern).any(|item| item == Item::Error))
+        {
+            return Err(DialectError::TimestampFormat(raw.to_string()));
+        }
+        Ok(format)
+    }
+}
+
+impl From<String> for TimestampFormat {
+    fn from(raw: String) -> Self {
+        if raw.trim().eq_ignore_ascii_case("rfc3339") {
+            TimestampFormat::Rfc3339
+        } else {
+            TimestampFormat::Strftime(raw)
+        }
+    }
+}
+
+impl From<TimestampFormat> for String {
+    fn from(format: TimestampFormat) -> Self {
+        match format {
+            TimestampFormat::Rfc3339 => "rfc3339".to_string(),
+            TimestampFormat::Strftime(pattern) => pattern,
+        }
+    }
+}
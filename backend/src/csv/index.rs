@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use super::dialect::CsvDialect;
+
+/// The index file kept in each user's CSV directory.
+pub const INDEX_FILE: &str = "index.json";
+
+/// Every CSV file of one user, oldest first. At most the last one is still open.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CsvIndex {
+    pub files: Vec<CsvFileEntry>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CsvFileEntry {
+    /// File name within the user's directory
+    pub file: String,
+    /// What the rotation policy grouped into this file, e.g. a day or a session
+    pub segment: String,
+    pub opened_at: String,
+    /// `None` while samples are still being appended
+    pub closed_at: Option<String>,
+    /// Data rows, not counting the header
+    pub rows: u64,
+    /// Size on disk, as of the last time the file was opened or closed
+    pub bytes: u64,
+    pub compressed: bool,
+    pub columns: Vec<String>,
+    /// Missing from indexes written before dialects existed, whose files are RFC 4180
+    #[serde(default)]
+    pub dialect: CsvDialect,
+}
+
+impl CsvIndex {
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(INDEX_FILE)
+    }
+
+    /// Reads the index in `dir`; a directory without one has no files yet.
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        match fs::read_to_string(Self::path(dir)) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(io::Error::other),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Replaces the index in `dir` in one step, so readers never see half of it.
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        let path = Self::path(dir);
+        let partial = path.with_extension("json.tmp");
+        fs::write(
+            &partial,
+            serde_json::to_vec_pretty(self).map_err(io::Error::other)?,
+        )?;
+        fs::rename(&partial, &path)
+    }
+
+    pub fn open_file(&self) -> Option<&CsvFileEntry> {
+        self.files.last().filter(|entry| entry.closed_at.is_none())
+    }
+
+    pub fn contains(&self, file: &str) -> bool {
+        self.files.iter().any(|entry| entry.file == file)
+    }
+}
@@ -1,2 +1,13 @@
-// This module contains CSV-related functionality
-// It is a placeholder for potential future CSV handling abstractions and code organization
\ No newline at end of file
+//! Per-user CSV logs written alongside `data_logs`.
+//!
+//! `CsvExporter` appends samples to each user's current file and starts a new one
+//! whenever the `RotationPolicy` says so. The files of a user live in their own
+//! directory, listed in order by an `index.json` next to them. Only a bounded number
+//! of files is held open at a time; the rest are reopened when they're next written.
+//! Each file keeps the `CsvDialect` it was started with.
+
+pub mod cache;
+pub mod dialect;
+pub mod index;
+pub mod rotation;
+pub mod writer;
@@ -0,0 +1,188 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{str::FromStr, time::Duration};
+use thiserror::Error;
+
+use super::dialect::{CsvDialect, DialectError};
+
+#[derive(Debug, Error)]
+pub enum CsvSettingsError {
+    #[error("invalid CSV_ROTATION '{0}' (expected none, day, session or size:<bytes>[K|M|G])")]
+    Rotation(String),
+    #[error("invalid CSV_COMPRESS '{0}' (expected true or false)")]
+    Compress(String),
+    #[error("invalid {0} '{1}' (expected a whole number above zero)")]
+    Limit(&'static str, String),
+    #[error("invalid CSV_BOM '{0}' (expected true or false)")]
+    Bom(String),
+    #[error("invalid {0}: {1}")]
+    Dialect(&'static str, DialectError),
+}
+
+/// How many CSV writers stay open at once unless `CSV_MAX_OPEN_WRITERS` says otherwise.
+pub const DEFAULT_MAX_OPEN_WRITERS: usize = 128;
+/// How long a CSV writer may sit unused before it's closed, unless `CSV_WRITER_IDLE_SECS`
+/// says otherwise.
+pub const DEFAULT_WRITER_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// When a user's samples move on to a new CSV file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase", tag = "policy")]
+pub enum RotationPolicy {
+    /// One file per user, appended to forever
+    #[default]
+    None,
+    /// A new file each UTC day
+    Day,
+    /// A new file per recording session
+    Session,
+    /// A new file once the current one reaches `max_bytes`
+    Size { max_bytes: u64 },
+}
+
+impl RotationPolicy {
+    /// The file a sample belongs in, as far as the policy is concerned. Samples with
+    /// the same segment share a file; `Size` only ever has one segment and rotates
+    /// on file size instead.
+    pub fn segment(&self, session_id: Option<i64>, timestamp: DateTime<Utc>) -> String {
+        match self {
+            RotationPolicy::None | RotationPolicy::Size { .. } => String::new(),
+            RotationPolicy::Day => timestamp.format("%Y-%m-%d").to_string(),
+            RotationPolicy::Session => match session_id {
+                Some(id) => format!("session-{}", id),
+                None => "no-session".to_string(),
+            },
+        }
+    }
+}
+
+impl FromStr for RotationPolicy {
+    type Err = CsvSettingsError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let invalid = || CsvSettingsError::Rotation(raw.to_string());
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "" | "none" => Ok(RotationPolicy::None),
+            "day" | "daily" => Ok(RotationPolicy::Day),
+            "session" => Ok(RotationPolicy::Session),
+            other => {
+                let size = other.strip_prefix("size:").ok_or_else(invalid)?;
+                let (digits, multiplier) = match size.char_indices().last() {
+                    Some((at, 'k')) => (&size[..at], 1 << 10),
+                    Some((at, 'm')) => (&size[..at], 1 << 20),
+                    Some((at, 'g')) => (&size[..at], 1 << 30),
+                    _ => (size, 1),
+                };
+                let max_bytes = digits
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|count| count.checked_mul(multiplier))
+                    .filter(|&max_bytes| max_bytes > 0)
+                    .ok_or_else(invalid)?;
+                Ok(RotationPolicy::Size { max_bytes })
+            }
+        }
+    }
+}
+
+/// How per-user CSV files are rotated and kept.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CsvSettings {
+    pub rotation: RotationPolicy,
+    /// Gzip files once they are closed
+    pub compress: bool,
+    /// Most files held open for appending at once; the least recently used is closed
+    pub max_open_writers: usize,
+    /// Files not appended to for this long are closed until the next sample
+    pub writer_idle_timeout: Duration,
+    /// How new files and exports are spelled; existing files keep their own
+    pub dialect: CsvDialect,
+}
+
+impl Default for CsvSettings {
+    fn default() -> Self {
+        Self {
+            rotation: RotationPolicy::default(),
+            compress: false,
+            max_open_writers: DEFAULT_MAX_OPEN_WRITERS,
+            writer_idle_timeout: DEFAULT_WRITER_IDLE_TIMEOUT,
+            dialect: CsvDialect::default(),
+        }
+    }
+}
+
+impl CsvSettings {
+    /// Reads `CSV_ROTATION`, `CSV_COMPRESS`, `CSV_MAX_OPEN_WRITERS` and
+    /// `CSV_WRITER_IDLE_SECS`, defaulting to a single uncompressed file per user, and
+    /// the dialect from `CSV_DELIMITER`, `CSV_QUOTE`, `CSV_LINE_ENDING`, `CSV_BOM` and
+    /// `CSV_TIMESTAMP_FORMAT`, defaulting to RFC 4180 with `\n` line endings.
+    pub fn from_env() -> Result<Self, CsvSettingsError> {
+        let defaults = Self::default();
+        let rotation = match std::env::var("CSV_ROTATION") {
+            Ok(raw) => raw.parse()?,
+            Err(_) => defaults.rotation,
+        };
+        let compress = match std::env::var("CSV_COMPRESS") {
+            Ok(raw) => flag(&raw).ok_or(CsvSettingsError::Compress(raw))?,
+            Err(_) => defaults.compress,
+        };
+        let max_open_writers = match std::env::var("CSV_MAX_OPEN_WRITERS") {
+            Ok(raw) => positive("CSV_MAX_OPEN_WRITERS", raw)? as usize,
+            Err(_) => defaults.max_open_writers,
+        };
+        let writer_idle_timeout = match std::env::var("CSV_WRITER_IDLE_SECS") {
+            Ok(raw) => Duration::from_secs(positive("CSV_WRITER_IDLE_SECS", raw)?),
+            Err(_) => defaults.writer_idle_timeout,
+        };
+        Ok(Self {
+            rotation,
+            compress,
+            max_open_writers,
+            writer_idle_timeout,
+            dialect: dialect_from_env(defaults.dialect)?,
+        })
+    }
+}
+
+fn dialect_from_env(defaults: CsvDialect) -> Result<CsvDialect, CsvSettingsError> {
+    fn var<T>(
+        name: &'static str,
+        parse: impl FnOnce(&str) -> Result<T, DialectError>,
+    ) -> Result<Option<T>, CsvSettingsError> {
+        match std::env::var(name) {
+            Ok(raw) => parse(&raw)
+                .map(Some)
+                .map_err(|err| CsvSettingsError::Dialect(name, err)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    let bom = match std::env::var("CSV_BOM") {
+        Ok(raw) => flag(&raw).ok_or(CsvSettingsError::Bom(raw))?,
+        Err(_) => defaults.bom,
+    };
+    Ok(CsvDialect {
+        delimiter: var("CSV_DELIMITER", CsvDialect::parse_delimiter)?
+            .unwrap_or(defaults.delimiter),
+        quote_style: var("CSV_QUOTE", str::parse)?.unwrap_or(defaults.quote_style),
+        line_ending: var("CSV_LINE_ENDING", str::parse)?.unwrap_or(defaults.line_ending),
+        bom,
+        timestamp_format: var("CSV_TIMESTAMP_FORMAT", str::parse)?
+            .unwrap_or(defaults.timestamp_format),
+    })
+}
+
+fn flag(raw: &str) -> Option<bool> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "" | "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn positive(var: &'static str, raw: String) -> Result<u64, CsvSettingsError> {
+    match raw.trim().parse::<u64>() {
+        Ok(value) if value > 0 => Ok(value),
+        _ => Err(CsvSettingsError::Limit(var, raw)),
+    }
+}
@@ -1,48 +1,678 @@
-use std::{fs::{self, File}, io, path::Path};
+use chrono::{DateTime, Utc};
 use csv::Writer;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
 
+use super::{
+    cache::{WriterCache, WriterCacheStats},
+    dialect::{CsvDialect, TimestampFormat},
+    index::{CsvFileEntry, CsvIndex},
+    rotation::{CsvSettings, RotationPolicy},
+};
+use crate::models::{codebook::Codebook, user_state::DataLog, username::Username};
+
+/// One sample, ready to be written as a CSV row.
+pub struct CsvRow {
+    pub session_id: Option<i64>,
+    pub timestamp: DateTime<Utc>,
+    pub record: Vec<String>,
+}
+
+impl CsvRow {
+    /// The row a logged sample is written as under `codebook`.
+    pub fn from_log(log: &DataLog, codebook: &Codebook) -> Self {
+        Self {
+            session_id: log.session_id,
+            timestamp: DateTime::parse_from_rfc3339(&log.timestamp)
+                .map(|timestamp| timestamp.with_timezone(&Utc))
+                .unwrap_or_else(|_| Utc::now()),
+            record: codebook.csv_record(log),
+        }
+    }
+}
+
+/// Holds one user's files against changes by other processes, e.g. a rebuild run from
+/// the command line while the server appends to them. Released when dropped.
+pub struct UserLock {
+    _file: File,
+}
+
+/// Writes every user's samples to their CSV files under `base_dir`, rotating them as
+/// `settings` say. Each user has a directory named after [`Username::file_stem`].
+///
+/// At most `settings.max_open_writers` files are open at once. A file closed to make
+/// room, or after sitting idle, is flushed and reopened for appending on its next row.
+///
+/// A user's directory replaced by another process is noticed before the next access and
+/// loaded again; rows it already holds are then skipped, see [`CsvExporter::rebuild`].
 pub struct CsvExporter {
-    base_dir: String,
+    base_dir: PathBuf,
+    settings: CsvSettings,
+    header: Vec<String>,
+    logs: Mutex<HashMap<Username, Arc<Mutex<UserLog>>>>,
+    writers: Mutex<WriterCache>,
 }
 
 impl CsvExporter {
-    pub fn new(base_dir: &str) -> Self {
+    pub fn new(base_dir: PathBuf, settings: CsvSettings, header: Vec<String>) -> Self {
+        let writers = WriterCache::new(settings.max_open_writers, settings.writer_idle_timeout);
         Self {
-            base_dir: base_dir.to_string(),
+            base_dir,
+            settings,
+            header,
+            logs: Mutex::new(HashMap::new()),
+            writers: Mutex::new(writers),
         }
     }
-    
-    pub fn ensure_dir_exists(&self) -> io::Result<()> {
-        fs::create_dir_all(&self.base_dir)?;
+
+    pub fn base_dir(&self) -> &Path {
+        &self.base_dir
+    }
+
+    pub fn settings(&self) -> &CsvSettings {
+        &self.settings
+    }
+
+    /// Appends one sample to `username`'s current file, first starting a new file if
+    /// the sample doesn't belong in the current one. The row is buffered; see
+    /// [`CsvExporter::flush`].
+    pub fn append(&self, username: &Username, row: &CsvRow) -> csv::Result<()> {
+        let log = self.user_log(username)?;
+        let hit = {
+            let mut log = log.lock().unwrap();
+            self.reload_if_replaced(&mut log, username)?;
+            let hit = log.writer.is_some();
+            log.append(&self.settings, &self.header, row)?;
+            hit
+        };
+
+        // Other users' files are closed only once this one's lock is released
+        let evicted = self
+            .writers
+            .lock()
+            .unwrap()
+            .touch(username, hit, Instant::now());
+        for username in evicted {
+            tracing::debug!("Closing the least recently used CSV file, of {}", username);
+            self.release_writer(&username);
+        }
         Ok(())
     }
-    
-    pub fn get_writer(&self, filename: &str) -> io::Result<Writer<File>> {
-        self.ensure_dir_exists()?;
-        
-        let path = Path::new(&self.base_dir).join(filename);
-        let file_exists = path.exists();
-        
+
+    /// Hands the rows buffered for each of `usernames` to the operating system, and
+    /// waits for them to reach the disk if `fsync` is set.
+    pub fn flush<'a>(
+        &self,
+        usernames: impl IntoIterator<Item = &'a Username>,
+        fsync: bool,
+    ) -> io::Result<()> {
+        for username in usernames {
+            let Some(log) = self.logs.lock().unwrap().get(username).cloned() else {
+                continue;
+            };
+            let mut log = log.lock().unwrap();
+            if let Some(writer) = log.writer.as_mut() {
+                writer.flush()?;
+                if fsync {
+                    writer.get_ref().sync_data()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes every open file, e.g. before the server stops.
+    pub fn flush_all(&self, fsync: bool) -> io::Result<()> {
+        let usernames: Vec<_> = self.logs.lock().unwrap().keys().cloned().collect();
+        self.flush(&usernames, fsync)
+    }
+
+    /// Every one of `username`'s files, oldest first, with its data rows.
+    pub fn read_rows(
+        &self,
+        username: &Username,
+    ) -> io::Result<Vec<(CsvFileEntry, Vec<Vec<String>>)>> {
+        let log = self.user_log(username)?;
+        let mut log = log.lock().unwrap();
+        self.reload_if_replaced(&mut log, username)?;
+        if let Some(writer) = log.writer.as_mut() {
+            writer.flush()?;
+        }
+
+        let mut files = Vec::with_capacity(log.index.files.len());
+        for entry in &log.index.files {
+            let records = read_records(&log.dir.join(&entry.file), entry)?;
+            files.push((entry.clone(), records));
+        }
+        Ok(files)
+    }
+
+    /// Makes the next appends for `username` skip rows no newer than the newest row
+    /// already in their files, until one is newer. For rows that may have been written
+    /// before the server stopped.
+    pub fn skip_written(&self, username: &Username) -> io::Result<()> {
+        let log = self.user_log(username)?;
+        let mut log = log.lock().unwrap();
+        self.reload_if_replaced(&mut log, username)?;
+        log.skip_through = log.last_timestamp()?;
+        Ok(())
+    }
+
+    /// Locks `username`'s files against other processes, waiting while one of them holds
+    /// the lock. Appends and rebuilds take it, so neither sees the other half done.
+    pub fn lock_user(&self, username: &Username) -> io::Result<UserLock> {
+        fs::create_dir_all(&self.base_dir)?;
+        // Dot-prefixed names never clash with a user's directory
+        let path = self.base_dir.join(format!(".{}.lock", username.file_stem()));
         let file = File::options()
             .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        file.lock()?;
+        Ok(UserLock { _file: file })
+    }
+
+    /// Replaces all of `username`'s files with `rows`. They are written into a directory
+    /// of their own, which takes the old directory's place only once it is complete.
+    ///
+    /// Callers hold [`CsvExporter::lock_user`] from before they read `rows` until this
+    /// returns. Samples committed before the rows were read but still on their way to
+    /// the files are then skipped, here or in another process that finds the directory
+    /// replaced: nothing up to the last row written here is appended again.
+    pub fn rebuild(
+        &self,
+        username: &Username,
+        rows: impl IntoIterator<Item = CsvRow>,
+    ) -> csv::Result<CsvIndex> {
+        let log = self.user_log(username)?;
+        let mut log = log.lock().unwrap();
+        log.writer = None;
+        self.writers.lock().unwrap().forget(username);
+
+        // Dot-prefixed names never clash with a user's directory
+        let staging = self.base_dir.join(format!(".{}.rebuild", log.stem));
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        fs::create_dir_all(&staging)?;
+        let mut fresh = UserLog {
+            dir: staging.clone(),
+            dir_id: None,
+            stem: log.stem.clone(),
+            index: CsvIndex::default(),
+            writer: None,
+            skip_through: None,
+        };
+        let mut last = None;
+        for row in rows {
+            fresh.append(&self.settings, &self.header, &row)?;
+            // Compared with the samples still on their way, not with the files
+            last = Some((row.timestamp, TimestampFormat::Rfc3339));
+        }
+        if let Some(mut writer) = fresh.writer.take() {
+            writer.flush()?;
+            writer.get_ref().sync_all()?;
+        }
+        fresh.index.save(&staging)?;
+
+        let replaced = self.base_dir.join(format!(".{}.old", log.stem));
+        if replaced.exists() {
+            fs::remove_dir_all(&replaced)?;
+        }
+        if log.dir.exists() {
+            fs::rename(&log.dir, &replaced)?;
+        }
+        fs::rename(&staging, &log.dir)?;
+        fs::remove_dir_all(&replaced).or_else(|err| match err.kind() {
+            io::ErrorKind::NotFound => Ok(()),
+            _ => Err(err),
+        })?;
+
+        log.index = fresh.index;
+        log.dir_id = dir_identity(&log.dir);
+        log.skip_through = last;
+        Ok(log.index.clone())
+    }
+
+    /// Closes the files nobody has appended to for the idle timeout and returns how
+    /// many there were.
+    pub fn close_idle_writers(&self) -> usize {
+        let idle = self.writers.lock().unwrap().take_idle(Instant::now());
+        for username in &idle {
+            self.release_writer(username);
+        }
+        if !idle.is_empty() {
+            let stats = self.writer_stats();
+            tracing::info!(
+                "Closed {} idle CSV file(s); {} open, {} hits, {} misses, {} evictions so far",
+                idle.len(),
+                stats.open,
+                stats.hits,
+                stats.misses,
+                stats.evictions
+            );
+        }
+        idle.len()
+    }
+
+    pub fn writer_stats(&self) -> WriterCacheStats {
+        self.writers.lock().unwrap().stats()
+    }
+
+    /// Closes the file of a session that has ended, when files are kept per session.
+    pub fn end_session(&self, username: &Username, session_id: i64) -> io::Result<()> {
+        if self.settings.rotation != RotationPolicy::Session {
+            return Ok(());
+        }
+        let segment = self.settings.rotation.segment(Some(session_id), Utc::now());
+
+        let log = self.user_log(username)?;
+        let mut log = log.lock().unwrap();
+        self.reload_if_replaced(&mut log, username)?;
+        if log.index.open_file().is_some_and(|open| open.segment == segment) {
+            log.close(self.settings.compress)?;
+            self.writers.lock().unwrap().forget(username);
+        }
+        Ok(())
+    }
+
+    /// `username`'s files, oldest first, with up to date counts for the open one.
+    pub fn index(&self, username: &Username) -> io::Result<CsvIndex> {
+        let log = self.user_log(username)?;
+        let mut log = log.lock().unwrap();
+        self.reload_if_replaced(&mut log, username)?;
+        log.refresh_open_entry()?;
+        Ok(log.index.clone())
+    }
+
+    /// Where one of `username`'s files is, if the index lists it.
+    pub fn file_path(&self, username: &Username, file: &str) -> io::Result<Option<PathBuf>> {
+        let log = self.user_log(username)?;
+        let mut log = log.lock().unwrap();
+        self.reload_if_replaced(&mut log, username)?;
+        Ok(log.index.contains(file).then(|| log.dir.join(file)))
+    }
+
+    /// Flushes and drops `username`'s writer, leaving their file open in the index.
+    fn release_writer(&self, username: &Username) {
+        let Some(log) = self.logs.lock().unwrap().get(username).cloned() else {
+            return;
+        };
+        let writer = log.lock().unwrap().writer.take();
+        if let Some(Err(err)) = writer.map(|mut writer| writer.flush()) {
+            tracing::warn!("Failed to flush the CSV file of {}: {}", username, err);
+        }
+    }
+
+    /// Loads `username`'s files again if another process swapped their directory.
+    fn reload_if_replaced(&self, log: &mut UserLog, username: &Username) -> io::Result<()> {
+        if dir_identity(&log.dir) == log.dir_id {
+            return Ok(());
+        }
+        *log = UserLog::load(&self.base_dir, username)?;
+        log.skip_through = log.last_timestamp()?;
+        self.writers.lock().unwrap().forget(username);
+        tracing::info!("The CSV files of {} were replaced on disk; loaded them again", username);
+        Ok(())
+    }
+
+    fn user_log(&self, username: &Username) -> io::Result<Arc<Mutex<UserLog>>> {
+        let mut logs = self.logs.lock().unwrap();
+        if let Some(log) = logs.get(username) {
+            return Ok(Arc::clone(log));
+        }
+
+        let log = Arc::new(Mutex::new(UserLog::load(&self.base_dir, username)?));
+        logs.insert(username.clone(), Arc::clone(&log));
+        Ok(log)
+    }
+}
+
+/// The files of one user and the writer of the open one, if any.
+struct UserLog {
+    dir: PathBuf,
+    /// Which directory `index` was loaded from, to notice it being replaced
+    dir_id: Option<DirIdentity>,
+    stem: String,
+    index: CsvIndex,
+    /// Opened lazily, so an index left open by a previous run or a writer closed by the
+    /// cache is picked up again
+    writer: Option<Writer<File>>,
+    /// Rows up to this time, as precise as the format keeps it, are already written
+    skip_through: Option<(DateTime<Utc>, TimestampFormat)>,
+}
+
+impl UserLog {
+    fn load(base_dir: &Path, username: &Username) -> io::Result<Self> {
+        let stem = username.file_stem();
+        let dir = base_dir.join(&stem);
+        let mut log = Self {
+            index: CsvIndex::load(&dir)?,
+            dir_id: dir_identity(&dir),
+            dir,
+            stem,
+            writer: None,
+            skip_through: None,
+        };
+        if log.index.files.is_empty() {
+            log.adopt_flat_files(base_dir, username)?;
+        }
+        // The previous run may have appended rows after it last saved the index
+        let dir = log.dir.clone();
+        if let Some(open) = log.index.files.last_mut().filter(|entry| entry.closed_at.is_none()) {
+            let path = dir.join(&open.file);
+            if fs::metadata(&path).is_ok_and(|meta| meta.len() > 0) {
+                open.rows = read_counts(&path, &open.dialect)?.1;
+            }
+        }
+        Ok(log)
+    }
+
+    /// Moves files from before rotation existed (`<name>.csv` and the
+    /// `<stem>.<timestamp>.csv` archives) into the user's directory as closed files.
+    fn adopt_flat_files(&mut self, base_dir: &Path, username: &Username) -> io::Result<()> {
+        let mut found = Vec::new();
+        for entry in fs::read_dir(base_dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let current = name == format!("{}.csv", self.stem)
+                || name == format!("{}.csv", username.as_str());
+            let archived = name
+                .strip_prefix(&format!("{}.", self.stem))
+                .and_then(|rest| rest.strip_suffix(".csv"))
+                .is_some_and(is_archive_timestamp);
+            if (current || archived) && entry.file_type()?.is_file() {
+                found.push((entry.metadata()?.modified()?, entry.path()));
+            }
+        }
+        if found.is_empty() {
+            return Ok(());
+        }
+        // Archives are older than the file that replaced them
+        found.sort();
+
+        fs::create_dir_all(&self.dir)?;
+        self.dir_id = dir_identity(&self.dir);
+        for (modified, path) in found {
+            let file = self.unused_name(&format!("{}_legacy", self.stem));
+            let target = self.dir.join(&file);
+            fs::rename(&path, &target)?;
+            let (columns, rows) = read_counts(&target, &CsvDialect::default())?;
+            let modified = DateTime::<Utc>::from(modified).to_rfc3339();
+            self.index.files.push(CsvFileEntry {
+                file,
+                segment: "legacy".to_string(),
+                opened_at: modified.clone(),
+                closed_at: Some(modified),
+                rows,
+                bytes: fs::metadata(&target)?.len(),
+                compressed: false,
+                columns,
+                dialect: CsvDialect::default(),
+            });
+        }
+        self.index.save(&self.dir)
+    }
+
+    fn append(
+        &mut self,
+        settings: &CsvSettings,
+        header: &[String],
+        row: &CsvRow,
+    ) -> csv::Result<()> {
+        if let Some((through, format)) = &self.skip_through {
+            if format.as_written(row.timestamp).is_some_and(|timestamp| timestamp <= *through) {
+                return Ok(());
+            }
+            self.skip_through = None;
+        }
+        let segment = settings.rotation.segment(row.session_id, row.timestamp);
+
+        if let Some(open) = self.index.open_file() {
+            let full = match settings.rotation {
+                RotationPolicy::Size { max_bytes } => self.open_size()? >= max_bytes,
+                _ => false,
+            };
+            // So does a codebook or dialect change, so each file has one header and dialect
+            if open.segment != segment
+                || open.columns != header
+                || open.dialect != settings.dialect
+                || full
+            {
+                self.close(settings.compress)?;
+            }
+        }
+        if self.index.open_file().is_none() {
+            self.open_new(settings, segment, header, row.timestamp)?;
+        }
+
+        if self.writer.is_none() {
+            let open = self.index.files.last_mut().expect("a file is open by now");
+            self.writer = Some(reopen(&self.dir, open)?);
+        }
+        let writer = self.writer.as_mut().expect("writer was just opened");
+        let open = self.index.files.last_mut().expect("a file is open by now");
+        // Left in the writer's buffer until `CsvExporter::flush` or the file is closed
+        writer.write_record(open.dialect.record(row.record.clone()))?;
+        open.rows += 1;
+        Ok(())
+    }
+
+    fn open_new(
+        &mut self,
+        settings: &CsvSettings,
+        segment: String,
+        header: &[String],
+        opened_at: DateTime<Utc>,
+    ) -> io::Result<()> {
+        let base = match settings.rotation {
+            RotationPolicy::None => self.stem.clone(),
+            RotationPolicy::Day | RotationPolicy::Session => format!("{}_{}", self.stem, segment),
+            RotationPolicy::Size { .. } => {
+                format!("{}_{}", self.stem, opened_at.format("%Y%m%dT%H%M%S"))
+            }
+        };
+        let file = self.unused_name(&base);
+
+        fs::create_dir_all(&self.dir)?;
+        self.dir_id = dir_identity(&self.dir);
+        let mut created = File::options()
+            .create_new(true)
             .append(true)
-            .open(&path)?;
-            
-        let mut writer = csv::WriterBuilder::new().from_writer(file);
-        
-        if !file_exists {
-            writer.write_record(&[
-                "username",
-                "text_entry",
-                "category1",
-                "category2",
-                "category3",
-                "category4",
-                "timestamp",
-            ])?;
+            .open(self.dir.join(&file))?;
+        settings.dialect.write_preamble(&mut created)?;
+        let mut writer = settings.dialect.writer_builder().from_writer(created);
+        writer.write_record(header)?;
+        writer.flush()?;
+
+        self.index.files.push(CsvFileEntry {
+            file,
+            segment,
+            opened_at: opened_at.to_rfc3339(),
+            closed_at: None,
+            rows: 0,
+            bytes: writer.get_ref().metadata()?.len(),
+            compressed: false,
+            columns: header.to_vec(),
+            dialect: settings.dialect.clone(),
+        });
+        self.index.save(&self.dir)?;
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    /// Closes the open file, compressing it if asked to.
+    fn close(&mut self, compress: bool) -> io::Result<()> {
+        if let Some(mut writer) = self.writer.take() {
             writer.flush()?;
         }
-        
-        Ok(writer)
+        let Some(open) = self
+            .index
+            .files
+            .last_mut()
+            .filter(|entry| entry.closed_at.is_none())
+        else {
+            return Ok(());
+        };
+
+        let path = self.dir.join(&open.file);
+        if compress && path.exists() {
+            let file = format!("{}.gz", open.file);
+            gzip(&path, &self.dir.join(&file))?;
+            fs::remove_file(&path)?;
+            open.file = file;
+            open.compressed = true;
+        }
+        open.bytes = fs::metadata(self.dir.join(&open.file))
+            .map(|meta| meta.len())
+            .unwrap_or(0);
+        open.closed_at = Some(Utc::now().to_rfc3339());
+        self.index.save(&self.dir)
     }
-}
\ No newline at end of file
+
+    /// The timestamp of the newest row, the last value in it, and the format it is in.
+    fn last_timestamp(&mut self) -> io::Result<Option<(DateTime<Utc>, TimestampFormat)>> {
+        if let Some(writer) = self.writer.as_mut() {
+            writer.flush()?;
+        }
+        for entry in self.index.files.iter().rev() {
+            let records = read_records(&self.dir.join(&entry.file), entry)?;
+            if let Some(last) = records.last().and_then(|record| record.last()) {
+                let format = &entry.dialect.timestamp_format;
+                return Ok(format.parse(last).map(|last| (last, format.clone())));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Size of the open file on disk.
+    fn open_size(&self) -> io::Result<u64> {
+        match (&self.writer, self.index.open_file()) {
+            (Some(writer), _) => Ok(writer.get_ref().metadata()?.len()),
+            (None, Some(open)) => Ok(fs::metadata(self.dir.join(&open.file))
+                .map(|meta| meta.len())
+                .unwrap_or(0)),
+            (None, None) => Ok(0),
+        }
+    }
+
+    fn refresh_open_entry(&mut self) -> io::Result<()> {
+        let bytes = self.open_size()?;
+        if let Some(open) = self
+            .index
+            .files
+            .last_mut()
+            .filter(|entry| entry.closed_at.is_none())
+        {
+            open.bytes = bytes;
+        }
+        Ok(())
+    }
+
+    /// `base.csv`, or `base-1.csv`, `base-2.csv`, ... if that is taken.
+    fn unused_name(&self, base: &str) -> String {
+        (0..)
+            .map(|n| match n {
+                0 => format!("{}.csv", base),
+                n => format!("{}-{}.csv", base, n),
+            })
+            .find(|file| {
+                let compressed = format!("{}.gz", file);
+                !self.index.contains(file)
+                    && !self.index.contains(&compressed)
+                    && !self.dir.join(file).exists()
+                    && !self.dir.join(&compressed).exists()
+            })
+            .unwrap()
+    }
+}
+
+/// Tells a directory apart from one that later took its place under the same path.
+#[cfg(unix)]
+type DirIdentity = (u64, u64);
+#[cfg(not(unix))]
+type DirIdentity = std::time::SystemTime;
+
+#[cfg(unix)]
+fn dir_identity(dir: &Path) -> Option<DirIdentity> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(dir).ok().map(|meta| (meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn dir_identity(dir: &Path) -> Option<DirIdentity> {
+    fs::metadata(dir).and_then(|meta| meta.created()).ok()
+}
+
+/// Opens the file an index left open for appending again, in the dialect it was started
+/// with. Only a file that lost its contents gets the header written again.
+fn reopen(dir: &Path, open: &mut CsvFileEntry) -> io::Result<Writer<File>> {
+    let path = dir.join(&open.file);
+    let empty = fs::metadata(&path).map(|meta| meta.len() == 0).unwrap_or(true);
+
+    let mut file = File::options().create(true).append(true).open(&path)?;
+    if empty {
+        open.dialect.write_preamble(&mut file)?;
+    }
+    let mut writer = open.dialect.writer_builder().from_writer(file);
+    if empty {
+        writer.write_record(&open.columns)?;
+        writer.flush()?;
+        open.rows = 0;
+    }
+    Ok(writer)
+}
+
+/// The data rows of the file `entry` lists at `path`, without its header.
+fn read_records(path: &Path, entry: &CsvFileEntry) -> io::Result<Vec<Vec<String>>> {
+    let file = File::open(path)?;
+    let reader: Box<dyn io::Read> = if entry.compressed {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    let mut reader = entry.dialect.reader_builder().from_reader(reader);
+    reader
+        .records()
+        .map(|record| Ok(record?.iter().map(str::to_string).collect()))
+        .collect()
+}
+
+/// The header of the CSV at `path` and how many data rows follow it.
+fn read_counts(path: &Path, dialect: &CsvDialect) -> io::Result<(Vec<String>, u64)> {
+    let mut reader = dialect.reader_builder().from_path(path)?;
+    let columns = reader.headers()?.iter().map(str::to_string).collect();
+    let mut rows = 0;
+    for record in reader.records() {
+        record?;
+        rows += 1;
+    }
+    Ok((columns, rows))
+}
+
+fn gzip(source: &Path, target: &Path) -> io::Result<()> {
+    let partial = target.with_extension("gz.tmp");
+    let mut encoder = GzEncoder::new(File::create(&partial)?, Compression::default());
+    io::copy(&mut File::open(source)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::rename(&partial, target)
+}
+
+/// Whether `text` looks like the `%Y%m%dT%H%M%S` stamp archived files were named with.
+fn is_archive_timestamp(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    bytes.len() == 15
+        && bytes[8] == b'T'
+        && bytes
+            .iter()
+            .enumerate()
+            .all(|(at, byte)| at == 8 || byte.is_ascii_digit())
+}
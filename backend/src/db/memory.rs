@@ -0,0 +1,610 @@
+use async_trait::async_trait;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{Mutex, MutexGuard},
+};
+use tokio::sync::mpsc;
+
+use super::repository::{PoolStats, StateRepository};
+use crate::models::{
+    live::LiveRecording,
+    log_query::{LogQuery, SortOrder},
+    recording_session::RecordingSession,
+    user::{Assignment, Role, User, UserSummary},
+    user_state::{DataLog, Sample, UserState},
+    username::Username,
+};
+
+/// A `StateRepository` that lives entirely in memory, for tests.
+///
+/// It answers the same way `SqliteRepository` does, ordering included, but nothing
+/// survives the process and foreign keys are not enforced.
+#[derive(Default)]
+pub struct InMemoryRepository {
+    store: Mutex<Store>,
+}
+
+#[derive(Default)]
+struct Store {
+    user_states: BTreeMap<Username, UserState>,
+    data_logs: Vec<DataLog>,
+    csv_outbox: BTreeSet<i64>,
+    users: BTreeMap<Username, User>,
+    assignments: BTreeSet<(Username, Username)>,
+    recording_sessions: Vec<RecordingSession>,
+    /// Recording session id -> who samples it and until when
+    session_leases: HashMap<i64, SessionLease>,
+    auth_sessions: HashMap<String, AuthSession>,
+}
+
+struct AuthSession {
+    username: Username,
+    expires_at: String,
+}
+
+struct SessionLease {
+    owner: String,
+    expires_at: String,
+}
+
+impl InMemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn store(&self) -> MutexGuard<'_, Store> {
+        self.store.lock().unwrap()
+    }
+}
+
+impl Store {
+    /// Logs matching everything in `query` except paging, oldest first.
+    fn filtered_logs(&self, query: &LogQuery) -> Vec<DataLog> {
+        let from = query.from.map(|from| from.to_rfc3339());
+        let to = query.to.map(|to| to.to_rfc3339());
+        let text = query.text.as_ref().map(|text| text.to_lowercase());
+
+        let mut logs: Vec<DataLog> = self
+            .data_logs
+            .iter()
+            .filter(|log| {
+                query
+                    .usernames
+                    .as_ref()
+                    .is_none_or(|usernames| usernames.contains(&log.username))
+            })
+            .filter(|log| from.as_ref().is_none_or(|from| log.timestamp >= *from))
+            .filter(|log| to.as_ref().is_none_or(|to| log.timestamp < *to))
+            .filter(|log| {
+                query.value.as_ref().is_none_or(|value| {
+                    log.categories.iter().any(|(key, selected)| {
+                        selected == value
+                            && query.category.as_ref().is_none_or(|category| key == category)
+                    })
+                })
+            })
+            .filter(|log| {
+                text.as_ref()
+                    .is_none_or(|text| log.text_entry.to_lowercase().contains(text))
+            })
+            .cloned()
+            .collect();
+        logs.sort_by(|a, b| (&a.timestamp, a.id).cmp(&(&b.timestamp, b.id)));
+        logs
+    }
+
+    fn open_session(&self, username: &Username) -> Option<&RecordingSession> {
+        self.recording_sessions
+            .iter()
+            .find(|session| session.username == *username && session.ended_at.is_none())
+    }
+}
+
+#[async_trait]
+impl StateRepository for InMemoryRepository {
+    async fn get_user_state(&self, username: &Username) -> Result<Option<UserState>, sqlx::Error> {
+        Ok(self.store().user_states.get(username).cloned())
+    }
+
+    async fn save_user_state(&self, state: &UserState) -> Result<(), sqlx::Error> {
+        self.store()
+            .user_states
+            .insert(state.username.clone(), state.clone());
+        Ok(())
+    }
+
+    async fn save_entry(
+        &self,
+        username: &Username,
+        text_entry: &str,
+        categories: &BTreeMap<String, String>,
+    ) -> Result<UserState, sqlx::Error> {
+        let mut store = self.store();
+        let state = store
+            .user_states
+            .entry(username.clone())
+            .or_insert_with(|| UserState::blank(username.clone()));
+        state.text_entry = text_entry.to_string();
+        state.categories = categories.clone();
+        Ok(state.clone())
+    }
+
+    async fn save_text_entry(
+        &self,
+        username: &Username,
+        text_entry: &str,
+    ) -> Result<UserState, sqlx::Error> {
+        let mut store = self.store();
+        let state = store
+            .user_states
+            .entry(username.clone())
+            .or_insert_with(|| UserState::blank(username.clone()));
+        state.text_entry = text_entry.to_string();
+        Ok(state.clone())
+    }
+
+    async fn save_category(
+        &self,
+        username: &Username,
+        key: &str,
+        value: &str,
+    ) -> Result<UserState, sqlx::Error> {
+        let mut store = self.store();
+        let state = store
+            .user_states
+            .entry(username.clone())
+            .or_insert_with(|| UserState::blank(username.clone()));
+        state.categories.insert(key.to_string(), value.to_string());
+        Ok(state.clone())
+    }
+
+    async fn log_data_entry(&self, log: &DataLog) -> Result<(), sqlx::Error> {
+        let mut store = self.store();
+        let id = store.data_logs.len() as i64 + 1;
+        store.data_logs.push(DataLog {
+            id: Some(id),
+            ..log.clone()
+        });
+        Ok(())
+    }
+
+    async fn record_samples(&self, samples: &[Sample]) -> Result<Vec<DataLog>, sqlx::Error> {
+        let mut store = self.store();
+        let mut logs = Vec::with_capacity(samples.len());
+        for sample in samples {
+            let ended = sample.log.session_id.is_some_and(|session_id| {
+                !store
+                    .recording_sessions
+                    .iter()
+                    .any(|session| session.id == session_id && session.ended_at.is_none())
+            });
+            if ended {
+                continue;
+            }
+            let id = store.data_logs.len() as i64 + 1;
+            let log = DataLog {
+                id: Some(id),
+                ..sample.log.clone()
+            };
+            store.data_logs.push(log.clone());
+            store.csv_outbox.insert(id);
+            if let Some(state) = store.user_states.get_mut(&log.username) {
+                state.last_saved = Some(log.timestamp.clone());
+                state.last_data = Some(sample.summary.clone());
+            }
+            logs.push(log);
+        }
+        Ok(logs)
+    }
+
+    async fn csv_outbox(&self, limit: u32) -> Result<Vec<DataLog>, sqlx::Error> {
+        let store = self.store();
+        Ok(store
+            .csv_outbox
+            .iter()
+            .take(limit as usize)
+            .map(|id| store.data_logs[*id as usize - 1].clone())
+            .collect())
+    }
+
+    async fn clear_csv_outbox(&self, log_ids: &[i64]) -> Result<(), sqlx::Error> {
+        let mut store = self.store();
+        for id in log_ids {
+            store.csv_outbox.remove(id);
+        }
+        Ok(())
+    }
+
+    async fn clear_user_csv_outbox(
+        &self,
+        username: &Username,
+        through_id: i64,
+    ) -> Result<(), sqlx::Error> {
+        let mut store = self.store();
+        let Store {
+            data_logs,
+            csv_outbox,
+            ..
+        } = &mut *store;
+        csv_outbox
+            .retain(|id| *id > through_id || data_logs[*id as usize - 1].username != *username);
+        Ok(())
+    }
+
+    async fn query_logs(&self, query: &LogQuery) -> Result<Vec<DataLog>, sqlx::Error> {
+        let mut logs = self.store().filtered_logs(query);
+        if query.sort == SortOrder::Desc {
+            logs.reverse();
+        }
+        if let Some(after) = &query.after {
+            let position = (&after.timestamp, Some(after.id));
+            logs.retain(|log| match query.sort {
+                SortOrder::Asc => (&log.timestamp, log.id) > position,
+                SortOrder::Desc => (&log.timestamp, log.id) < position,
+            });
+        }
+        logs.truncate(query.limit as usize);
+        Ok(logs)
+    }
+
+    async fn stream_logs(&self, query: &LogQuery, rows: mpsc::Sender<Result<DataLog, sqlx::Error>>) {
+        let mut logs = self.store().filtered_logs(query);
+        if query.sort == SortOrder::Desc {
+            logs.reverse();
+        }
+        for log in logs {
+            if rows.send(Ok(log)).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    async fn create_user(&self, user: &User) -> Result<Option<Role>, sqlx::Error> {
+        let mut store = self.store();
+        if store.users.contains_key(&user.username) {
+            return Ok(None);
+        }
+        let role = if store.users.is_empty() {
+            Role::Admin
+        } else {
+            user.role
+        };
+        let user = User {
+            role,
+            ..user.clone()
+        };
+        store.users.insert(user.username.clone(), user);
+        Ok(Some(role))
+    }
+
+    async fn get_user(&self, username: &Username) -> Result<Option<User>, sqlx::Error> {
+        Ok(self.store().users.get(username).cloned())
+    }
+
+    async fn list_users(&self) -> Result<Vec<UserSummary>, sqlx::Error> {
+        Ok(self
+            .store()
+            .users
+            .values()
+            .map(|user| UserSummary {
+                username: user.username.clone(),
+                role: user.role,
+                created_at: user.created_at.clone(),
+            })
+            .collect())
+    }
+
+    async fn set_user_role(&self, username: &Username, role: Role) -> Result<bool, sqlx::Error> {
+        match self.store().users.get_mut(username) {
+            Some(user) => {
+                user.role = role;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn assign_participant(
+        &self,
+        observer: &Username,
+        participant: &Username,
+    ) -> Result<(), sqlx::Error> {
+        self.store()
+            .assignments
+            .insert((observer.clone(), participant.clone()));
+        Ok(())
+    }
+
+    async fn unassign_participant(
+        &self,
+        observer: &Username,
+        participant: &Username,
+    ) -> Result<(), sqlx::Error> {
+        self.store()
+            .assignments
+            .remove(&(observer.clone(), participant.clone()));
+        Ok(())
+    }
+
+    async fn is_assigned(
+        &self,
+        observer: &Username,
+        participant: &Username,
+    ) -> Result<bool, sqlx::Error> {
+        Ok(self
+            .store()
+            .assignments
+            .contains(&(observer.clone(), participant.clone())))
+    }
+
+    async fn list_assigned_participants(
+        &self,
+        observer: &Username,
+    ) -> Result<Vec<Username>, sqlx::Error> {
+        Ok(self
+            .store()
+            .assignments
+            .iter()
+            .filter(|(assigned_to, _)| assigned_to == observer)
+            .map(|(_, participant)| participant.clone())
+            .collect())
+    }
+
+    async fn list_participants(&self) -> Result<Vec<Username>, sqlx::Error> {
+        Ok(self
+            .store()
+            .users
+            .values()
+            .filter(|user| user.role == Role::Participant)
+            .map(|user| user.username.clone())
+            .collect())
+    }
+
+    async fn list_assignments(&self) -> Result<Vec<Assignment>, sqlx::Error> {
+        Ok(self
+            .store()
+            .assignments
+            .iter()
+            .map(|(observer, participant)| Assignment {
+                observer: observer.clone(),
+                participant: participant.clone(),
+            })
+            .collect())
+    }
+
+    async fn start_recording_session(
+        &self,
+        username: &Username,
+        label: Option<&str>,
+        started_at: &str,
+    ) -> Result<Option<RecordingSession>, sqlx::Error> {
+        let mut store = self.store();
+        if store.open_session(username).is_some() {
+            return Ok(None);
+        }
+
+        let session = RecordingSession {
+            id: store.recording_sessions.len() as i64 + 1,
+            username: username.clone(),
+            label: label.map(str::to_string),
+            started_at: started_at.to_string(),
+            ended_at: None,
+            interrupted: false,
+        };
+        store.recording_sessions.push(session.clone());
+
+        store
+            .user_states
+            .entry(username.clone())
+            .or_insert_with(|| UserState::blank(username.clone()))
+            .is_recording = true;
+
+        Ok(Some(session))
+    }
+
+    async fn stop_recording_session(
+        &self,
+        id: i64,
+        ended_at: &str,
+    ) -> Result<Option<RecordingSession>, sqlx::Error> {
+        let mut store = self.store();
+        let latest = store
+            .data_logs
+            .iter()
+            .filter(|log| log.session_id == Some(id))
+            .map(|log| log.timestamp.as_str())
+            .chain([ended_at])
+            .max()
+            .map(str::to_string);
+        let Some(session) = store
+            .recording_sessions
+            .iter_mut()
+            .find(|session| session.id == id && session.ended_at.is_none())
+        else {
+            return Ok(None);
+        };
+        session.ended_at = latest;
+        let session = session.clone();
+
+        if let Some(state) = store.user_states.get_mut(&session.username) {
+            state.is_recording = false;
+        }
+        Ok(Some(session))
+    }
+
+    async fn interrupt_recording_sessions(
+        &self,
+        ids: &[i64],
+        ended_at: &str,
+    ) -> Result<Vec<RecordingSession>, sqlx::Error> {
+        let mut store = self.store();
+        let mut interrupted = Vec::new();
+        for session in &mut store.recording_sessions {
+            if ids.contains(&session.id) && session.ended_at.is_none() {
+                session.ended_at = Some(ended_at.to_string());
+                session.interrupted = true;
+                interrupted.push(session.clone());
+            }
+        }
+        for session in &interrupted {
+            if let Some(state) = store.user_states.get_mut(&session.username) {
+                state.is_recording = false;
+            }
+        }
+        Ok(interrupted)
+    }
+
+    async fn get_recording_session(
+        &self,
+        id: i64,
+    ) -> Result<Option<RecordingSession>, sqlx::Error> {
+        Ok(self
+            .store()
+            .recording_sessions
+            .iter()
+            .find(|session| session.id == id)
+            .cloned())
+    }
+
+    async fn open_recording_session(
+        &self,
+        username: &Username,
+    ) -> Result<Option<RecordingSession>, sqlx::Error> {
+        Ok(self.store().open_session(username).cloned())
+    }
+
+    async fn open_recording_sessions(&self) -> Result<Vec<RecordingSession>, sqlx::Error> {
+        let mut sessions: Vec<RecordingSession> = self
+            .store()
+            .recording_sessions
+            .iter()
+            .filter(|session| session.ended_at.is_none())
+            .cloned()
+            .collect();
+        sessions.sort_by(|a, b| a.started_at.cmp(&b.started_at));
+        Ok(sessions)
+    }
+
+    async fn claim_recording_session(
+        &self,
+        id: i64,
+        owner: &str,
+        now: &str,
+        lease_expires_at: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let mut store = self.store();
+        let open = store
+            .recording_sessions
+            .iter()
+            .any(|session| session.id == id && session.ended_at.is_none());
+        let taken = store
+            .session_leases
+            .get(&id)
+            .is_some_and(|lease| lease.owner != owner && lease.expires_at.as_str() >= now);
+        if !open || taken {
+            return Ok(false);
+        }
+        store.session_leases.insert(
+            id,
+            SessionLease {
+                owner: owner.to_string(),
+                expires_at: lease_expires_at.to_string(),
+            },
+        );
+        Ok(true)
+    }
+
+    async fn live_recordings(&self) -> Result<Vec<LiveRecording>, sqlx::Error> {
+        let store = self.store();
+        Ok(store
+            .user_states
+            .values()
+            .filter(|state| state.is_recording)
+            .filter_map(|state| {
+                let session = store.open_session(&state.username)?;
+                Some(LiveRecording {
+                    username: state.username.clone(),
+                    text_entry: state.text_entry.clone(),
+                    categories: state.categories.clone(),
+                    last_saved: state.last_saved.clone(),
+                    session_id: session.id,
+                    label: session.label.clone(),
+                    started_at: session.started_at.clone(),
+                })
+            })
+            .collect())
+    }
+
+    async fn list_recording_sessions(
+        &self,
+        usernames: Option<&[Username]>,
+    ) -> Result<Vec<RecordingSession>, sqlx::Error> {
+        let mut sessions: Vec<RecordingSession> = self
+            .store()
+            .recording_sessions
+            .iter()
+            .filter(|session| usernames.is_none_or(|usernames| usernames.contains(&session.username)))
+            .cloned()
+            .collect();
+        sessions.sort_by(|a, b| (&b.started_at, b.id).cmp(&(&a.started_at, a.id)));
+        Ok(sessions)
+    }
+
+    async fn session_logs(&self, session_id: i64) -> Result<Vec<DataLog>, sqlx::Error> {
+        let mut logs: Vec<DataLog> = self
+            .store()
+            .data_logs
+            .iter()
+            .filter(|log| log.session_id == Some(session_id))
+            .cloned()
+            .collect();
+        logs.sort_by(|a, b| (&a.timestamp, a.id).cmp(&(&b.timestamp, b.id)));
+        Ok(logs)
+    }
+
+    async fn create_session(
+        &self,
+        token_hash: &str,
+        username: &Username,
+        _created_at: &str,
+        expires_at: &str,
+    ) -> Result<(), sqlx::Error> {
+        self.store().auth_sessions.insert(
+            token_hash.to_string(),
+            AuthSession {
+                username: username.clone(),
+                expires_at: expires_at.to_string(),
+            },
+        );
+        Ok(())
+    }
+
+    async fn find_session_user(
+        &self,
+        token_hash: &str,
+        now: &str,
+    ) -> Result<Option<(Username, Role)>, sqlx::Error> {
+        let store = self.store();
+        Ok(store
+            .auth_sessions
+            .get(token_hash)
+            .filter(|session| session.expires_at.as_str() > now)
+            .and_then(|session| store.users.get(&session.username))
+            .map(|user| (user.username.clone(), user.role)))
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> Result<(), sqlx::Error> {
+        self.store().auth_sessions.remove(token_hash);
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        None
+    }
+
+    async fn close(&self) {}
+}
@@ -0,0 +1,143 @@
+use sqlx::{
+    migrate::{MigrateError, Migrator},
+    Pool, Sqlite,
+};
+use thiserror::Error;
+
+/// The SQLite migrations in `backend/migrations/sqlite`, embedded at compile time.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+/// The PostgreSQL migrations in `backend/migrations/postgres`. Versions match the
+/// SQLite ones, so a schema version means the same thing on either backend.
+#[cfg(feature = "postgres")]
+pub static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("migration failed: {0}")]
+    Migrate(#[from] MigrateError),
+    #[error(
+        "database schema is at version {database}, but this binary only knows up to \
+         version {binary}; refusing to start (upgrade the binary instead)"
+    )]
+    DatabaseTooNew { database: i64, binary: i64 },
+}
+
+/// The newest migration compiled into this binary.
+pub fn latest_version() -> i64 {
+    newest(&MIGRATOR)
+}
+
+fn newest(migrator: &Migrator) -> i64 {
+    migrator.iter().map(|migration| migration.version).max().unwrap_or(0)
+}
+
+/// The newest migration recorded in the database, or 0 for a fresh one.
+pub async fn applied_version(pool: &Pool<Sqlite>) -> Result<i64, sqlx::Error> {
+    let has_table = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations')",
+    )
+    .fetch_one(pool)
+    .await?;
+    if !has_table {
+        return Ok(0);
+    }
+
+    sqlx::query_scalar::<_, Option<i64>>(
+        "SELECT MAX(version) FROM _sqlx_migrations WHERE success = 1",
+    )
+    .fetch_one(pool)
+    .await
+    .map(|version| version.unwrap_or(0))
+}
+
+/// Brings the schema up to date, refusing databases written by a newer binary.
+///
+/// Databases the server created before it had migrations are adopted: the baseline
+/// migration leaves their tables in place, and their pre-codebook category columns are
+/// converted first, so every later migration and query sees the current shape.
+///
+/// Returns the schema version the database is at afterwards.
+pub async fn run_migrations(pool: &Pool<Sqlite>) -> Result<i64, MigrationError> {
+    let binary = latest_version();
+    let database = applied_version(pool).await?;
+    if database > binary {
+        return Err(MigrationError::DatabaseTooNew { database, binary });
+    }
+
+    convert_legacy_categories(pool).await?;
+    MIGRATOR.run(pool).await?;
+    Ok(applied_version(pool).await?)
+}
+
+/// The tables that stored a sample's categories as four fixed columns before the codebook
+/// made them a JSON object.
+const LEGACY_CATEGORY_TABLES: [&str; 2] = ["user_states", "data_logs"];
+
+/// Moves `category1` .. `category4` of databases written before the codebook into the
+/// `categories` object, keyed like the default codebook's fields, and drops the old
+/// columns. Tables already in the new shape, or not there yet, are left alone.
+async fn convert_legacy_categories(pool: &Pool<Sqlite>) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    for table in LEGACY_CATEGORY_TABLES {
+        let legacy = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?) WHERE name = 'category1')",
+        )
+        .bind(table)
+        .fetch_one(&mut *tx)
+        .await?;
+        if !legacy {
+            continue;
+        }
+
+        sqlx::query(&format!(
+            r#"
+            ALTER TABLE {table} ADD COLUMN categories TEXT NOT NULL DEFAULT '{{}}';
+            UPDATE {table} SET categories = json_object(
+                'category1', category1,
+                'category2', category2,
+                'category3', category3,
+                'category4', category4
+            );
+            ALTER TABLE {table} DROP COLUMN category1;
+            ALTER TABLE {table} DROP COLUMN category2;
+            ALTER TABLE {table} DROP COLUMN category3;
+            ALTER TABLE {table} DROP COLUMN category4;
+            "#
+        ))
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await
+}
+
+/// Like [`run_migrations`], for a PostgreSQL database.
+#[cfg(feature = "postgres")]
+pub async fn run_postgres_migrations(pool: &sqlx::PgPool) -> Result<i64, MigrationError> {
+    let binary = newest(&POSTGRES_MIGRATOR);
+    let database = postgres_applied_version(pool).await?;
+    if database > binary {
+        return Err(MigrationError::DatabaseTooNew { database, binary });
+    }
+
+    POSTGRES_MIGRATOR.run(pool).await?;
+    Ok(postgres_applied_version(pool).await?)
+}
+
+#[cfg(feature = "postgres")]
+async fn postgres_applied_version(pool: &sqlx::PgPool) -> Result<i64, sqlx::Error> {
+    let has_table =
+        sqlx::query_scalar::<_, bool>("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+            .fetch_one(pool)
+            .await?;
+    if !has_table {
+        return Ok(0);
+    }
+
+    sqlx::query_scalar::<_, Option<i64>>("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+        .fetch_one(pool)
+        .await
+        .map(|version| version.unwrap_or(0))
+}
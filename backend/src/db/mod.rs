@@ -1,2 +1,8 @@
 // This module contains database-related functionality
-// It is a placeholder for potential future database abstractions and code organization
\ No newline at end of file
+
+pub mod memory;
+pub mod migrations;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod repository;
+pub mod sqlite;
@@ -0,0 +1,752 @@
+//! PostgreSQL storage, for deployments where several backend instances share one
+//! database. Only built with the `postgres` feature.
+
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use std::collections::BTreeMap;
+use sqlx::{types::Json, Pool, Postgres, QueryBuilder};
+use tokio::sync::mpsc;
+
+use super::{
+    repository::{PoolStats, StateRepository},
+    sqlite::escape_like,
+};
+use crate::models::{
+    live::LiveRecording,
+    log_query::{LogQuery, SortOrder},
+    recording_session::RecordingSession,
+    user::{Assignment, Role, User, UserSummary},
+    user_state::{DataLog, Sample, UserState},
+    username::Username,
+};
+
+/// The same operations as `SqliteRepository`, in PostgreSQL's dialect.
+#[derive(Clone)]
+pub struct PostgresRepository {
+    pool: Pool<Postgres>,
+}
+
+impl PostgresRepository {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl StateRepository for PostgresRepository {
+    async fn get_user_state(&self, username: &Username) -> Result<Option<UserState>, sqlx::Error> {
+        sqlx::query_as::<_, UserState>(
+            "SELECT * FROM user_states WHERE username = $1"
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn save_user_state(&self, state: &UserState) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO user_states (
+                username, text_entry, categories, is_recording, last_saved, last_data
+            ) VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT(username) DO UPDATE SET
+                text_entry = excluded.text_entry,
+                categories = excluded.categories,
+                is_recording = excluded.is_recording,
+                last_saved = excluded.last_saved,
+                last_data = excluded.last_data
+            "#,
+        )
+        .bind(&state.username)
+        .bind(&state.text_entry)
+        .bind(Json(&state.categories))
+        .bind(state.is_recording)
+        .bind(&state.last_saved)
+        .bind(&state.last_data)
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(())
+    }
+
+    async fn save_entry(
+        &self,
+        username: &Username,
+        text_entry: &str,
+        categories: &BTreeMap<String, String>,
+    ) -> Result<UserState, sqlx::Error> {
+        sqlx::query_as::<_, UserState>(
+            r#"
+            INSERT INTO user_states (username, text_entry, categories, is_recording)
+            VALUES ($1, $2, $3, FALSE)
+            ON CONFLICT(username) DO UPDATE SET
+                text_entry = excluded.text_entry,
+                categories = excluded.categories
+            RETURNING *
+            "#,
+        )
+        .bind(username)
+        .bind(text_entry)
+        .bind(Json(categories))
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn save_text_entry(
+        &self,
+        username: &Username,
+        text_entry: &str,
+    ) -> Result<UserState, sqlx::Error> {
+        sqlx::query_as::<_, UserState>(
+            r#"
+            INSERT INTO user_states (username, text_entry, is_recording)
+            VALUES ($1, $2, FALSE)
+            ON CONFLICT(username) DO UPDATE SET text_entry = excluded.text_entry
+            RETURNING *
+            "#,
+        )
+        .bind(username)
+        .bind(text_entry)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn save_category(
+        &self,
+        username: &Username,
+        key: &str,
+        value: &str,
+    ) -> Result<UserState, sqlx::Error> {
+        sqlx::query_as::<_, UserState>(
+            r#"
+            INSERT INTO user_states (username, text_entry, categories, is_recording)
+            VALUES ($1, '', jsonb_build_object($2::text, $3::text), FALSE)
+            ON CONFLICT(username) DO UPDATE SET
+                categories = user_states.categories || excluded.categories
+            RETURNING *
+            "#,
+        )
+        .bind(username)
+        .bind(key)
+        .bind(value)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn log_data_entry(&self, log: &DataLog) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO data_logs (username, text_entry, categories, timestamp, session_id)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+        )
+        .bind(&log.username)
+        .bind(&log.text_entry)
+        .bind(Json(&log.categories))
+        .bind(&log.timestamp)
+        .bind(log.session_id)
+        .execute(&self.pool)
+        .await?;
+        
+        Ok(())
+    }
+
+    async fn record_samples(&self, samples: &[Sample]) -> Result<Vec<DataLog>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let mut logs = Vec::with_capacity(samples.len());
+        for sample in samples {
+            let log = &sample.log;
+            // Another server may have stopped the session since this was sampled; the
+            // session's row stays locked until this commits, so stopping waits for it
+            let id: Option<i64> = sqlx::query_scalar(
+                r#"
+                INSERT INTO data_logs (username, text_entry, categories, timestamp, session_id)
+                SELECT $1, $2, $3, $4, $5
+                WHERE $5::BIGINT IS NULL OR EXISTS (
+                    SELECT 1 FROM recording_sessions
+                    WHERE id = $5 AND ended_at IS NULL
+                    FOR SHARE
+                )
+                RETURNING id
+                "#,
+            )
+            .bind(&log.username)
+            .bind(&log.text_entry)
+            .bind(Json(&log.categories))
+            .bind(&log.timestamp)
+            .bind(log.session_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+            let Some(id) = id else {
+                continue;
+            };
+
+            sqlx::query("INSERT INTO csv_outbox (log_id) VALUES ($1)")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(
+                "UPDATE user_states SET last_saved = $1, last_data = $2 WHERE username = $3",
+            )
+            .bind(&log.timestamp)
+            .bind(&sample.summary)
+            .bind(&log.username)
+            .execute(&mut *tx)
+            .await?;
+
+            logs.push(DataLog {
+                id: Some(id),
+                ..log.clone()
+            });
+        }
+        tx.commit().await?;
+        Ok(logs)
+    }
+
+    async fn csv_outbox(&self, limit: u32) -> Result<Vec<DataLog>, sqlx::Error> {
+        sqlx::query_as::<_, DataLog>(
+            r#"
+            SELECT data_logs.* FROM csv_outbox
+            JOIN data_logs ON data_logs.id = csv_outbox.log_id
+            ORDER BY data_logs.id
+            LIMIT $1
+            "#,
+        )
+        .bind(i64::from(limit))
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn clear_csv_outbox(&self, log_ids: &[i64]) -> Result<(), sqlx::Error> {
+        if log_ids.is_empty() {
+            return Ok(());
+        }
+        let mut builder = QueryBuilder::<Postgres>::new("DELETE FROM csv_outbox WHERE log_id IN (");
+        let mut ids = builder.separated(", ");
+        for id in log_ids {
+            ids.push_bind(*id);
+        }
+        builder.push(")");
+        builder.build().execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn clear_user_csv_outbox(
+        &self,
+        username: &Username,
+        through_id: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "DELETE FROM csv_outbox WHERE log_id <= $1 \
+             AND log_id IN (SELECT id FROM data_logs WHERE username = $2)",
+        )
+        .bind(through_id)
+        .bind(username)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn query_logs(&self, query: &LogQuery) -> Result<Vec<DataLog>, sqlx::Error> {
+        if query.usernames.as_ref().is_some_and(Vec::is_empty) {
+            return Ok(Vec::new());
+        }
+        let mut builder = log_filter_query(query);
+
+        let (comparison, direction) = match query.sort {
+            SortOrder::Asc => (">", "ASC"),
+            SortOrder::Desc => ("<", "DESC"),
+        };
+        if let Some(after) = &query.after {
+            builder
+                .push(format!(" AND (timestamp {} ", comparison))
+                .push_bind(&after.timestamp)
+                .push(" OR (timestamp = ")
+                .push_bind(&after.timestamp)
+                .push(format!(" AND id {} ", comparison))
+                .push_bind(after.id)
+                .push("))");
+        }
+
+        builder
+            .push(format!(" ORDER BY timestamp {0}, id {0} LIMIT ", direction))
+            .push_bind(i64::from(query.limit));
+
+        builder.build_query_as::<DataLog>().fetch_all(&self.pool).await
+    }
+
+    async fn stream_logs(
+        &self,
+        query: &LogQuery,
+        rows: mpsc::Sender<Result<DataLog, sqlx::Error>>,
+    ) {
+        if query.usernames.as_ref().is_some_and(Vec::is_empty) {
+            return;
+        }
+        let direction = match query.sort {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        };
+        let mut builder = log_filter_query(query);
+        builder.push(format!(" ORDER BY timestamp {0}, id {0}", direction));
+
+        let mut logs = builder.build_query_as::<DataLog>().fetch(&self.pool);
+        while let Some(row) = logs.next().await {
+            let failed = row.is_err();
+            if rows.send(row).await.is_err() || failed {
+                break;
+            }
+        }
+    }
+
+    async fn create_user(&self, user: &User) -> Result<Option<Role>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        // Under READ COMMITTED two registrations could both see an empty table; this lock
+        // mode conflicts with itself but not with readers, so they take turns
+        sqlx::query("LOCK TABLE users IN SHARE ROW EXCLUSIVE MODE")
+            .execute(&mut *tx)
+            .await?;
+        let role = sqlx::query_scalar::<_, Role>(
+            r#"
+            INSERT INTO users (username, password_hash, role, created_at)
+            SELECT $1, $2, CASE WHEN EXISTS (SELECT 1 FROM users) THEN $3 ELSE $4 END, $5
+            ON CONFLICT(username) DO NOTHING
+            RETURNING role
+            "#,
+        )
+        .bind(&user.username)
+        .bind(&user.password_hash)
+        .bind(user.role)
+        .bind(Role::Admin)
+        .bind(&user.created_at)
+        .fetch_optional(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(role)
+    }
+
+    async fn get_user(&self, username: &Username) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = $1")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn list_users(&self) -> Result<Vec<UserSummary>, sqlx::Error> {
+        sqlx::query_as::<_, UserSummary>(
+            "SELECT username, role, created_at FROM users ORDER BY username",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn set_user_role(&self, username: &Username, role: Role) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("UPDATE users SET role = $1 WHERE username = $2")
+            .bind(role)
+            .bind(username)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn assign_participant(
+        &self,
+        observer: &Username,
+        participant: &Username,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO observer_assignments (observer, participant)
+            VALUES ($1, $2)
+            ON CONFLICT(observer, participant) DO NOTHING
+            "#,
+        )
+        .bind(observer)
+        .bind(participant)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn unassign_participant(
+        &self,
+        observer: &Username,
+        participant: &Username,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM observer_assignments WHERE observer = $1 AND participant = $2")
+            .bind(observer)
+            .bind(participant)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn is_assigned(&self, observer: &Username, participant: &Username) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM observer_assignments WHERE observer = $1 AND participant = $2
+            )
+            "#,
+        )
+        .bind(observer)
+        .bind(participant)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn list_assigned_participants(
+        &self,
+        observer: &Username,
+    ) -> Result<Vec<Username>, sqlx::Error> {
+        sqlx::query_scalar::<_, Username>(
+            "SELECT participant FROM observer_assignments WHERE observer = $1 ORDER BY participant",
+        )
+        .bind(observer)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn list_participants(&self) -> Result<Vec<Username>, sqlx::Error> {
+        sqlx::query_scalar::<_, Username>(
+            "SELECT username FROM users WHERE role = 'participant' ORDER BY username",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn list_assignments(&self) -> Result<Vec<Assignment>, sqlx::Error> {
+        sqlx::query_as::<_, Assignment>(
+            "SELECT observer, participant FROM observer_assignments ORDER BY observer, participant",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn start_recording_session(
+        &self,
+        username: &Username,
+        label: Option<&str>,
+        started_at: &str,
+    ) -> Result<Option<RecordingSession>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Another instance may be starting the same user's session; the open-session
+        // index lets exactly one insert through
+        let Some(session) = sqlx::query_as::<_, RecordingSession>(
+            r#"
+            INSERT INTO recording_sessions (username, label, started_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (username) WHERE ended_at IS NULL DO NOTHING
+            RETURNING *
+            "#,
+        )
+        .bind(username)
+        .bind(label)
+        .bind(started_at)
+        .fetch_optional(&mut *tx)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO user_states (username, text_entry, is_recording)
+            VALUES ($1, '', TRUE)
+            ON CONFLICT (username) DO UPDATE SET is_recording = TRUE
+            "#,
+        )
+        .bind(username)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Some(session))
+    }
+
+    async fn stop_recording_session(
+        &self,
+        id: i64,
+        ended_at: &str,
+    ) -> Result<Option<RecordingSession>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        // Waits for samples being saved into the session, so the next statement sees them
+        sqlx::query("SELECT id FROM recording_sessions WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        let session = sqlx::query_as::<_, RecordingSession>(
+            r#"
+            UPDATE recording_sessions
+            SET ended_at = GREATEST(
+                $1::TEXT COLLATE "C",
+                (SELECT MAX(timestamp COLLATE "C") FROM data_logs WHERE session_id = $2)
+            )
+            WHERE id = $2 AND ended_at IS NULL
+            RETURNING *
+            "#,
+        )
+        .bind(ended_at)
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(session) = &session {
+            sqlx::query("UPDATE user_states SET is_recording = FALSE WHERE username = $1")
+                .bind(&session.username)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(session)
+    }
+
+    async fn interrupt_recording_sessions(
+        &self,
+        ids: &[i64],
+        ended_at: &str,
+    ) -> Result<Vec<RecordingSession>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let mut sessions = Vec::new();
+        for id in ids {
+            let session = sqlx::query_as::<_, RecordingSession>(
+                "UPDATE recording_sessions SET ended_at = $1, interrupted = TRUE
+                 WHERE id = $2 AND ended_at IS NULL RETURNING *",
+            )
+            .bind(ended_at)
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?;
+            let Some(session) = session else {
+                continue;
+            };
+            sqlx::query("UPDATE user_states SET is_recording = FALSE WHERE username = $1")
+                .bind(&session.username)
+                .execute(&mut *tx)
+                .await?;
+            sessions.push(session);
+        }
+
+        tx.commit().await?;
+        Ok(sessions)
+    }
+
+    async fn get_recording_session(
+        &self,
+        id: i64,
+    ) -> Result<Option<RecordingSession>, sqlx::Error> {
+        sqlx::query_as::<_, RecordingSession>("SELECT * FROM recording_sessions WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn open_recording_session(
+        &self,
+        username: &Username,
+    ) -> Result<Option<RecordingSession>, sqlx::Error> {
+        sqlx::query_as::<_, RecordingSession>(
+            "SELECT * FROM recording_sessions WHERE username = $1 AND ended_at IS NULL",
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn open_recording_sessions(&self) -> Result<Vec<RecordingSession>, sqlx::Error> {
+        sqlx::query_as::<_, RecordingSession>(
+            "SELECT * FROM recording_sessions WHERE ended_at IS NULL ORDER BY started_at",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn claim_recording_session(
+        &self,
+        id: i64,
+        owner: &str,
+        now: &str,
+        lease_expires_at: &str,
+    ) -> Result<bool, sqlx::Error> {
+        // A claim racing another one waits for its row lock, then sees the winner's lease
+        let result = sqlx::query(
+            r#"
+            UPDATE recording_sessions SET owner = $1, lease_expires_at = $2
+            WHERE id = $3 AND ended_at IS NULL
+              AND (owner IS NULL OR owner = $1 OR lease_expires_at IS NULL OR lease_expires_at < $4)
+            "#,
+        )
+        .bind(owner)
+        .bind(lease_expires_at)
+        .bind(id)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn live_recordings(&self) -> Result<Vec<LiveRecording>, sqlx::Error> {
+        sqlx::query_as::<_, LiveRecording>(
+            r#"
+            SELECT us.username, us.text_entry, us.categories, us.last_saved,
+                   rs.id AS session_id, rs.label, rs.started_at
+            FROM user_states us
+            JOIN recording_sessions rs ON rs.username = us.username AND rs.ended_at IS NULL
+            WHERE us.is_recording
+            ORDER BY us.username
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn list_recording_sessions(
+        &self,
+        usernames: Option<&[Username]>,
+    ) -> Result<Vec<RecordingSession>, sqlx::Error> {
+        let mut builder = QueryBuilder::<Postgres>::new("SELECT * FROM recording_sessions");
+        if let Some(usernames) = usernames {
+            if usernames.is_empty() {
+                return Ok(Vec::new());
+            }
+            builder.push(" WHERE username IN (");
+            let mut separated = builder.separated(", ");
+            for username in usernames {
+                separated.push_bind(username);
+            }
+            separated.push_unseparated(")");
+        }
+        builder.push(" ORDER BY started_at DESC, id DESC");
+
+        builder
+            .build_query_as::<RecordingSession>()
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn session_logs(&self, session_id: i64) -> Result<Vec<DataLog>, sqlx::Error> {
+        sqlx::query_as::<_, DataLog>(
+            "SELECT * FROM data_logs WHERE session_id = $1 ORDER BY timestamp, id",
+        )
+        .bind(session_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn create_session(
+        &self,
+        token_hash: &str,
+        username: &Username,
+        created_at: &str,
+        expires_at: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO auth_sessions (token_hash, username, created_at, expires_at)
+            VALUES ($1, $2, $3, $4)
+            "#,
+        )
+        .bind(token_hash)
+        .bind(username)
+        .bind(created_at)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn find_session_user(
+        &self,
+        token_hash: &str,
+        now: &str,
+    ) -> Result<Option<(Username, Role)>, sqlx::Error> {
+        sqlx::query_as::<_, (Username, Role)>(
+            r#"
+            SELECT users.username, users.role
+            FROM auth_sessions
+            JOIN users ON users.username = auth_sessions.username
+            WHERE auth_sessions.token_hash = $1 AND auth_sessions.expires_at > $2
+            "#,
+        )
+        .bind(token_hash)
+        .bind(now)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM auth_sessions WHERE token_hash = $1")
+            .bind(token_hash)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), sqlx::Error> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        Some(PoolStats {
+            size: self.pool.size(),
+            idle: self.pool.num_idle(),
+            max: self.pool.options().get_max_connections(),
+        })
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+/// `SELECT * FROM data_logs` narrowed by everything in `query` except paging.
+fn log_filter_query(query: &LogQuery) -> QueryBuilder<'_, Postgres> {
+    let mut builder = QueryBuilder::<Postgres>::new("SELECT * FROM data_logs WHERE 1 = 1");
+
+    if let Some(usernames) = &query.usernames {
+        builder.push(" AND username IN (");
+        let mut separated = builder.separated(", ");
+        for username in usernames {
+            separated.push_bind(username);
+        }
+        separated.push_unseparated(")");
+    }
+    if let Some(from) = &query.from {
+        builder.push(" AND timestamp >= ").push_bind(from.to_rfc3339());
+    }
+    if let Some(to) = &query.to {
+        builder.push(" AND timestamp < ").push_bind(to.to_rfc3339());
+    }
+    if let Some(value) = &query.value {
+        builder.push(
+            " AND EXISTS (SELECT 1 FROM jsonb_each_text(data_logs.categories) AS category \
+             WHERE category.value = ",
+        );
+        builder.push_bind(value);
+        if let Some(category) = &query.category {
+            builder.push(" AND category.key = ").push_bind(category);
+        }
+        builder.push(")");
+    }
+    if let Some(text) = &query.text {
+        // ILIKE, as SQLite's LIKE is case-insensitive too
+        builder
+            .push(" AND text_entry ILIKE ")
+            .push_bind(format!("%{}%", escape_like(text)))
+            .push(" ESCAPE '\\'");
+    }
+
+    builder
+}
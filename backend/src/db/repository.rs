@@ -0,0 +1,223 @@
+//! The storage interface the handlers, recorder and live feed go through.
+//!
+//! `SqliteRepository` is what the server runs on by default, `PostgresRepository` (behind
+//! the `postgres` feature) lets several servers share a database, and `InMemoryRepository`
+//! keeps everything in process memory so tests can run without a database.
+
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use tokio::sync::mpsc;
+
+use crate::models::{
+    live::LiveRecording,
+    log_query::LogQuery,
+    recording_session::RecordingSession,
+    user::{Assignment, Role, User, UserSummary},
+    user_state::{DataLog, Sample, UserState},
+    username::Username,
+};
+
+/// How busy the connection pool is, for `/metrics`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Connections open right now, idle or not
+    pub size: u32,
+    pub idle: usize,
+    pub max: u32,
+}
+
+#[async_trait]
+pub trait StateRepository: Send + Sync {
+    // User state and samples
+
+    async fn get_user_state(&self, username: &Username) -> Result<Option<UserState>, sqlx::Error>;
+
+    /// Writes the whole row, server-owned columns included.
+    async fn save_user_state(&self, state: &UserState) -> Result<(), sqlx::Error>;
+
+    /// Stores what a client entered and selected, leaving `is_recording`, `last_saved` and
+    /// `last_data` to the session endpoints and the recorder. Returns the stored state.
+    async fn save_entry(
+        &self,
+        username: &Username,
+        text_entry: &str,
+        categories: &BTreeMap<String, String>,
+    ) -> Result<UserState, sqlx::Error>;
+
+    /// Like `save_entry`, for the text entry alone; the categories are left as stored.
+    async fn save_text_entry(
+        &self,
+        username: &Username,
+        text_entry: &str,
+    ) -> Result<UserState, sqlx::Error>;
+
+    /// Like `save_entry`, for one category alone; the other categories are left as stored.
+    async fn save_category(
+        &self,
+        username: &Username,
+        key: &str,
+        value: &str,
+    ) -> Result<UserState, sqlx::Error>;
+
+    async fn log_data_entry(&self, log: &DataLog) -> Result<(), sqlx::Error>;
+
+    /// Saves samples in one transaction: each one's `data_logs` row, an outbox entry for
+    /// its CSV row and the user's latest sample. Samples of a session that has ended are
+    /// dropped. Returns the logs it saved, with their ids.
+    async fn record_samples(&self, samples: &[Sample]) -> Result<Vec<DataLog>, sqlx::Error>;
+
+    /// Logs whose CSV row is still owed, oldest first.
+    async fn csv_outbox(&self, limit: u32) -> Result<Vec<DataLog>, sqlx::Error>;
+
+    /// Marks the CSV rows of `log_ids` as written.
+    async fn clear_csv_outbox(&self, log_ids: &[i64]) -> Result<(), sqlx::Error>;
+
+    /// Marks the CSV rows of `username`'s logs up to `through_id` as written, e.g. once
+    /// their files were rebuilt from those logs.
+    async fn clear_user_csv_outbox(
+        &self,
+        username: &Username,
+        through_id: i64,
+    ) -> Result<(), sqlx::Error>;
+
+    /// Runs a filtered, keyset-paginated query over `data_logs`.
+    async fn query_logs(&self, query: &LogQuery) -> Result<Vec<DataLog>, sqlx::Error>;
+
+    /// Sends every log matching `query` down `rows` in `query.sort` order, ignoring
+    /// `after` and `limit`. A database error is forwarded too, so the receiver can tell
+    /// a failed export from a finished one. Stops early once the receiver is dropped.
+    async fn stream_logs(&self, query: &LogQuery, rows: mpsc::Sender<Result<DataLog, sqlx::Error>>);
+
+    // Accounts and observer assignments
+
+    /// Inserts a new account with `user.role`, or as admin if it is the very first one;
+    /// the check and the insert are atomic, so two first registrations can't both become
+    /// admin. Returns the role stored, or `None` if the username is already taken.
+    async fn create_user(&self, user: &User) -> Result<Option<Role>, sqlx::Error>;
+
+    async fn get_user(&self, username: &Username) -> Result<Option<User>, sqlx::Error>;
+
+    async fn list_users(&self) -> Result<Vec<UserSummary>, sqlx::Error>;
+
+    /// Returns `false` if no such user exists.
+    async fn set_user_role(&self, username: &Username, role: Role) -> Result<bool, sqlx::Error>;
+
+    async fn assign_participant(
+        &self,
+        observer: &Username,
+        participant: &Username,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn unassign_participant(
+        &self,
+        observer: &Username,
+        participant: &Username,
+    ) -> Result<(), sqlx::Error>;
+
+    async fn is_assigned(
+        &self,
+        observer: &Username,
+        participant: &Username,
+    ) -> Result<bool, sqlx::Error>;
+
+    async fn list_assigned_participants(
+        &self,
+        observer: &Username,
+    ) -> Result<Vec<Username>, sqlx::Error>;
+
+    async fn list_participants(&self) -> Result<Vec<Username>, sqlx::Error>;
+
+    async fn list_assignments(&self) -> Result<Vec<Assignment>, sqlx::Error>;
+
+    // Recording sessions
+
+    /// Opens a recording session and marks the user as recording.
+    /// Returns `None` if the user already has an open session.
+    async fn start_recording_session(
+        &self,
+        username: &Username,
+        label: Option<&str>,
+        started_at: &str,
+    ) -> Result<Option<RecordingSession>, sqlx::Error>;
+
+    /// Closes an open recording session and marks its user as no longer recording. It
+    /// ends at `ended_at` or its latest sample, whichever is later, as another server
+    /// may have sampled it in the meantime. Returns `None` if there is no such session
+    /// or it was already closed.
+    async fn stop_recording_session(
+        &self,
+        id: i64,
+        ended_at: &str,
+    ) -> Result<Option<RecordingSession>, sqlx::Error>;
+
+    /// Closes those of `ids` still open as interrupted and marks their users as no longer
+    /// recording; for shutting down. Returns the sessions it closed.
+    async fn interrupt_recording_sessions(
+        &self,
+        ids: &[i64],
+        ended_at: &str,
+    ) -> Result<Vec<RecordingSession>, sqlx::Error>;
+
+    async fn get_recording_session(&self, id: i64)
+    -> Result<Option<RecordingSession>, sqlx::Error>;
+
+    async fn open_recording_session(
+        &self,
+        username: &Username,
+    ) -> Result<Option<RecordingSession>, sqlx::Error>;
+
+    async fn open_recording_sessions(&self) -> Result<Vec<RecordingSession>, sqlx::Error>;
+
+    /// Takes or renews the lease of `owner` on an open session until `lease_expires_at`.
+    /// Returns `false` if the session has ended or another owner's lease is still
+    /// running at `now`. Timestamps compare as text, so all of them must share a format.
+    async fn claim_recording_session(
+        &self,
+        id: i64,
+        owner: &str,
+        now: &str,
+        lease_expires_at: &str,
+    ) -> Result<bool, sqlx::Error>;
+
+    /// Everyone currently recording, with their latest state and open session.
+    async fn live_recordings(&self) -> Result<Vec<LiveRecording>, sqlx::Error>;
+
+    /// Newest first; `None` lists every user's sessions.
+    async fn list_recording_sessions(
+        &self,
+        usernames: Option<&[Username]>,
+    ) -> Result<Vec<RecordingSession>, sqlx::Error>;
+
+    /// Every sample taken during a session, oldest first.
+    async fn session_logs(&self, session_id: i64) -> Result<Vec<DataLog>, sqlx::Error>;
+
+    // Login sessions
+
+    async fn create_session(
+        &self,
+        token_hash: &str,
+        username: &Username,
+        created_at: &str,
+        expires_at: &str,
+    ) -> Result<(), sqlx::Error>;
+
+    /// Returns the user and role owning an unexpired session with the given token hash.
+    async fn find_session_user(
+        &self,
+        token_hash: &str,
+        now: &str,
+    ) -> Result<Option<(Username, Role)>, sqlx::Error>;
+
+    async fn delete_session(&self, token_hash: &str) -> Result<(), sqlx::Error>;
+
+    // Health
+
+    /// A round trip to the database, for `/readyz`.
+    async fn ping(&self) -> Result<(), sqlx::Error>;
+
+    /// `None` for storage without a connection pool.
+    fn pool_stats(&self) -> Option<PoolStats>;
+
+    /// Waits for queries in flight and closes every connection; later queries fail.
+    async fn close(&self);
+}
@@ -1,6 +1,20 @@
-use sqlx::{Pool, Sqlite};
-use crate::models::user_state::{UserState, DataLog};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use std::collections::BTreeMap;
+use sqlx::{types::Json, Pool, QueryBuilder, Sqlite};
+use tokio::sync::mpsc;
 
+use super::repository::{PoolStats, StateRepository};
+use crate::models::{
+    live::LiveRecording,
+    log_query::{LogQuery, SortOrder},
+    recording_session::RecordingSession,
+    username::Username,
+    user::{Assignment, Role, User, UserSummary},
+    user_state::{UserState, DataLog, Sample},
+};
+
+#[derive(Clone)]
 pub struct SqliteRepository {
     pool: Pool<Sqlite>,
 }
@@ -9,8 +23,11 @@ impl SqliteRepository {
     pub fn new(pool: Pool<Sqlite>) -> Self {
         Self { pool }
     }
-    
-    pub async fn get_user_state(&self, username: &str) -> Result<Option<UserState>, sqlx::Error> {
+}
+
+#[async_trait]
+impl StateRepository for SqliteRepository {
+    async fn get_user_state(&self, username: &Username) -> Result<Option<UserState>, sqlx::Error> {
         sqlx::query_as::<_, UserState>(
             "SELECT * FROM user_states WHERE username = ?"
         )
@@ -18,20 +35,16 @@ impl SqliteRepository {
         .fetch_optional(&self.pool)
         .await
     }
-    
-    pub async fn save_user_state(&self, state: &UserState) -> Result<(), sqlx::Error> {
+
+    async fn save_user_state(&self, state: &UserState) -> Result<(), sqlx::Error> {
         sqlx::query(
             r#"
             INSERT INTO user_states (
-                username, text_entry, category1, category2, category3, category4,
-                is_recording, last_saved, last_data
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                username, text_entry, categories, is_recording, last_saved, last_data
+            ) VALUES (?, ?, ?, ?, ?, ?)
             ON CONFLICT(username) DO UPDATE SET
                 text_entry = excluded.text_entry,
-                category1 = excluded.category1,
-                category2 = excluded.category2,
-                category3 = excluded.category3,
-                category4 = excluded.category4,
+                categories = excluded.categories,
                 is_recording = excluded.is_recording,
                 last_saved = excluded.last_saved,
                 last_data = excluded.last_data
@@ -39,11 +52,8 @@ impl SqliteRepository {
         )
         .bind(&state.username)
         .bind(&state.text_entry)
-        .bind(&state.category1)
-        .bind(&state.category2)
-        .bind(&state.category3)
-        .bind(&state.category4)
-        .bind(&state.is_recording)
+        .bind(Json(&state.categories))
+        .bind(state.is_recording)
         .bind(&state.last_saved)
         .bind(&state.last_data)
         .execute(&self.pool)
@@ -51,34 +61,692 @@ impl SqliteRepository {
         
         Ok(())
     }
-    
-    pub async fn log_data_entry(&self, log: &DataLog) -> Result<(), sqlx::Error> {
+
+    async fn save_entry(
+        &self,
+        username: &Username,
+        text_entry: &str,
+        categories: &BTreeMap<String, String>,
+    ) -> Result<UserState, sqlx::Error> {
+        // Fetching all rows lets SQLite finish (and commit) the statement before this
+        // returns; sqlx hands over the first RETURNING row as soon as it is produced
+        let mut saved = sqlx::query_as::<_, UserState>(
+            r#"
+            INSERT INTO user_states (username, text_entry, categories, is_recording)
+            VALUES (?, ?, ?, FALSE)
+            ON CONFLICT(username) DO UPDATE SET
+                text_entry = excluded.text_entry,
+                categories = excluded.categories
+            RETURNING *
+            "#,
+        )
+        .bind(username)
+        .bind(text_entry)
+        .bind(Json(categories))
+        .fetch_all(&self.pool)
+        .await?;
+
+        saved.pop().ok_or(sqlx::Error::RowNotFound)
+    }
+
+    async fn save_text_entry(
+        &self,
+        username: &Username,
+        text_entry: &str,
+    ) -> Result<UserState, sqlx::Error> {
+        let mut saved = sqlx::query_as::<_, UserState>(
+            r#"
+            INSERT INTO user_states (username, text_entry, is_recording)
+            VALUES (?, ?, FALSE)
+            ON CONFLICT(username) DO UPDATE SET text_entry = excluded.text_entry
+            RETURNING *
+            "#,
+        )
+        .bind(username)
+        .bind(text_entry)
+        .fetch_all(&self.pool)
+        .await?;
+
+        saved.pop().ok_or(sqlx::Error::RowNotFound)
+    }
+
+    async fn save_category(
+        &self,
+        username: &Username,
+        key: &str,
+        value: &str,
+    ) -> Result<UserState, sqlx::Error> {
+        // Merging an object sets the one key without building a JSON path out of it
+        let mut saved = sqlx::query_as::<_, UserState>(
+            r#"
+            INSERT INTO user_states (username, text_entry, categories, is_recording)
+            VALUES (?, '', json_object(?, ?), FALSE)
+            ON CONFLICT(username) DO UPDATE SET
+                categories = json_patch(categories, excluded.categories)
+            RETURNING *
+            "#,
+        )
+        .bind(username)
+        .bind(key)
+        .bind(value)
+        .fetch_all(&self.pool)
+        .await?;
+
+        saved.pop().ok_or(sqlx::Error::RowNotFound)
+    }
+
+    async fn log_data_entry(&self, log: &DataLog) -> Result<(), sqlx::Error> {
         sqlx::query(
             r#"
-            INSERT INTO data_logs (
-                username, text_entry, category1, category2, category3, category4, timestamp
-            ) VALUES (?, ?, ?, ?, ?, ?, ?)
+            INSERT INTO data_logs (username, text_entry, categories, timestamp, session_id)
+            VALUES (?, ?, ?, ?, ?)
             "#,
         )
         .bind(&log.username)
         .bind(&log.text_entry)
-        .bind(&log.category1)
-        .bind(&log.category2)
-        .bind(&log.category3)
-        .bind(&log.category4)
+        .bind(Json(&log.categories))
         .bind(&log.timestamp)
+        .bind(log.session_id)
         .execute(&self.pool)
         .await?;
         
         Ok(())
     }
-    
-    pub async fn get_user_logs(&self, username: &str) -> Result<Vec<DataLog>, sqlx::Error> {
+
+    async fn record_samples(&self, samples: &[Sample]) -> Result<Vec<DataLog>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let mut logs = Vec::with_capacity(samples.len());
+        for sample in samples {
+            let log = &sample.log;
+            // Another server may have stopped the session since this was sampled
+            let id: Option<i64> = sqlx::query_scalar(
+                r#"
+                INSERT INTO data_logs (username, text_entry, categories, timestamp, session_id)
+                SELECT ?1, ?2, ?3, ?4, ?5
+                WHERE ?5 IS NULL
+                   OR EXISTS (SELECT 1 FROM recording_sessions WHERE id = ?5 AND ended_at IS NULL)
+                RETURNING id
+                "#,
+            )
+            .bind(&log.username)
+            .bind(&log.text_entry)
+            .bind(Json(&log.categories))
+            .bind(&log.timestamp)
+            .bind(log.session_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+            let Some(id) = id else {
+                continue;
+            };
+
+            sqlx::query("INSERT INTO csv_outbox (log_id) VALUES (?)")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("UPDATE user_states SET last_saved = ?, last_data = ? WHERE username = ?")
+                .bind(&log.timestamp)
+                .bind(&sample.summary)
+                .bind(&log.username)
+                .execute(&mut *tx)
+                .await?;
+
+            logs.push(DataLog {
+                id: Some(id),
+                ..log.clone()
+            });
+        }
+        tx.commit().await?;
+        Ok(logs)
+    }
+
+    async fn csv_outbox(&self, limit: u32) -> Result<Vec<DataLog>, sqlx::Error> {
         sqlx::query_as::<_, DataLog>(
-            "SELECT * FROM data_logs WHERE username = ? ORDER BY timestamp DESC"
+            r#"
+            SELECT data_logs.* FROM csv_outbox
+            JOIN data_logs ON data_logs.id = csv_outbox.log_id
+            ORDER BY data_logs.id
+            LIMIT ?
+            "#,
+        )
+        .bind(i64::from(limit))
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn clear_csv_outbox(&self, log_ids: &[i64]) -> Result<(), sqlx::Error> {
+        if log_ids.is_empty() {
+            return Ok(());
+        }
+        let mut builder = QueryBuilder::<Sqlite>::new("DELETE FROM csv_outbox WHERE log_id IN (");
+        let mut ids = builder.separated(", ");
+        for id in log_ids {
+            ids.push_bind(*id);
+        }
+        builder.push(")");
+        builder.build().execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn clear_user_csv_outbox(
+        &self,
+        username: &Username,
+        through_id: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "DELETE FROM csv_outbox WHERE log_id <= ? \
+             AND log_id IN (SELECT id FROM data_logs WHERE username = ?)",
         )
+        .bind(through_id)
         .bind(username)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn query_logs(&self, query: &LogQuery) -> Result<Vec<DataLog>, sqlx::Error> {
+        if query.usernames.as_ref().is_some_and(Vec::is_empty) {
+            return Ok(Vec::new());
+        }
+        let mut builder = log_filter_query(query);
+
+        let (comparison, direction) = match query.sort {
+            SortOrder::Asc => (">", "ASC"),
+            SortOrder::Desc => ("<", "DESC"),
+        };
+        if let Some(after) = &query.after {
+            builder
+                .push(format!(" AND (timestamp {} ", comparison))
+                .push_bind(&after.timestamp)
+                .push(" OR (timestamp = ")
+                .push_bind(&after.timestamp)
+                .push(format!(" AND id {} ", comparison))
+                .push_bind(after.id)
+                .push("))");
+        }
+
+        builder
+            .push(format!(" ORDER BY timestamp {0}, id {0} LIMIT ", direction))
+            .push_bind(i64::from(query.limit));
+
+        builder.build_query_as::<DataLog>().fetch_all(&self.pool).await
+    }
+
+    async fn stream_logs(
+        &self,
+        query: &LogQuery,
+        rows: mpsc::Sender<Result<DataLog, sqlx::Error>>,
+    ) {
+        if query.usernames.as_ref().is_some_and(Vec::is_empty) {
+            return;
+        }
+        let direction = match query.sort {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        };
+        let mut builder = log_filter_query(query);
+        builder.push(format!(" ORDER BY timestamp {0}, id {0}", direction));
+
+        let mut logs = builder.build_query_as::<DataLog>().fetch(&self.pool);
+        while let Some(row) = logs.next().await {
+            let failed = row.is_err();
+            if rows.send(row).await.is_err() || failed {
+                break;
+            }
+        }
+    }
+
+    async fn create_user(&self, user: &User) -> Result<Option<Role>, sqlx::Error> {
+        // An INSERT takes SQLite's write lock before it reads, so the emptiness check can't
+        // race another registration. sqlx hands over the first RETURNING row before SQLite
+        // has finished (and committed) the statement, so all of them are fetched.
+        let roles = sqlx::query_scalar::<_, Role>(
+            r#"
+            INSERT INTO users (username, password_hash, role, created_at)
+            SELECT ?, ?, CASE WHEN EXISTS (SELECT 1 FROM users) THEN ? ELSE ? END, ?
+            WHERE true
+            ON CONFLICT(username) DO NOTHING
+            RETURNING role
+            "#,
+        )
+        .bind(&user.username)
+        .bind(&user.password_hash)
+        .bind(user.role)
+        .bind(Role::Admin)
+        .bind(&user.created_at)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(roles.into_iter().next())
+    }
+
+    async fn get_user(&self, username: &Username) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = ?")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn list_users(&self) -> Result<Vec<UserSummary>, sqlx::Error> {
+        sqlx::query_as::<_, UserSummary>(
+            "SELECT username, role, created_at FROM users ORDER BY username",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn set_user_role(&self, username: &Username, role: Role) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("UPDATE users SET role = ? WHERE username = ?")
+            .bind(role)
+            .bind(username)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn assign_participant(
+        &self,
+        observer: &Username,
+        participant: &Username,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO observer_assignments (observer, participant)
+            VALUES (?, ?)
+            ON CONFLICT(observer, participant) DO NOTHING
+            "#,
+        )
+        .bind(observer)
+        .bind(participant)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn unassign_participant(
+        &self,
+        observer: &Username,
+        participant: &Username,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM observer_assignments WHERE observer = ? AND participant = ?")
+            .bind(observer)
+            .bind(participant)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn is_assigned(&self, observer: &Username, participant: &Username) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM observer_assignments WHERE observer = ? AND participant = ?
+            )
+            "#,
+        )
+        .bind(observer)
+        .bind(participant)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn list_assigned_participants(
+        &self,
+        observer: &Username,
+    ) -> Result<Vec<Username>, sqlx::Error> {
+        sqlx::query_scalar::<_, Username>(
+            "SELECT participant FROM observer_assignments WHERE observer = ? ORDER BY participant",
+        )
+        .bind(observer)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn list_participants(&self) -> Result<Vec<Username>, sqlx::Error> {
+        sqlx::query_scalar::<_, Username>(
+            "SELECT username FROM users WHERE role = 'participant' ORDER BY username",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn list_assignments(&self) -> Result<Vec<Assignment>, sqlx::Error> {
+        sqlx::query_as::<_, Assignment>(
+            "SELECT observer, participant FROM observer_assignments ORDER BY observer, participant",
+        )
         .fetch_all(&self.pool)
         .await
     }
-}
\ No newline at end of file
+
+    async fn start_recording_session(
+        &self,
+        username: &Username,
+        label: Option<&str>,
+        started_at: &str,
+    ) -> Result<Option<RecordingSession>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let already_open: Option<i64> = sqlx::query_scalar(
+            "SELECT id FROM recording_sessions WHERE username = ? AND ended_at IS NULL",
+        )
+        .bind(username)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if already_open.is_some() {
+            return Ok(None);
+        }
+
+        let session = sqlx::query_as::<_, RecordingSession>(
+            r#"
+            INSERT INTO recording_sessions (username, label, started_at)
+            VALUES (?, ?, ?)
+            RETURNING *
+            "#,
+        )
+        .bind(username)
+        .bind(label)
+        .bind(started_at)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO user_states (username, text_entry, is_recording)
+            VALUES (?, '', TRUE)
+            ON CONFLICT(username) DO UPDATE SET is_recording = TRUE
+            "#,
+        )
+        .bind(username)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Some(session))
+    }
+
+    async fn stop_recording_session(
+        &self,
+        id: i64,
+        ended_at: &str,
+    ) -> Result<Option<RecordingSession>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let session = sqlx::query_as::<_, RecordingSession>(
+            r#"
+            UPDATE recording_sessions
+            SET ended_at = MAX(
+                ?1,
+                COALESCE((SELECT MAX(timestamp) FROM data_logs WHERE session_id = ?2), ?1)
+            )
+            WHERE id = ?2 AND ended_at IS NULL
+            RETURNING *
+            "#,
+        )
+        .bind(ended_at)
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(session) = &session {
+            sqlx::query("UPDATE user_states SET is_recording = FALSE WHERE username = ?")
+                .bind(&session.username)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(session)
+    }
+
+    async fn interrupt_recording_sessions(
+        &self,
+        ids: &[i64],
+        ended_at: &str,
+    ) -> Result<Vec<RecordingSession>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let mut sessions = Vec::new();
+        for id in ids {
+            let session = sqlx::query_as::<_, RecordingSession>(
+                "UPDATE recording_sessions SET ended_at = ?, interrupted = TRUE
+                 WHERE id = ? AND ended_at IS NULL RETURNING *",
+            )
+            .bind(ended_at)
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?;
+            let Some(session) = session else {
+                continue;
+            };
+            sqlx::query("UPDATE user_states SET is_recording = FALSE WHERE username = ?")
+                .bind(&session.username)
+                .execute(&mut *tx)
+                .await?;
+            sessions.push(session);
+        }
+
+        tx.commit().await?;
+        Ok(sessions)
+    }
+
+    async fn get_recording_session(
+        &self,
+        id: i64,
+    ) -> Result<Option<RecordingSession>, sqlx::Error> {
+        sqlx::query_as::<_, RecordingSession>("SELECT * FROM recording_sessions WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn open_recording_session(
+        &self,
+        username: &Username,
+    ) -> Result<Option<RecordingSession>, sqlx::Error> {
+        sqlx::query_as::<_, RecordingSession>(
+            "SELECT * FROM recording_sessions WHERE username = ? AND ended_at IS NULL",
+        )
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn open_recording_sessions(&self) -> Result<Vec<RecordingSession>, sqlx::Error> {
+        sqlx::query_as::<_, RecordingSession>(
+            "SELECT * FROM recording_sessions WHERE ended_at IS NULL ORDER BY started_at",
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn claim_recording_session(
+        &self,
+        id: i64,
+        owner: &str,
+        now: &str,
+        lease_expires_at: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE recording_sessions SET owner = ?, lease_expires_at = ?
+            WHERE id = ? AND ended_at IS NULL
+              AND (owner IS NULL OR owner = ? OR lease_expires_at IS NULL OR lease_expires_at < ?)
+            "#,
+        )
+        .bind(owner)
+        .bind(lease_expires_at)
+        .bind(id)
+        .bind(owner)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn live_recordings(&self) -> Result<Vec<LiveRecording>, sqlx::Error> {
+        sqlx::query_as::<_, LiveRecording>(
+            r#"
+            SELECT us.username, us.text_entry, us.categories, us.last_saved,
+                   rs.id AS session_id, rs.label, rs.started_at
+            FROM user_states us
+            JOIN recording_sessions rs ON rs.username = us.username AND rs.ended_at IS NULL
+            WHERE us.is_recording
+            ORDER BY us.username
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn list_recording_sessions(
+        &self,
+        usernames: Option<&[Username]>,
+    ) -> Result<Vec<RecordingSession>, sqlx::Error> {
+        let mut builder = QueryBuilder::<Sqlite>::new("SELECT * FROM recording_sessions");
+        if let Some(usernames) = usernames {
+            if usernames.is_empty() {
+                return Ok(Vec::new());
+            }
+            builder.push(" WHERE username IN (");
+            let mut separated = builder.separated(", ");
+            for username in usernames {
+                separated.push_bind(username);
+            }
+            separated.push_unseparated(")");
+        }
+        builder.push(" ORDER BY started_at DESC, id DESC");
+
+        builder
+            .build_query_as::<RecordingSession>()
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn session_logs(&self, session_id: i64) -> Result<Vec<DataLog>, sqlx::Error> {
+        sqlx::query_as::<_, DataLog>(
+            "SELECT * FROM data_logs WHERE session_id = ? ORDER BY timestamp, id",
+        )
+        .bind(session_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn create_session(
+        &self,
+        token_hash: &str,
+        username: &Username,
+        created_at: &str,
+        expires_at: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO auth_sessions (token_hash, username, created_at, expires_at)
+            VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .bind(token_hash)
+        .bind(username)
+        .bind(created_at)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn find_session_user(
+        &self,
+        token_hash: &str,
+        now: &str,
+    ) -> Result<Option<(Username, Role)>, sqlx::Error> {
+        sqlx::query_as::<_, (Username, Role)>(
+            r#"
+            SELECT users.username, users.role
+            FROM auth_sessions
+            JOIN users ON users.username = auth_sessions.username
+            WHERE auth_sessions.token_hash = ? AND auth_sessions.expires_at > ?
+            "#,
+        )
+        .bind(token_hash)
+        .bind(now)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn delete_session(&self, token_hash: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM auth_sessions WHERE token_hash = ?")
+            .bind(token_hash)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), sqlx::Error> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    fn pool_stats(&self) -> Option<PoolStats> {
+        Some(PoolStats {
+            size: self.pool.size(),
+            idle: self.pool.num_idle(),
+            max: self.pool.options().get_max_connections(),
+        })
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+/// `SELECT * FROM data_logs` narrowed by everything in `query` except paging.
+fn log_filter_query(query: &LogQuery) -> QueryBuilder<'_, Sqlite> {
+    let mut builder = QueryBuilder::<Sqlite>::new("SELECT * FROM data_logs WHERE 1 = 1");
+
+    if let Some(usernames) = &query.usernames {
+        builder.push(" AND username IN (");
+        let mut separated = builder.separated(", ");
+        for username in usernames {
+            separated.push_bind(username);
+        }
+        separated.push_unseparated(")");
+    }
+    if let Some(from) = &query.from {
+        builder.push(" AND timestamp >= ").push_bind(from.to_rfc3339());
+    }
+    if let Some(to) = &query.to {
+        builder.push(" AND timestamp < ").push_bind(to.to_rfc3339());
+    }
+    if let Some(value) = &query.value {
+        builder.push(" AND EXISTS (SELECT 1 FROM json_each(data_logs.categories) WHERE json_each.value = ");
+        builder.push_bind(value);
+        if let Some(category) = &query.category {
+            builder.push(" AND json_each.key = ").push_bind(category);
+        }
+        builder.push(")");
+    }
+    if let Some(text) = &query.text {
+        builder
+            .push(" AND text_entry LIKE ")
+            .push_bind(format!("%{}%", escape_like(text)))
+            .push(" ESCAPE '\\'");
+    }
+
+    builder
+}
+
+/// Escapes `%`, `_` and the escape character itself for a `LIKE ... ESCAPE '\'` pattern.
+pub(super) fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
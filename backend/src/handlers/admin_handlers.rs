@@ -0,0 +1,250 @@
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::sync::Arc;
+
+use crate::{
+    auth::extractor::AuthUser,
+    csv::index::CsvIndex,
+    handlers::error::{AppError, JsonBody, PathParams},
+    live,
+    models::{
+        app_state::AppState,
+        live::LiveSnapshot,
+        user::{Assignment, Role, RoleUpdate, UserSummary},
+        username::Username,
+    },
+    reconcile::{self, CsvRebuild, ReconcileError},
+};
+
+pub async fn list_users(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<UserSummary>>, AppError> {
+    state
+        .repo
+        .list_users()
+        .await
+        .map(Json)
+        .map_err(|err| AppError::internal("Failed to list users", err))
+}
+
+pub async fn set_user_role(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    PathParams(username): PathParams<String>,
+    JsonBody(update): JsonBody<RoleUpdate>,
+) -> Result<StatusCode, AppError> {
+    let username = Username::parse(&username)?;
+
+    // Guard against an admin locking everyone out of the admin screens
+    if username == user.username && update.role != Role::Admin {
+        return Err(AppError::BadRequest("Admins cannot remove their own admin role".into()));
+    }
+
+    match state.repo.set_user_role(&username, update.role).await {
+        Ok(true) => Ok(StatusCode::NO_CONTENT),
+        Ok(false) => Err(AppError::NotFound("User not found".into())),
+        Err(err) => Err(AppError::internal("Failed to update role", err)),
+    }
+}
+
+pub async fn list_assignments(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<Assignment>>, AppError> {
+    state
+        .repo
+        .list_assignments()
+        .await
+        .map(Json)
+        .map_err(|err| AppError::internal("Failed to list assignments", err))
+}
+
+pub async fn assign_participant(
+    State(state): State<Arc<AppState>>,
+    JsonBody(assignment): JsonBody<Assignment>,
+) -> Result<StatusCode, AppError> {
+    let lookup_error = |err| AppError::internal("Failed to look up user", err);
+
+    match state.repo.get_user(&assignment.observer).await.map_err(lookup_error)? {
+        Some(observer) if observer.role == Role::Observer => {}
+        Some(_) => {
+            return Err(AppError::BadRequest(format!(
+                "'{}' does not have the observer role",
+                assignment.observer
+            )));
+        }
+        None => return Err(AppError::NotFound("Observer not found".into())),
+    }
+    if state
+        .repo
+        .get_user(&assignment.participant)
+        .await
+        .map_err(lookup_error)?
+        .is_none()
+    {
+        return Err(AppError::NotFound("Participant not found".into()));
+    }
+
+    state
+        .repo
+        .assign_participant(&assignment.observer, &assignment.participant)
+        .await
+        .map_err(|err| AppError::internal("Failed to save assignment", err))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn unassign_participant(
+    State(state): State<Arc<AppState>>,
+    JsonBody(assignment): JsonBody<Assignment>,
+) -> Result<StatusCode, AppError> {
+    state
+        .repo
+        .unassign_participant(&assignment.observer, &assignment.participant)
+        .await
+        .map_err(|err| AppError::internal("Failed to remove assignment", err))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `GET /api/admin/live`: who is recording right now. `/api/ws` pushes the same
+/// snapshot whenever it changes.
+pub async fn live_recordings(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<LiveSnapshot>, AppError> {
+    live::snapshot(state.repo.as_ref())
+        .await
+        .map(Json)
+        .map_err(|err| AppError::internal("Failed to load recordings", err))
+}
+
+/// Downloads a user's newest CSV file as it currently is on disk.
+pub async fn download_csv(
+    State(state): State<Arc<AppState>>,
+    PathParams(username): PathParams<String>,
+) -> Result<Response, AppError> {
+    let username = known_user(&state, &username).await?;
+    let index = csv_index(&state, &username).await?;
+    let Some(newest) = index.files.last() else {
+        return Err(AppError::NotFound("No CSV has been recorded for this user".into()));
+    };
+    send_csv_file(&state, &username, &newest.file).await
+}
+
+/// `GET /api/admin/csv/{username}/files`: every CSV file of a user, oldest first.
+pub async fn list_csv_files(
+    State(state): State<Arc<AppState>>,
+    PathParams(username): PathParams<String>,
+) -> Result<Json<CsvIndex>, AppError> {
+    let username = known_user(&state, &username).await?;
+    csv_index(&state, &username).await.map(Json)
+}
+
+/// `GET /api/admin/csv/{username}/files/{file}`: one of the files the index lists.
+pub async fn download_csv_file(
+    State(state): State<Arc<AppState>>,
+    PathParams((username, file)): PathParams<(String, String)>,
+) -> Result<Response, AppError> {
+    let username = known_user(&state, &username).await?;
+    send_csv_file(&state, &username, &file).await
+}
+
+/// `POST /api/admin/csv/{username}/rebuild`: rewrites a user's CSV files from the
+/// database, e.g. after one was deleted or edited by hand.
+pub async fn rebuild_csv(
+    State(state): State<Arc<AppState>>,
+    PathParams(username): PathParams<String>,
+) -> Result<Json<CsvIndex>, AppError> {
+    let username = known_user(&state, &username).await?;
+    reconcile::rebuild_user(
+        state.repo.as_ref(),
+        &state.csv,
+        &state.codebook,
+        &username,
+    )
+    .await
+    .map(Json)
+    .map_err(|err| rebuild_error(&username, err))
+}
+
+/// `POST /api/admin/rebuild-csv`: rewrites every user's CSV files from the database.
+pub async fn rebuild_all_csv(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<CsvRebuild>>, AppError> {
+    reconcile::rebuild_all(state.repo.as_ref(), &state.csv, &state.codebook)
+        .await
+        .map(Json)
+        .map_err(|err| AppError::internal("Failed to rebuild CSV files", err))
+}
+
+async fn known_user(state: &AppState, username: &str) -> Result<Username, AppError> {
+    let username = Username::parse(username)?;
+    let known_user = state
+        .repo
+        .get_user(&username)
+        .await
+        .map_err(|err| AppError::internal("Failed to look up user", err))?
+        .is_some();
+    if !known_user {
+        return Err(AppError::NotFound("User not found".into()));
+    }
+    Ok(username)
+}
+
+async fn send_csv_file(
+    state: &AppState,
+    username: &Username,
+    file: &str,
+) -> Result<Response, AppError> {
+    // Only names the index lists ever reach the filesystem
+    let (csv, username, name) = (Arc::clone(&state.csv), username.clone(), file.to_string());
+    let path = tokio::task::spawn_blocking(move || csv.file_path(&username, &name))
+        .await
+        .map_err(csv_index_error)?
+        .map_err(csv_index_error)?
+        .ok_or_else(|| AppError::NotFound("No such CSV file".into()))?;
+    let contents = match tokio::fs::read(&path).await {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(AppError::NotFound("No such CSV file".into()));
+        }
+        Err(err) => return Err(AppError::internal("Failed to read CSV file", err)),
+    };
+
+    let content_type = if file.ends_with(".gz") {
+        "application/gzip"
+    } else {
+        "text/csv; charset=utf-8"
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", file),
+            ),
+        ],
+        contents,
+    )
+        .into_response())
+}
+
+fn rebuild_error(username: &Username, err: ReconcileError) -> AppError {
+    AppError::internal(format!("Failed to rebuild the CSV files of {}", username), err)
+}
+
+/// `username`'s index, read off the runtime as it may touch their files.
+async fn csv_index(state: &AppState, username: &Username) -> Result<CsvIndex, AppError> {
+    let (csv, username) = (Arc::clone(&state.csv), username.clone());
+    tokio::task::spawn_blocking(move || csv.index(&username))
+        .await
+        .map_err(csv_index_error)?
+        .map_err(csv_index_error)
+}
+
+fn csv_index_error(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> AppError {
+    AppError::internal("Failed to read CSV index", err)
+}
@@ -0,0 +1,122 @@
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::{Duration, Utc};
+use std::sync::Arc;
+
+use crate::{
+    auth::{
+        extractor::AuthUser,
+        password::{hash_password, verify_login, MIN_PASSWORD_LENGTH},
+        token::{generate_token, hash_token, SESSION_TTL_HOURS},
+    },
+    handlers::error::{AppError, JsonBody},
+    models::{
+        app_state::AppState,
+        user::{AuthResponse, Credentials, Role, User},
+        username::Username,
+    },
+};
+
+pub async fn register(
+    State(state): State<Arc<AppState>>,
+    JsonBody(credentials): JsonBody<Credentials>,
+) -> Result<(StatusCode, Json<AuthResponse>), AppError> {
+    if credentials.password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(AppError::BadRequest(format!(
+            "Password must be at least {} characters long",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+
+    // Argon2 is deliberately slow, so it runs off the async workers
+    let password = credentials.password;
+    let password_hash = tokio::task::spawn_blocking(move || hash_password(&password))
+        .await
+        .map_err(|err| AppError::internal("Failed to hash password", err))?
+        .map_err(|err| AppError::internal("Failed to hash password", err))?;
+
+    // The very first account bootstraps the deployment, so the repository makes it admin
+    let user = User {
+        username: credentials.username,
+        password_hash,
+        role: Role::Participant,
+        created_at: Utc::now().to_rfc3339(),
+    };
+
+    let role = match state.repo.create_user(&user).await {
+        Ok(Some(role)) => role,
+        Ok(None) => {
+            return Err(AppError::Conflict("Username is already taken".into()));
+        }
+        Err(err) => return Err(AppError::internal("Failed to create user", err)),
+    };
+
+    let response = start_session(&state, &user.username, role).await?;
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+pub async fn login(
+    State(state): State<Arc<AppState>>,
+    JsonBody(credentials): JsonBody<Credentials>,
+) -> Result<Json<AuthResponse>, AppError> {
+    let user = state
+        .repo
+        .get_user(&credentials.username)
+        .await
+        .map_err(|err| AppError::internal("Failed to look up user", err))?;
+
+    // Unknown users are verified against a dummy hash and get the same message as wrong
+    // passwords, so neither the answer nor its timing tells which accounts exist
+    let password = credentials.password;
+    let password_hash = user.as_ref().map(|user| user.password_hash.clone());
+    let verified =
+        tokio::task::spawn_blocking(move || verify_login(&password, password_hash.as_deref()))
+            .await
+            .map_err(|err| AppError::internal("Failed to verify password", err))?;
+
+    match user {
+        Some(user) if verified => {
+            Ok(Json(start_session(&state, &user.username, user.role).await?))
+        }
+        _ => Err(AppError::Unauthorized("Invalid username or password".into())),
+    }
+}
+
+pub async fn logout(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> Result<StatusCode, AppError> {
+    state
+        .repo
+        .delete_session(&user.token_hash)
+        .await
+        .map_err(|err| AppError::internal("Failed to end session", err))?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn start_session(
+    state: &AppState,
+    username: &Username,
+    role: Role,
+) -> Result<AuthResponse, AppError> {
+    let token = generate_token();
+    let now = Utc::now();
+    let expires_at = now + Duration::hours(SESSION_TTL_HOURS);
+
+    state
+        .repo
+        .create_session(
+            &hash_token(&token),
+            username,
+            &now.to_rfc3339(),
+            &expires_at.to_rfc3339(),
+        )
+        .await
+        .map_err(|err| AppError::internal("Failed to create session", err))?;
+
+    Ok(AuthResponse {
+        token,
+        username: username.clone(),
+        role,
+    })
+}
@@ -0,0 +1,179 @@
+use axum::{
+    extract::{FromRequest, FromRequestParts, Request},
+    http::{header, request::Parts, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+
+pub use shared::problem::{codes, ProblemDetails};
+
+use crate::{csv::dialect::DialectError, models::username::UsernameError, request_id};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Everything a handler can fail with. Each variant answers with its own status and one
+/// of the stable `codes` that clients match on.
+#[derive(Debug, Error)]
+pub enum AppError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    /// The request was well-formed, but its content breaks a rule (an unknown field, ...)
+    #[error("{0}")]
+    Unprocessable(String),
+    #[error(transparent)]
+    Username(#[from] UsernameError),
+    #[error(transparent)]
+    Dialect(#[from] DialectError),
+    /// A body, query string or path that didn't parse; `status` is what axum chose for it
+    #[error("{message}")]
+    InvalidRequest { status: StatusCode, message: String },
+    /// Something broke on our side. Only `message` reaches the client; `source` is logged.
+    #[error("{message}")]
+    Internal {
+        message: String,
+        #[source]
+        source: BoxError,
+    },
+}
+
+impl AppError {
+    /// A server-side failure: `message` says what we were doing, `source` what went wrong.
+    pub fn internal(message: impl Into<String>, source: impl Into<BoxError>) -> Self {
+        AppError::Internal {
+            message: message.into(),
+            source: source.into(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) | AppError::Dialect(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Unprocessable(_) | AppError::Username(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::InvalidRequest { status, .. } => *status,
+            AppError::Internal { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::BadRequest(_) => codes::BAD_REQUEST,
+            AppError::Unauthorized(_) => codes::UNAUTHORIZED,
+            AppError::Forbidden(_) => codes::FORBIDDEN,
+            AppError::NotFound(_) => codes::NOT_FOUND,
+            AppError::Conflict(_) => codes::CONFLICT,
+            AppError::Unprocessable(_) => codes::UNPROCESSABLE,
+            AppError::Username(_) => codes::INVALID_USERNAME,
+            AppError::Dialect(_) => codes::INVALID_CSV_DIALECT,
+            AppError::InvalidRequest { .. } => codes::INVALID_REQUEST,
+            AppError::Internal { .. } => codes::INTERNAL_ERROR,
+        }
+    }
+}
+
+/// Anything under `/api` that no route matches, so it isn't mistaken for a page of the
+/// frontend.
+pub async fn unknown_endpoint() -> AppError {
+    AppError::NotFound("No such API endpoint".to_string())
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        if let AppError::Internal { message, source } = &self {
+            tracing::error!("{}: {}", message, source);
+        }
+
+        let status = self.status();
+        let problem = ProblemDetails {
+            kind: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or("Error").to_string(),
+            status: status.as_u16(),
+            detail: self.to_string(),
+            code: self.code().to_string(),
+            request_id: request_id::current(),
+        };
+        let mut response = (status, Json(problem)).into_response();
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        response
+    }
+}
+
+/// `Json`, but a body that fails to parse or validate is answered with `ProblemDetails`
+/// instead of axum's plain-text rejection.
+pub struct JsonBody<T>(pub T);
+
+impl<T, S> FromRequest<S> for JsonBody<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        match Json::<T>::from_request(request, state).await {
+            Ok(Json(value)) => Ok(JsonBody(value)),
+            Err(rejection) => Err(AppError::InvalidRequest {
+                status: rejection.status(),
+                message: rejection.body_text(),
+            }),
+        }
+    }
+}
+
+/// `Query`, with its rejections answered like `JsonBody`'s.
+pub struct QueryParams<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for QueryParams<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match axum::extract::Query::<T>::from_request_parts(parts, state).await {
+            Ok(axum::extract::Query(value)) => Ok(QueryParams(value)),
+            Err(rejection) => Err(AppError::InvalidRequest {
+                status: rejection.status(),
+                message: rejection.body_text(),
+            }),
+        }
+    }
+}
+
+/// `Path`, with its rejections answered like `JsonBody`'s.
+pub struct PathParams<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for PathParams<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match axum::extract::Path::<T>::from_request_parts(parts, state).await {
+            Ok(axum::extract::Path(value)) => Ok(PathParams(value)),
+            Err(rejection) => Err(AppError::InvalidRequest {
+                status: rejection.status(),
+                message: rejection.body_text(),
+            }),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::Serialize;
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use crate::{metrics, models::app_state::AppState};
+
+/// Tells apart the probe files of readiness checks that run at the same time.
+static PROBE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: Vec<ReadinessCheck>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReadinessCheck {
+    pub name: &'static str,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ReadinessCheck {
+    fn new(name: &'static str, result: Result<(), String>) -> Self {
+        let error = result.err();
+        if let Some(error) = &error {
+            tracing::warn!("Readiness check {} failed: {}", name, error);
+        }
+        Self {
+            name,
+            ok: error.is_none(),
+            error,
+        }
+    }
+}
+
+/// `GET /healthz`: the process is up and answering.
+pub async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// `GET /readyz`: the database answers and the data and CSV directories take writes.
+/// 503 with the failed checks otherwise.
+pub async fn readyz(State(state): State<Arc<AppState>>) -> (StatusCode, Json<Readiness>) {
+    let mut checks = vec![
+        ReadinessCheck::new("database", state.repo.ping().await.map_err(|err| err.to_string())),
+        ReadinessCheck::new("data_dir", probe_dir(&state.data_dir).await),
+    ];
+    if state.csv.base_dir() != state.data_dir {
+        checks.push(ReadinessCheck::new("csv_dir", probe_dir(state.csv.base_dir()).await));
+    }
+
+    let ready = checks.iter().all(|check| check.ok);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(Readiness { ready, checks }))
+}
+
+/// `GET /metrics`: counters and gauges in the Prometheus text format.
+pub async fn metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        metrics::render(&state),
+    )
+}
+
+/// Writes and removes a file in `dir`.
+async fn probe_dir(dir: &Path) -> Result<(), String> {
+    let probe = dir.join(format!(
+        ".readyz-{}-{}",
+        std::process::id(),
+        PROBE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let written = tokio::fs::write(&probe, b"ok").await;
+    let removed = tokio::fs::remove_file(&probe).await;
+    written
+        .and(removed)
+        .map_err(|err| format!("{}: {}", dir.display(), err))
+}
@@ -0,0 +1,242 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::State,
+    http::header,
+    response::IntoResponse,
+    Json,
+};
+use futures_util::StreamExt;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::{
+    auth::{extractor::AuthUser, permissions::ensure_can_access},
+    csv::dialect::CsvDialect,
+    handlers::error::{AppError, QueryParams},
+    models::{
+        app_state::AppState,
+        codebook::Codebook,
+        log_query::{
+            ExportParams, LogCursor, LogPage, LogQuery, LogQueryParams, SortOrder,
+            DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE,
+        },
+        user::Role,
+        user_state::DataLog,
+        username::Username,
+    },
+};
+
+/// `GET /api/logs`: browse `data_logs` history, limited to what the caller may see.
+pub async fn query_logs(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    QueryParams(params): QueryParams<LogQueryParams>,
+) -> Result<Json<LogPage>, AppError> {
+    let usernames = match params.username {
+        Some(username) => {
+            let username = Username::parse(&username)?;
+            ensure_can_access(&state, &user, &username).await?;
+            Some(vec![username])
+        }
+        None => visible_usernames(&state, &user).await?,
+    };
+
+    let after = match params.cursor.as_deref() {
+        Some(cursor) => Some(
+            LogCursor::decode(cursor)
+                .ok_or_else(|| AppError::BadRequest("Invalid cursor".into()))?,
+        ),
+        None => None,
+    };
+
+    if let Some(category) = &params.category
+        && !state.codebook.fields.iter().any(|field| &field.key == category)
+    {
+        return Err(AppError::BadRequest(format!("Unknown category field '{}'", category)));
+    }
+
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let query = LogQuery {
+        usernames,
+        from: params.from,
+        to: params.to,
+        category: params.category,
+        value: params.value.filter(|value| !value.is_empty()),
+        text: params.q.filter(|q| !q.is_empty()),
+        after,
+        sort: params.sort,
+        // One extra row tells us whether there is another page
+        limit: limit + 1,
+    };
+
+    let mut items = state
+        .repo
+        .query_logs(&query)
+        .await
+        .map_err(|err| AppError::internal("Failed to query logs", err))?;
+
+    let next_cursor = if items.len() > limit as usize {
+        items.truncate(limit as usize);
+        items.last().and_then(|last| {
+            last.id.map(|id| {
+                LogCursor {
+                    timestamp: last.timestamp.clone(),
+                    id,
+                }
+                .encode()
+            })
+        })
+    } else {
+        None
+    };
+
+    Ok(Json(LogPage { items, next_cursor }))
+}
+
+/// Rows buffered between the database and the response body.
+const EXPORT_BUFFER_ROWS: usize = 256;
+
+/// `GET /api/logs/export`: stream `data_logs` as CSV, oldest first, without holding the
+/// whole export in memory. Columns match the recorded CSV files, optionally led by `id`.
+pub async fn export_logs(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    QueryParams(params): QueryParams<ExportParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let requested = params
+        .users
+        .as_deref()
+        .map(|users| {
+            users
+                .split(',')
+                .filter(|username| !username.trim().is_empty())
+                .map(Username::parse)
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .transpose()?;
+    let usernames = match requested {
+        Some(usernames) if !usernames.is_empty() => {
+            for username in &usernames {
+                ensure_can_access(&state, &user, username).await?;
+            }
+            Some(usernames)
+        }
+        _ => visible_usernames(&state, &user).await?,
+    };
+
+    let dialect = export_dialect(state.csv.settings().dialect.clone(), &params)?;
+    let filename = match usernames.as_deref() {
+        Some([username]) => format!("{}.csv", username.file_stem()),
+        _ => "export.csv".to_string(),
+    };
+
+    let query = LogQuery {
+        usernames,
+        from: params.from,
+        to: params.to,
+        sort: SortOrder::Asc,
+        ..LogQuery::default()
+    };
+
+    let (rows, received) = mpsc::channel(EXPORT_BUFFER_ROWS);
+    let repo = state.repo.clone();
+    tokio::spawn(async move { repo.stream_logs(&query, rows).await });
+
+    let codebook = Arc::clone(&state.codebook);
+    let include_id = params.include_id;
+    let mut preamble = Vec::new();
+    dialect
+        .write_preamble(&mut preamble)
+        .expect("in-memory CSV write");
+    if params.header {
+        let mut header = codebook.csv_header();
+        if include_id {
+            header.insert(0, "id".to_string());
+        }
+        preamble.extend_from_slice(&encode_csv(&dialect, [header]));
+    }
+    let preamble = (!preamble.is_empty()).then(|| Ok(Bytes::from(preamble)));
+
+    // Whatever rows are already waiting go out together as one chunk
+    let body_rows = ReceiverStream::new(received)
+        .ready_chunks(EXPORT_BUFFER_ROWS)
+        .map(move |batch| {
+            let records = batch
+                .into_iter()
+                .map(|row| row.map(|log| dialect.record(log_record(&codebook, &log, include_id))))
+                .collect::<Result<Vec<_>, sqlx::Error>>()?;
+            Ok::<_, sqlx::Error>(encode_csv(&dialect, records))
+        });
+    let body = futures_util::stream::iter(preamble).chain(body_rows);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        Body::from_stream(body),
+    ))
+}
+
+/// One export row, in the same column order as `Codebook::csv_header`.
+fn log_record(codebook: &Codebook, log: &DataLog, include_id: bool) -> Vec<String> {
+    let mut record = Vec::with_capacity(codebook.fields.len() + 4);
+    if include_id {
+        record.push(log.id.map(|id| id.to_string()).unwrap_or_default());
+    }
+    record.extend(codebook.csv_record(log));
+    record
+}
+
+/// The configured dialect with whatever the export asks to do differently.
+fn export_dialect(mut dialect: CsvDialect, params: &ExportParams) -> Result<CsvDialect, AppError> {
+    if let Some(delimiter) = &params.delimiter {
+        dialect.delimiter = CsvDialect::parse_delimiter(delimiter)?;
+    }
+    if let Some(quote) = &params.quote {
+        dialect.quote_style = quote.parse()?;
+    }
+    if let Some(line_ending) = &params.line_ending {
+        dialect.line_ending = line_ending.parse()?;
+    }
+    if let Some(bom) = params.bom {
+        dialect.bom = bom;
+    }
+    if let Some(timestamp_format) = &params.timestamp_format {
+        dialect.timestamp_format = timestamp_format.parse()?;
+    }
+    Ok(dialect)
+}
+
+fn encode_csv(dialect: &CsvDialect, records: impl IntoIterator<Item = Vec<String>>) -> Bytes {
+    let mut writer = dialect.writer_builder().from_writer(Vec::new());
+    for record in records {
+        // Writing into a Vec cannot fail
+        writer.write_record(&record).expect("in-memory CSV write");
+    }
+    Bytes::from(writer.into_inner().expect("in-memory CSV flush"))
+}
+
+/// Whose logs the caller sees when they don't ask for a specific user.
+pub(crate) async fn visible_usernames(
+    state: &AppState,
+    user: &AuthUser,
+) -> Result<Option<Vec<Username>>, AppError> {
+    match user.role {
+        Role::Admin => Ok(None),
+        Role::Observer => {
+            let mut usernames = state
+                .repo
+                .list_assigned_participants(&user.username)
+                .await
+                .map_err(|err| AppError::internal("Failed to list participants", err))?;
+            usernames.push(user.username.clone());
+            Ok(Some(usernames))
+        }
+        Role::Participant => Ok(Some(vec![user.username.clone()])),
+    }
+}
@@ -1 +1,9 @@
-pub mod state_handlers;
\ No newline at end of file
+pub mod admin_handlers;
+pub mod auth_handlers;
+pub mod error;
+pub mod health_handlers;
+pub mod log_handlers;
+pub mod session_handlers;
+pub mod site_handlers;
+pub mod state_handlers;
+pub mod ws_handlers;
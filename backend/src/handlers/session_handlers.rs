@@ -0,0 +1,153 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    Json,
+};
+use chrono::Utc;
+use std::sync::Arc;
+
+use crate::{
+    auth::{extractor::AuthUser, permissions::ensure_can_access},
+    handlers::{
+        error::{AppError, JsonBody, PathParams, QueryParams},
+        log_handlers::visible_usernames,
+    },
+    models::{
+        app_state::AppState,
+        recording_session::{RecordingSession, SessionListParams, StartSession},
+        user_state::DataLog,
+        username::Username,
+    },
+};
+
+/// `POST /api/sessions`: start recording for the caller, or for a participant they code for.
+/// The server samples the user's state from then on until the session is stopped.
+pub async fn start_session(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    JsonBody(request): JsonBody<StartSession>,
+) -> Result<(StatusCode, Json<RecordingSession>), AppError> {
+    let username = request.username.unwrap_or_else(|| user.username.clone());
+    ensure_can_access(&state, &user, &username).await?;
+
+    let label = request
+        .label
+        .as_deref()
+        .map(str::trim)
+        .filter(|label| !label.is_empty());
+    let started = state
+        .repo
+        .start_recording_session(&username, label, &Utc::now().to_rfc3339())
+        .await
+        .map_err(|err| AppError::internal("Failed to start session", err))?;
+
+    match started {
+        Some(session) => {
+            // Should taking the lease fail, another server adopts the session once it
+            // notices nobody holds it
+            let sampling = state
+                .recorder
+                .start(Arc::clone(&state), &session.username, session.id)
+                .await;
+            if let Err(err) = sampling {
+                tracing::warn!("Failed to start sampling session {}: {}", session.id, err);
+            }
+            state.live.notify();
+            Ok((StatusCode::CREATED, Json(session)))
+        }
+        None => Err(AppError::Conflict("A recording session is already open for this user".into())),
+    }
+}
+
+/// `POST /api/sessions/{id}/stop`: close an open session.
+pub async fn stop_session(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    PathParams(id): PathParams<i64>,
+) -> Result<Json<RecordingSession>, AppError> {
+    let session = find_session(&state, &user, id).await?;
+    if session.ended_at.is_some() {
+        return Err(AppError::Conflict("Session has already stopped".into()));
+    }
+
+    // Another server sampling the session drops its samples once it has ended, and the
+    // end time is moved past any it saved first, so no sample lands after it
+    state.recorder.stop(&session.username).await;
+
+    let stopped = state
+        .repo
+        .stop_recording_session(id, &Utc::now().to_rfc3339())
+        .await
+        .map_err(|err| AppError::internal("Failed to stop session", err))?
+        .ok_or_else(|| AppError::Conflict("Session has already stopped".into()))?;
+    let (csv, username) = (Arc::clone(&state.csv), stopped.username.clone());
+    match tokio::task::spawn_blocking(move || csv.end_session(&username, id)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => tracing::warn!("Failed to close the CSV file of session {}: {}", id, err),
+        Err(err) => tracing::warn!("Failed to close the CSV file of session {}: {}", id, err),
+    }
+    state.live.notify();
+    Ok(Json(stopped))
+}
+
+/// `GET /api/sessions`: sessions the caller may see, newest first.
+pub async fn list_sessions(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    QueryParams(params): QueryParams<SessionListParams>,
+) -> Result<Json<Vec<RecordingSession>>, AppError> {
+    let usernames = match params.username {
+        Some(username) => {
+            let username = Username::parse(&username)?;
+            ensure_can_access(&state, &user, &username).await?;
+            Some(vec![username])
+        }
+        None => visible_usernames(&state, &user).await?,
+    };
+
+    state
+        .repo
+        .list_recording_sessions(usernames.as_deref())
+        .await
+        .map(Json)
+        .map_err(|err| AppError::internal("Failed to list sessions", err))
+}
+
+pub async fn get_session(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    PathParams(id): PathParams<i64>,
+) -> Result<Json<RecordingSession>, AppError> {
+    find_session(&state, &user, id).await.map(Json)
+}
+
+/// `GET /api/sessions/{id}/samples`: everything recorded during one session, oldest first.
+pub async fn get_session_samples(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    PathParams(id): PathParams<i64>,
+) -> Result<Json<Vec<DataLog>>, AppError> {
+    find_session(&state, &user, id).await?;
+
+    state
+        .repo
+        .session_logs(id)
+        .await
+        .map(Json)
+        .map_err(|err| AppError::internal("Failed to load samples", err))
+}
+
+async fn find_session(
+    state: &AppState,
+    user: &AuthUser,
+    id: i64,
+) -> Result<RecordingSession, AppError> {
+    let session = state
+        .repo
+        .get_recording_session(id)
+        .await
+        .map_err(|err| AppError::internal("Failed to load session", err))?
+        .ok_or_else(|| AppError::NotFound("Session not found".into()))?;
+    ensure_can_access(state, user, &session.username).await?;
+    Ok(session)
+}
@@ -0,0 +1,68 @@
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+
+use crate::site::{asset_path, names_file, Asset, Site, INDEX, PKG_DIR};
+
+/// For files whose names don't change from one build to the next: kept, but checked with
+/// the server before every use.
+const REVALIDATE: &str = "no-cache";
+
+/// For the rest of the site root (icons, images, ...), which rarely changes.
+const CACHE_AN_HOUR: &str = "public, max-age=3600";
+
+/// `GET` on anything no other route took: the file of the site root the path names, or
+/// `index.html` for the pages the frontend routes itself. Paths that name a file that
+/// isn't there are 404, so a stale script tag doesn't get HTML back.
+pub async fn serve_site(State(site): State<Arc<Site>>, uri: Uri, headers: HeaderMap) -> Response {
+    let path = uri.path();
+    if let Some(file) = asset_path(path)
+        && let Some(asset) = site.load(&file).await
+    {
+        return asset_response(&file, asset, &headers);
+    }
+    if names_file(path) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    match site.load(INDEX).await {
+        Some(index) => asset_response(INDEX, index, &headers),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+fn asset_response(file: &str, asset: Asset, request_headers: &HeaderMap) -> Response {
+    let cache_control = if file.ends_with(".html") || file.starts_with(&format!("{}/", PKG_DIR)) {
+        REVALIDATE
+    } else {
+        CACHE_AN_HOUR
+    };
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(cache_control));
+    if let Ok(etag) = HeaderValue::from_str(&asset.etag) {
+        headers.insert(header::ETAG, etag);
+    }
+
+    if matches_etag(request_headers, &asset.etag) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+    let content_type = mime_guess::from_path(file).first_or_octet_stream();
+    if let Ok(content_type) = HeaderValue::from_str(content_type.as_ref()) {
+        headers.insert(header::CONTENT_TYPE, content_type);
+    }
+    (headers, Body::from(asset.contents)).into_response()
+}
+
+/// Whether the browser's `If-None-Match` names the copy it would get anyway.
+fn matches_etag(request_headers: &HeaderMap, etag: &str) -> bool {
+    request_headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
@@ -1,120 +1,112 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::State,
     Json,
 };
-use chrono::{DateTime, Utc};
 use std::sync::Arc;
 
-use crate::models::{app_state::AppState, user_state::UserState};
+use crate::{
+    auth::{extractor::AuthUser, permissions::ensure_can_access},
+    handlers::error::{AppError, JsonBody, PathParams},
+    models::{
+        app_state::AppState,
+        codebook::Codebook,
+        user::Role,
+        user_state::UserState,
+        username::Username,
+    },
+};
 
 pub async fn get_user_state(
     State(state): State<Arc<AppState>>,
-    Path(username): Path<String>,
-) -> Result<Json<UserState>, StatusCode> {
-    let result = sqlx::query_as::<_, UserState>(
-        r#"
-        SELECT * FROM user_states
-        WHERE username = ?
-        "#,
-    )
-    .bind(&username)
-    .fetch_optional(&state.db)
-    .await;
-    
-    match result {
-        Ok(Some(user_state)) => Ok(Json(user_state)),
-        Ok(None) => Err(StatusCode::NOT_FOUND),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+    user: AuthUser,
+) -> Result<Json<UserState>, AppError> {
+    load_state(&state, &user.username).await
 }
 
 pub async fn update_user_state(
     State(state): State<Arc<AppState>>,
-    Json(user_state): Json<UserState>,
-) -> StatusCode {
-    // Update the user state in the database
-    let result = sqlx::query(
-        r#"
-        INSERT INTO user_states (
-            username, text_entry, category1, category2, category3, category4,
-            is_recording, last_saved, last_data
-        ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
-        ON CONFLICT(username) DO UPDATE SET
-            text_entry = excluded.text_entry,
-            category1 = excluded.category1,
-            category2 = excluded.category2,
-            category3 = excluded.category3,
-            category4 = excluded.category4,
-            is_recording = excluded.is_recording,
-            last_saved = excluded.last_saved,
-            last_data = excluded.last_data
-        "#,
-    )
-    .bind(&user_state.username)
-    .bind(&user_state.text_entry)
-    .bind(&user_state.category1)
-    .bind(&user_state.category2)
-    .bind(&user_state.category3)
-    .bind(&user_state.category4)
-    .bind(&user_state.is_recording)
-    .bind(&user_state.last_saved)
-    .bind(&user_state.last_data)
-    .execute(&state.db)
-    .await;
-    
-    // If we're recording, log the data
-    if user_state.is_recording {
-        // Get the current timestamp
-        let now: DateTime<Utc> = Utc::now();
-        let timestamp = now.to_rfc3339();
-        
-        // Log to the database
-        let log_result = sqlx::query(
-            r#"
-            INSERT INTO data_logs (
-                username, text_entry, category1, category2, category3, category4, timestamp
-            ) VALUES (?, ?, ?, ?, ?, ?, ?)
-            "#,
-        )
-        .bind(&user_state.username)
-        .bind(&user_state.text_entry)
-        .bind(&user_state.category1)
-        .bind(&user_state.category2)
-        .bind(&user_state.category3)
-        .bind(&user_state.category4)
-        .bind(&timestamp)
-        .execute(&state.db)
-        .await;
-        
-        if log_result.is_err() {
-            return StatusCode::INTERNAL_SERVER_ERROR;
-        }
-        
-        // Log to CSV file
-        match state.get_csv_writer(&user_state.username).await {
-            Ok(writer_mutex) => {
-                let mut writer = writer_mutex.lock().await;
-                let record_result = writer.write_record(&[
-                    &user_state.username,
-                    &user_state.text_entry,
-                    &user_state.category1,
-                    &user_state.category2,
-                    &user_state.category3,
-                    &user_state.category4,
-                    &timestamp,
-                ]);
-                
-                if let Err(_) = record_result.and_then(|_| Ok(writer.flush())) {
-                    return StatusCode::INTERNAL_SERVER_ERROR;
-                }
-            }
-            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
-        }
-    }
-    
-    match result {
-        Ok(_) => StatusCode::OK,
-        Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    user: AuthUser,
+    JsonBody(mut user_state): JsonBody<UserState>,
+) -> Result<Json<UserState>, AppError> {
+    // The session decides whose state this is, whatever the body claims
+    user_state.username = user.username;
+    save_state(&state, user_state).await
+}
+
+/// Reads the state of a participant on their behalf (observers and admins).
+pub async fn get_participant_state(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    PathParams(username): PathParams<String>,
+) -> Result<Json<UserState>, AppError> {
+    let username = Username::parse(&username)?;
+    ensure_can_access(&state, &user, &username).await?;
+    load_state(&state, &username).await
+}
+
+/// Codes on behalf of a participant (observers and admins).
+pub async fn update_participant_state(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+    PathParams(username): PathParams<String>,
+    JsonBody(mut user_state): JsonBody<UserState>,
+) -> Result<Json<UserState>, AppError> {
+    let username = Username::parse(&username)?;
+    ensure_can_access(&state, &user, &username).await?;
+    user_state.username = username;
+    save_state(&state, user_state).await
+}
+
+/// The codebook the data entry screen renders its fields from.
+pub async fn get_codebook(State(state): State<Arc<AppState>>, _user: AuthUser) -> Json<Codebook> {
+    Json(state.codebook.as_ref().clone())
+}
+
+/// Lists the participants the caller may code for.
+pub async fn list_participants(
+    State(state): State<Arc<AppState>>,
+    user: AuthUser,
+) -> Result<Json<Vec<Username>>, AppError> {
+    let result = match user.role {
+        Role::Admin => state.repo.list_participants().await,
+        _ => state.repo.list_assigned_participants(&user.username).await,
+    };
+
+    result
+        .map(Json)
+        .map_err(|err| AppError::internal("Failed to list participants", err))
+}
+
+async fn load_state(state: &AppState, username: &Username) -> Result<Json<UserState>, AppError> {
+    match state.repo.get_user_state(username).await {
+        Ok(Some(user_state)) => Ok(Json(user_state)),
+        Ok(None) => Err(AppError::NotFound("No saved state for this user".into())),
+        Err(err) => Err(AppError::internal("Failed to load state", err)),
     }
-}
\ No newline at end of file
+}
+
+async fn save_state(state: &AppState, user_state: UserState) -> Result<Json<UserState>, AppError> {
+    store_state(state, user_state).await.map(Json)
+}
+
+/// Stores the latest state pushed by a client. Samples are taken from it by the
+/// recorder, so only the entry and categories are written; the recording flag and the
+/// latest sample stay whatever the session endpoints and the recorder last set.
+async fn store_state(
+    state: &AppState,
+    user_state: UserState,
+) -> Result<UserState, AppError> {
+    state
+        .codebook
+        .check_values(&user_state.categories)
+        .map_err(AppError::Unprocessable)?;
+
+    let stored = state
+        .repo
+        .save_entry(&user_state.username, &user_state.text_entry, &user_state.categories)
+        .await
+        .map_err(|err| AppError::internal("Failed to save state", err))?;
+    state.live.notify();
+
+    Ok(stored)
+}
@@ -0,0 +1,202 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::Response,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tokio::sync::watch;
+
+use crate::{
+    auth::{extractor::AuthUser, permissions::ensure_can_access},
+    handlers::error::{AppError, QueryParams},
+    live,
+    models::{
+        app_state::AppState,
+        user::Role,
+        user_state::UserState,
+        username::Username,
+        ws_message::{ClientMessage, ServerMessage},
+    },
+};
+
+/// How long live dashboard pushes wait for related changes to land together.
+const LIVE_SETTLE: Duration = Duration::from_millis(250);
+
+/// Browsers can't set headers on a WebSocket handshake, so the token rides in the query.
+#[derive(Debug, Deserialize)]
+pub struct WsParams {
+    pub token: String,
+}
+
+/// `GET /api/ws`: a socket over which clients push field changes as they happen and
+/// get back acknowledgements stamped with server time.
+pub async fn ws_connect(
+    State(state): State<Arc<AppState>>,
+    QueryParams(params): QueryParams<WsParams>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, AppError> {
+    let user = AuthUser::from_token(&state, &params.token).await?;
+    Ok(ws.on_upgrade(move |socket| serve_socket(state, user, socket)))
+}
+
+async fn serve_socket(state: Arc<AppState>, mut user: AuthUser, mut socket: WebSocket) {
+    // Set once the client subscribes to the live dashboard feed
+    let mut live_updates: Option<watch::Receiver<u64>> = None;
+
+    loop {
+        let text = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => text,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            changed = live_changed(&mut live_updates) => {
+                if changed.is_err() {
+                    live_updates = None;
+                    continue;
+                }
+                // Let a burst of changes settle into one snapshot
+                tokio::time::sleep(LIVE_SETTLE).await;
+                if let Some(updates) = &mut live_updates {
+                    updates.borrow_and_update();
+                }
+                // Nothing is pushed after a logout or a demotion, however long ago the
+                // client subscribed
+                let error = match user.current_role(&state).await {
+                    Some(Role::Admin) => None,
+                    Some(_) => Some("Only admins can watch live recordings"),
+                    None => Some("Session is invalid or has expired"),
+                };
+                if let Some(error) = error {
+                    let reply = ServerMessage::Error {
+                        seq: None,
+                        error: error.to_string(),
+                    };
+                    let _ = send(&mut socket, &reply).await;
+                    break;
+                }
+                if send(&mut socket, &live_message(&state).await).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        // Logging out elsewhere ends the socket too, and role changes apply from the
+        // next message on
+        match user.current_role(&state).await {
+            Some(role) => user.role = role,
+            None => {
+                let reply = ServerMessage::Error {
+                    seq: None,
+                    error: "Session is invalid or has expired".to_string(),
+                };
+                let _ = send(&mut socket, &reply).await;
+                break;
+            }
+        }
+
+        let reply = match serde_json::from_str::<ClientMessage>(text.as_str()) {
+            Ok(ClientMessage::Subscribe) if user.role != Role::Admin => ServerMessage::Error {
+                seq: None,
+                error: "Only admins can watch live recordings".to_string(),
+            },
+            Ok(ClientMessage::Subscribe) => {
+                let mut updates = state.live.subscribe();
+                updates.borrow_and_update();
+                live_updates = Some(updates);
+                live_message(&state).await
+            }
+            Ok(message) => handle_message(&state, &user, message).await,
+            Err(err) => ServerMessage::Error {
+                seq: None,
+                error: format!("Invalid message: {}", err),
+            },
+        };
+        if send(&mut socket, &reply).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Resolves when the live feed changes; never, if the client hasn't subscribed.
+async fn live_changed(
+    updates: &mut Option<watch::Receiver<u64>>,
+) -> Result<(), watch::error::RecvError> {
+    match updates {
+        Some(updates) => updates.changed().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn live_message(state: &AppState) -> ServerMessage {
+    match live::snapshot(state.repo.as_ref()).await {
+        Ok(snapshot) => ServerMessage::Live(snapshot),
+        Err(_) => ServerMessage::Error {
+            seq: None,
+            error: "Failed to load recordings".to_string(),
+        },
+    }
+}
+
+async fn handle_message(state: &AppState, user: &AuthUser, message: ClientMessage) -> ServerMessage {
+    match message {
+        ClientMessage::Update {
+            seq,
+            username,
+            field,
+            value,
+        } => {
+            let username = username.unwrap_or_else(|| user.username.clone());
+            match apply_update(state, user, &username, &field, value).await {
+                Ok(saved) => ServerMessage::Ack {
+                    seq,
+                    saved_at: Utc::now().to_rfc3339(),
+                    state: saved,
+                },
+                Err(err) => ServerMessage::Error {
+                    seq: Some(seq),
+                    error: err.to_string(),
+                },
+            }
+        }
+        ClientMessage::Subscribe => live_message(state).await,
+    }
+}
+
+/// Changes one field of `username`'s stored state, with the same checks as `POST /api/state`.
+/// Only that field is written, so updates to different fields racing each other all land.
+async fn apply_update(
+    state: &AppState,
+    user: &AuthUser,
+    username: &Username,
+    field: &str,
+    value: String,
+) -> Result<UserState, AppError> {
+    ensure_can_access(state, user, username).await?;
+
+    let saved = if field == "text_entry" {
+        state.repo.save_text_entry(username, &value).await
+    } else if state.codebook.fields.iter().any(|known| known.key == field) {
+        state
+            .codebook
+            .check_values(&BTreeMap::from([(field.to_string(), value.clone())]))
+            .map_err(AppError::Unprocessable)?;
+        state.repo.save_category(username, field, &value).await
+    } else {
+        return Err(AppError::Unprocessable(format!("Unknown field '{}'", field)));
+    };
+    let saved = saved.map_err(|err| AppError::internal("Failed to save state", err))?;
+    state.live.notify();
+
+    Ok(saved)
+}
+
+async fn send(socket: &mut WebSocket, message: &ServerMessage) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(message).expect("server messages serialize");
+    socket.send(Message::Text(text.into())).await
+}
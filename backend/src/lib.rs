@@ -0,0 +1,14 @@
+pub mod auth;
+pub mod config;
+pub mod csv;
+pub mod db;
+pub mod handlers;
+pub mod live;
+pub mod log_writer;
+pub mod metrics;
+pub mod models;
+pub mod reconcile;
+pub mod recorder;
+pub mod request_id;
+pub mod routes;
+pub mod site;
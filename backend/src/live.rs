@@ -0,0 +1,41 @@
+//! Change notifications for the live monitoring dashboard.
+//!
+//! Anything that changes what a recording user looks like (a pushed state, a sample,
+//! a session starting or stopping) bumps the feed. Subscribers re-read the current
+//! picture when woken, so bursts of changes collapse into one refresh.
+
+use chrono::Utc;
+use tokio::sync::watch;
+
+use crate::{db::repository::StateRepository, models::live::LiveSnapshot};
+
+pub struct LiveFeed {
+    version: watch::Sender<u64>,
+}
+
+impl Default for LiveFeed {
+    fn default() -> Self {
+        Self {
+            version: watch::Sender::new(0),
+        }
+    }
+}
+
+impl LiveFeed {
+    pub fn notify(&self) {
+        self.version.send_modify(|version| *version = version.wrapping_add(1));
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.version.subscribe()
+    }
+}
+
+/// Everyone recording right now.
+pub async fn snapshot(repo: &dyn StateRepository) -> Result<LiveSnapshot, sqlx::Error> {
+    let recordings = repo.live_recordings().await?;
+    Ok(LiveSnapshot {
+        generated_at: Utc::now().to_rfc3339(),
+        recordings,
+    })
+}
@@ -0,0 +1,419 @@
+//! Single writer for samples.
+//!
+//! Every sample goes through one task that owns the writes to `data_logs` and the CSV
+//! files. Samples queued while it is busy are written as a batch: their rows are
+//! inserted in one transaction and appended to the CSV files before one flush, so
+//! many recorders share a round of I/O instead of each paying for their own. Callers
+//! are answered once their sample is as durable as the `SyncPolicy` asks.
+//!
+//! The database is the record of truth. Each sample's `data_logs` row, the user's latest
+//! sample and a `csv_outbox` entry are committed together, and the outbox entry is only
+//! cleared once the CSV row has reached its file, which under `SyncPolicy::Buffered` is
+//! when the writer next flushes the files. Rows left behind by a failed write or a crash are
+//! written from the outbox later, so the CSV files catch up instead of silently falling
+//! behind; `backend reconcile` checks and repairs whatever is left, and `backend rebuild`
+//! rewrites a user's files from the database.
+
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    csv::writer::{CsvExporter, CsvRow, UserLock},
+    db::repository::StateRepository,
+    models::{
+        codebook::Codebook,
+        user_state::{DataLog, Sample},
+        username::Username,
+    },
+};
+
+/// Most samples written in one batch unless `LOG_MAX_BATCH` says otherwise.
+pub const DEFAULT_MAX_BATCH: usize = 256;
+
+#[derive(Debug, Error)]
+pub enum LogWriterSettingsError {
+    #[error("invalid LOG_SYNC '{0}' (expected buffered, flush or fsync)")]
+    Sync(String),
+    #[error("invalid LOG_MAX_BATCH '{0}' (expected a whole number above zero)")]
+    MaxBatch(String),
+}
+
+/// How far a batch's CSV rows get before the samples in it are acknowledged. Database
+/// rows are always committed first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Rows may wait in the writer's buffer until it fills up, the file is closed or
+    /// `max_batch` more rows have been written; until then they stay in the outbox
+    Buffered,
+    /// Rows are handed to the operating system
+    #[default]
+    Flush,
+    /// Rows are on disk
+    Fsync,
+}
+
+impl FromStr for SyncPolicy {
+    type Err = LogWriterSettingsError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "buffered" | "none" => Ok(SyncPolicy::Buffered),
+            "" | "flush" => Ok(SyncPolicy::Flush),
+            "fsync" | "sync" => Ok(SyncPolicy::Fsync),
+            _ => Err(LogWriterSettingsError::Sync(raw.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LogWriterSettings {
+    pub sync: SyncPolicy,
+    pub max_batch: usize,
+}
+
+impl Default for LogWriterSettings {
+    fn default() -> Self {
+        Self {
+            sync: SyncPolicy::default(),
+            max_batch: DEFAULT_MAX_BATCH,
+        }
+    }
+}
+
+impl LogWriterSettings {
+    /// Reads `LOG_SYNC` and `LOG_MAX_BATCH`.
+    pub fn from_env() -> Result<Self, LogWriterSettingsError> {
+        let defaults = Self::default();
+        let sync = match std::env::var("LOG_SYNC") {
+            Ok(raw) => raw.parse()?,
+            Err(_) => defaults.sync,
+        };
+        let max_batch = match std::env::var("LOG_MAX_BATCH") {
+            Ok(raw) => match raw.trim().parse::<usize>() {
+                Ok(max_batch) if max_batch > 0 => max_batch,
+                _ => return Err(LogWriterSettingsError::MaxBatch(raw)),
+            },
+            Err(_) => defaults.max_batch,
+        };
+        Ok(Self { sync, max_batch })
+    }
+}
+
+/// Why a sample wasn't written. Shared by every sample of a batch that failed together.
+#[derive(Clone, Debug, Error)]
+pub enum WriteError {
+    #[error("database error: {0}")]
+    Database(Arc<sqlx::Error>),
+    #[error("the log writer has stopped")]
+    Stopped,
+}
+
+/// How much the writer has done since the server started.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LogWriterStats {
+    pub batches: u64,
+    pub samples: u64,
+}
+
+enum Command {
+    Write(Sample, oneshot::Sender<Result<(), WriteError>>),
+    Flush(oneshot::Sender<()>),
+}
+
+#[derive(Default)]
+struct Counters {
+    batches: AtomicU64,
+    samples: AtomicU64,
+}
+
+/// Handle to the writer task. The task stops once every handle is dropped.
+pub struct LogWriter {
+    queue: mpsc::Sender<Command>,
+    counters: Arc<Counters>,
+}
+
+impl LogWriter {
+    /// Starts the writer task on the current runtime. CSV rows a previous run left in the
+    /// outbox are written by the first [`LogWriter::flush`], which the server awaits
+    /// before it records anything; a command run next to the server leaves them to it.
+    pub fn spawn(
+        repo: Arc<dyn StateRepository>,
+        csv: Arc<CsvExporter>,
+        codebook: Arc<Codebook>,
+        settings: LogWriterSettings,
+    ) -> Self {
+        let (queue, commands) = mpsc::channel(settings.max_batch * 4);
+        let counters = Arc::new(Counters::default());
+        let worker = Worker {
+            repo,
+            csv,
+            codebook,
+            settings,
+            counters: Arc::clone(&counters),
+            outbox_pending: false,
+            unflushed: Vec::new(),
+        };
+        tokio::spawn(worker.run(commands));
+        Self { queue, counters }
+    }
+
+    /// Queues `sample` and waits until it has been written. Once its database rows are
+    /// committed the sample counts as written; a CSV row that fails is retried from the
+    /// outbox.
+    pub async fn write(&self, sample: Sample) -> Result<(), WriteError> {
+        let (done, written) = oneshot::channel();
+        self.queue
+            .send(Command::Write(sample, done))
+            .await
+            .map_err(|_| WriteError::Stopped)?;
+        written.await.unwrap_or(Err(WriteError::Stopped))
+    }
+
+    /// Waits until everything queued so far, and whatever the outbox owes, is written and
+    /// every CSV file is flushed.
+    pub async fn flush(&self) -> Result<(), WriteError> {
+        let (done, flushed) = oneshot::channel();
+        self.queue
+            .send(Command::Flush(done))
+            .await
+            .map_err(|_| WriteError::Stopped)?;
+        flushed.await.map_err(|_| WriteError::Stopped)
+    }
+
+    pub fn stats(&self) -> LogWriterStats {
+        LogWriterStats {
+            batches: self.counters.batches.load(Ordering::Relaxed),
+            samples: self.counters.samples.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct Worker {
+    repo: Arc<dyn StateRepository>,
+    csv: Arc<CsvExporter>,
+    codebook: Arc<Codebook>,
+    settings: LogWriterSettings,
+    counters: Arc<Counters>,
+    /// Whether the outbox may hold rows that still need writing
+    outbox_pending: bool,
+    /// Logs whose CSV rows are appended but may still sit in a buffer, so their outbox
+    /// entries stay until the files are flushed
+    unflushed: Vec<i64>,
+}
+
+impl Worker {
+    async fn run(mut self, mut commands: mpsc::Receiver<Command>) {
+        let mut received = Vec::with_capacity(self.settings.max_batch);
+        let mut batch = Vec::with_capacity(self.settings.max_batch);
+        loop {
+            if self.outbox_pending {
+                self.replay_outbox().await;
+            }
+            if commands.recv_many(&mut received, self.settings.max_batch).await == 0 {
+                break;
+            }
+            for command in received.drain(..) {
+                match command {
+                    Command::Write(sample, done) => batch.push((sample, done)),
+                    Command::Flush(done) => {
+                        self.write_batch(std::mem::take(&mut batch)).await;
+                        // Also picks up rows another process left in the outbox
+                        self.replay_outbox().await;
+                        let csv = Arc::clone(&self.csv);
+                        let fsync = self.settings.sync == SyncPolicy::Fsync;
+                        match tokio::task::spawn_blocking(move || csv.flush_all(fsync)).await {
+                            Ok(Ok(())) => {}
+                            Ok(Err(err)) => tracing::warn!("Failed to flush CSV files: {}", err),
+                            Err(err) => tracing::warn!("Failed to flush CSV files: {}", err),
+                        }
+                        let _ = done.send(());
+                    }
+                }
+            }
+            self.write_batch(std::mem::take(&mut batch)).await;
+        }
+    }
+
+    async fn write_batch(&mut self, batch: Vec<(Sample, oneshot::Sender<Result<(), WriteError>>)>) {
+        if batch.is_empty() {
+            return;
+        }
+        let (samples, done): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+        let size = samples.len() as u64;
+
+        let result = match self.repo.record_samples(&samples).await {
+            // While older rows wait in the outbox, newer ones queue up behind them there
+            Ok(logs) if !self.outbox_pending => {
+                self.write_csv(logs, false).await;
+                Ok(())
+            }
+            Ok(_) => Ok(()),
+            Err(err) => Err(WriteError::Database(Arc::new(err))),
+        };
+        for done in done {
+            let _ = done.send(result.clone());
+        }
+
+        self.counters.batches.fetch_add(1, Ordering::Relaxed);
+        self.counters.samples.fetch_add(size, Ordering::Relaxed);
+        tracing::debug!("Wrote a batch of {} sample(s)", size);
+    }
+
+    /// Writes whatever CSV rows are still owed, a batch at a time.
+    async fn replay_outbox(&mut self) {
+        // Buffered rows are in the outbox too; replaying them would write them twice
+        if !self.flush_buffered().await {
+            return;
+        }
+        loop {
+            let logs = match self.repo.csv_outbox(self.settings.max_batch as u32).await {
+                Ok(logs) => logs,
+                Err(err) => {
+                    tracing::warn!("Failed to read the CSV outbox: {}", err);
+                    return;
+                }
+            };
+            if logs.is_empty() {
+                self.outbox_pending = false;
+                return;
+            }
+            tracing::info!("Writing {} CSV row(s) left in the outbox", logs.len());
+            if !self.write_csv(logs, true).await {
+                // Left for the next batch to try again
+                return;
+            }
+        }
+    }
+
+    /// Appends `logs` to the CSV files and clears them from the outbox once they reached
+    /// the files. With `replay`, rows a file already ends with are skipped: a crash may
+    /// have come between writing them and clearing them. Returns whether every row made it.
+    async fn write_csv(&mut self, logs: Vec<DataLog>, replay: bool) -> bool {
+        let csv = Arc::clone(&self.csv);
+        let codebook = Arc::clone(&self.codebook);
+        let sync = self.settings.sync;
+        let appended =
+            tokio::task::spawn_blocking(move || append_rows(&csv, &codebook, sync, &logs, replay))
+                .await;
+        let (written, complete) = match appended {
+            Ok(appended) => appended,
+            Err(err) => {
+                tracing::warn!("CSV writes stopped: {}", err);
+                (Vec::new(), false)
+            }
+        };
+
+        if sync == SyncPolicy::Buffered && !replay {
+            self.unflushed.extend(written);
+            if self.unflushed.len() >= self.settings.max_batch {
+                self.flush_buffered().await;
+            }
+        } else if let Err(err) = self.repo.clear_csv_outbox(&written).await {
+            // The rows are written; a replay would find them in place and skip them
+            tracing::warn!("Failed to clear the CSV outbox: {}", err);
+        }
+        if !complete {
+            self.outbox_pending = true;
+        }
+        complete
+    }
+
+    /// Flushes the CSV files so buffered rows reach them, then clears those rows from the
+    /// outbox. Returns whether none are left waiting.
+    async fn flush_buffered(&mut self) -> bool {
+        if self.unflushed.is_empty() {
+            return true;
+        }
+        let csv = Arc::clone(&self.csv);
+        match tokio::task::spawn_blocking(move || csv.flush_all(false)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => {
+                tracing::warn!("Failed to flush CSV files: {}", err);
+                return false;
+            }
+            Err(err) => {
+                tracing::warn!("Failed to flush CSV files: {}", err);
+                return false;
+            }
+        }
+        if let Err(err) = self.repo.clear_csv_outbox(&self.unflushed).await {
+            tracing::warn!("Failed to clear the CSV outbox: {}", err);
+        }
+        // Cleared or not, the rows are in their files now, where a replay would skip them
+        self.unflushed.clear();
+        true
+    }
+}
+
+/// Appends `logs` to their users' files, flushing as `sync` asks; rows being replayed
+/// are flushed whatever it says. Returns the ids of the logs whose rows are written, and
+/// whether that is all of them.
+fn append_rows(
+    csv: &CsvExporter,
+    codebook: &Codebook,
+    sync: SyncPolicy,
+    logs: &[DataLog],
+    replay: bool,
+) -> (Vec<i64>, bool) {
+    let mut locks: HashMap<&Username, UserLock> = HashMap::new();
+    let mut failed: HashSet<&Username> = HashSet::new();
+    let mut written = Vec::with_capacity(logs.len());
+
+    for log in logs {
+        let username = &log.username;
+        // Rows stay in order: once one of a user's rows fails, the rest wait too
+        if failed.contains(username) {
+            continue;
+        }
+        // Held to the end of the batch, so a rebuild never sees half of it
+        if !locks.contains_key(username) {
+            let locked = csv.lock_user(username).and_then(|lock| {
+                if replay {
+                    csv.skip_written(username)?;
+                }
+                Ok(lock)
+            });
+            match locked {
+                Ok(lock) => {
+                    locks.insert(username, lock);
+                }
+                Err(err) => {
+                    tracing::warn!("Failed to open the CSV files of {}: {}", username, err);
+                    failed.insert(username);
+                    continue;
+                }
+            }
+        }
+
+        match csv.append(username, &CsvRow::from_log(log, codebook)) {
+            Ok(()) => written.extend(log.id),
+            Err(err) => {
+                tracing::warn!("Failed to write the CSV row of {}: {}", username, err);
+                failed.insert(username);
+            }
+        }
+    }
+
+    // One flush per file, however many of its rows the batch held
+    if sync != SyncPolicy::Buffered || replay {
+        let users: HashSet<&Username> = logs.iter().map(|log| &log.username).collect();
+        for username in users {
+            if let Err(err) = csv.flush([username], sync == SyncPolicy::Fsync) {
+                tracing::warn!("Failed to flush the CSV file of {}: {}", username, err);
+                failed.insert(username);
+            }
+        }
+        let owners: HashMap<i64, &Username> =
+            logs.iter().filter_map(|log| Some((log.id?, &log.username))).collect();
+        written.retain(|id| owners.get(id).is_none_or(|username| !failed.contains(username)));
+    }
+    (written, failed.is_empty())
+}
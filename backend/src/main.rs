@@ -1,47 +1,273 @@
-mod models;
-mod handlers;
-mod db;
-mod csv;
-
-use axum::{
-    http::Method,
-    routing::{get, post},
-    Router,
+use axum::http::Method;
+use axum_backend::{
+    config::{Config, ConfigLayer, CorsOrigins},
+    models::{app_state::AppState, username::Username},
+    reconcile::{rebuild_all, rebuild_user, reconcile_user, CsvRebuild},
+    routes::{api_routes, site_routes},
+    site::Site,
 };
-use handlers::state_handlers::{get_user_state, update_user_state};
-use models::app_state::AppState;
-use std::{net::SocketAddr, sync::Arc};
-use tower_http::cors::{Any, CorsLayer};
+use clap::{Parser, Subcommand};
+use std::{path::PathBuf, process::ExitCode, sync::Arc, time::Duration};
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
 use tokio::net::TcpListener;
 
+#[derive(Parser)]
+#[command(version, about = "Backend for recording coded observations")]
+struct Cli {
+    /// TOML file of settings [default: backend.toml, if it exists]
+    #[arg(long, env = "BACKEND_CONFIG", value_name = "FILE", global = true)]
+    config: Option<PathBuf>,
+    #[command(flatten)]
+    settings: ConfigLayer,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the HTTP server (the default)
+    Serve,
+    /// Compare users' CSV files with the database, optionally rebuilding them from it
+    Reconcile {
+        /// Only check this user; may be given more than once
+        #[arg(long = "user", value_name = "USERNAME")]
+        users: Vec<String>,
+        /// Rebuild the CSV files of every user that disagrees with the database
+        #[arg(long)]
+        repair: bool,
+    },
+    /// Rewrite users' CSV files from the database; safe while the server runs
+    Rebuild {
+        /// Only rebuild this user's files; may be given more than once
+        #[arg(long = "user", value_name = "USERNAME")]
+        users: Vec<String>,
+    },
+}
+
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize logging
-    tracing_subscriber::fmt::init();
-    
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let config = match Config::load(cli.config.as_deref(), cli.settings) {
+        Ok(config) => config,
+        Err(err) => {
+            // Logging isn't set up yet, as the log level is part of the configuration
+            eprintln!("error: {}", err);
+            return ExitCode::from(2);
+        }
+    };
+
+    tracing_subscriber::fmt()
+        .with_env_filter(config.log_filter())
+        .init();
+
+    let result = match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(&config).await,
+        Command::Reconcile { users, repair } => reconcile(&config, users, repair).await,
+        Command::Rebuild { users } => rebuild(&config, users).await,
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            tracing::error!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn serve(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
     // Initialize app state
-    let app_state = Arc::new(AppState::new().await?);
+    let app_state = Arc::new(AppState::new(config).await?);
+    // Rows the previous run didn't get into the CSV files go before any new ones
+    app_state.log_writer.flush().await?;
+
+    // Recordings left open by the previous run keep sampling
+    let resumed = app_state
+        .recorder
+        .resume_open_sessions(Arc::clone(&app_state))
+        .await?;
+    if resumed > 0 {
+        tracing::info!("Resumed {} open recording session(s)", resumed);
+    }
+
+    // So do those of other servers on the same database that stop renewing their leases
+    let adopter_state = Arc::clone(&app_state);
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(adopter_state.recorder.lease());
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            let recorder = &adopter_state.recorder;
+            match recorder.resume_open_sessions(Arc::clone(&adopter_state)).await {
+                Ok(0) => {}
+                Ok(adopted) => tracing::info!("Adopted {} open recording session(s)", adopted),
+                Err(err) => tracing::warn!("Failed to look for unowned sessions: {}", err),
+            }
+        }
+    });
+
+    // Files nobody is writing to are closed so they don't hold on to descriptors
+    let sweeper_state = Arc::clone(&app_state);
+    let sweep_every = (app_state.csv.settings().writer_idle_timeout / 2).max(Duration::from_secs(1));
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(sweep_every);
+        loop {
+            ticks.tick().await;
+            let csv = Arc::clone(&sweeper_state.csv);
+            if let Err(err) = tokio::task::spawn_blocking(move || csv.close_idle_writers()).await {
+                tracing::warn!("Failed to close idle CSV files: {}", err);
+            }
+        }
+    });
     
     // Define CORS middleware
+    let allowed_origins = match &config.cors_origins {
+        CorsOrigins::Any => AllowOrigin::any(),
+        CorsOrigins::List(origins) => AllowOrigin::list(origins.iter().cloned()),
+    };
     let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST])
+        .allow_origin(allowed_origins)
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
         .allow_headers(Any);
     
+    // The frontend is served from the same origin as the API
+    let site = Site::from_config(config);
+    if site.has_index().await {
+        tracing::info!("Serving the frontend from {}", site);
+    } else {
+        tracing::warn!("No index.html in {}; only the API is served", site);
+    }
+
     // Define routes
-    let app = Router::new()
-        .route("/api/state/{username}", get(get_user_state))
-        .route("/api/state", post(update_user_state))
-        .layer(cors)
-        .with_state(app_state);
-    
-    // Start the server
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    tracing::info!("Server running on {}", addr);
+    let app = api_routes(Arc::clone(&app_state))
+        .merge(site_routes(site))
+        .layer(cors);
     
     // Create a TCP listener and serve the app
-    let listener = TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
-    
+    let listener = TcpListener::bind(config.bind)
+        .await
+        .map_err(|err| format!("failed to listen on {}: {}", config.bind, err))?;
+    tracing::info!("Server running on {}", config.bind);
+    // Stops accepting connections on the signal and returns once requests in flight are
+    // answered; WebSockets already upgraded don't hold it up
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+
+    tracing::info!("Shutting down");
+    app_state.shutdown().await;
+    tracing::info!("Shut down cleanly");
+    Ok(())
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM where there is one.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl-C: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// Meant to run while the server is stopped, as both would write the same files.
+async fn reconcile(
+    config: &Config,
+    users: Vec<String>,
+    repair: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let state = AppState::new(config).await?;
+    // Rows the outbox still owes are written first, so they don't count as missing
+    state.log_writer.flush().await?;
+
+    let usernames = if users.is_empty() {
+        let users = state.repo.list_users().await?;
+        users.into_iter().map(|user| user.username).collect()
+    } else {
+        users
+            .iter()
+            .map(|user| Username::parse(user))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    let mut inconsistent = 0;
+    for username in &usernames {
+        let report = reconcile_user(
+            state.repo.as_ref(),
+            &state.csv,
+            &state.codebook,
+            username,
+            repair,
+        )
+        .await?;
+        let outcome = match (report.is_consistent(), report.rebuilt) {
+            (true, _) => "ok",
+            (false, true) => "rebuilt from the database",
+            (false, false) => {
+                inconsistent += 1;
+                "out of sync"
+            }
+        };
+        println!(
+            "{}: {} samples, {} CSV rows, {} missing, {} extra, {} changed: {}",
+            username,
+            report.db_rows,
+            report.csv_rows,
+            report.missing,
+            report.extra,
+            report.changed,
+            outcome
+        );
+    }
+
+    if inconsistent > 0 {
+        return Err(format!(
+            "{} user(s) have CSV files that disagree with the database; run again with --repair to rebuild them",
+            inconsistent
+        )
+        .into());
+    }
+    Ok(())
+}
+
+async fn rebuild(config: &Config, users: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let state = AppState::new(config).await?;
+
+    let rebuilt = if users.is_empty() {
+        rebuild_all(state.repo.as_ref(), &state.csv, &state.codebook).await?
+    } else {
+        let mut rebuilt = Vec::new();
+        for user in &users {
+            let username = Username::parse(user)?;
+            let index =
+                rebuild_user(state.repo.as_ref(), &state.csv, &state.codebook, &username).await?;
+            rebuilt.push(CsvRebuild::new(username, &index));
+        }
+        rebuilt
+    };
+
+    for user in rebuilt {
+        println!("{}: {} rows in {} file(s)", user.username, user.rows, user.files);
+    }
     Ok(())
-}
\ No newline at end of file
+}
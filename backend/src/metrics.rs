@@ -0,0 +1,269 @@
+//! Request counters and a snapshot of the writers, recorders and database pool, rendered
+//! for `/metrics` in the Prometheus text format.
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::models::app_state::AppState;
+
+/// Upper bounds of the request latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// How far back the samples-per-second gauge looks, give or take a scrape interval.
+const SAMPLE_RATE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct RequestKey {
+    method: String,
+    /// The route's pattern, e.g. `/api/sessions/{id}`, so ids don't each get a series
+    route: String,
+    status: u16,
+}
+
+#[derive(Clone, Debug, Default)]
+struct RequestStats {
+    count: u64,
+    seconds: f64,
+    /// Requests that took at most `LATENCY_BUCKETS[i]`; not yet cumulative
+    buckets: [u64; LATENCY_BUCKETS.len()],
+}
+
+pub struct Metrics {
+    requests: Mutex<BTreeMap<RequestKey, RequestStats>>,
+    /// When `/metrics` saw how many samples logged so far, oldest first
+    sample_readings: Mutex<VecDeque<(Instant, u64)>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            requests: Mutex::new(BTreeMap::new()),
+            sample_readings: Mutex::new(VecDeque::from([(Instant::now(), 0)])),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn record_request(&self, method: &str, route: &str, status: StatusCode, took: Duration) {
+        let key = RequestKey {
+            method: method.to_string(),
+            route: route.to_string(),
+            status: status.as_u16(),
+        };
+        let seconds = took.as_secs_f64();
+        let mut requests = self.requests.lock().unwrap();
+        let stats = requests.entry(key).or_default();
+        stats.count += 1;
+        stats.seconds += seconds;
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            stats.buckets[bucket] += 1;
+        }
+    }
+
+    /// Samples per second since the newest reading at least a window old, or since the
+    /// server started if it hasn't run that long.
+    fn samples_per_second(&self, samples: u64) -> f64 {
+        let now = Instant::now();
+        let mut readings = self.sample_readings.lock().unwrap();
+        readings.push_back((now, samples));
+        while readings.len() > 2 && now.duration_since(readings[1].0) >= SAMPLE_RATE_WINDOW {
+            readings.pop_front();
+        }
+        let (since, then) = readings[0];
+        let elapsed = now.duration_since(since).as_secs_f64();
+        if elapsed > 0.0 {
+            samples.saturating_sub(then) as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Middleware: counts every request by method, route and status, and how long it took.
+pub async fn track_requests(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().clone();
+    let started = Instant::now();
+    let response = next.run(request).await;
+    state
+        .metrics
+        .record_request(method.as_str(), &route, response.status(), started.elapsed());
+    response
+}
+
+/// Everything `/metrics` reports, in the Prometheus text exposition format.
+pub fn render(state: &AppState) -> String {
+    let mut out = String::new();
+
+    header(&mut out, "backend_http_requests_total", "counter", "HTTP requests answered.");
+    let requests = state.metrics.requests.lock().unwrap().clone();
+    for (key, stats) in &requests {
+        let labels = request_labels(key);
+        let _ = writeln!(out, "backend_http_requests_total{{{}}} {}", labels, stats.count);
+    }
+
+    header(
+        &mut out,
+        "backend_http_request_duration_seconds",
+        "histogram",
+        "How long HTTP requests took to answer.",
+    );
+    for (key, stats) in &requests {
+        let labels = request_labels(key);
+        let mut cumulative = 0;
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(stats.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "backend_http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                labels, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "backend_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+            labels, stats.count
+        );
+        let _ = writeln!(
+            out,
+            "backend_http_request_duration_seconds_sum{{{}}} {}",
+            labels, stats.seconds
+        );
+        let _ = writeln!(
+            out,
+            "backend_http_request_duration_seconds_count{{{}}} {}",
+            labels, stats.count
+        );
+    }
+
+    let writer = state.log_writer.stats();
+    let samples_per_second = state.metrics.samples_per_second(writer.samples);
+    sample(
+        &mut out,
+        "backend_samples_logged_total",
+        "counter",
+        "Samples written to the database.",
+        writer.samples,
+    );
+    sample(
+        &mut out,
+        "backend_samples_logged_per_second",
+        "gauge",
+        "Samples written per second over about the last minute.",
+        samples_per_second,
+    );
+    sample(
+        &mut out,
+        "backend_sample_batches_total",
+        "counter",
+        "Batches the samples were written in.",
+        writer.batches,
+    );
+    sample(
+        &mut out,
+        "backend_active_recorders",
+        "gauge",
+        "Users being sampled right now.",
+        state.recorder.active(),
+    );
+
+    let cache = state.csv.writer_stats();
+    sample(
+        &mut out,
+        "backend_csv_writers_open",
+        "gauge",
+        "CSV files held open for writing.",
+        cache.open,
+    );
+    sample(
+        &mut out,
+        "backend_csv_writer_cache_hits_total",
+        "counter",
+        "CSV rows appended through a writer that was already open.",
+        cache.hits,
+    );
+    sample(
+        &mut out,
+        "backend_csv_writer_cache_misses_total",
+        "counter",
+        "CSV rows that had to open their file first.",
+        cache.misses,
+    );
+    sample(
+        &mut out,
+        "backend_csv_writer_cache_evictions_total",
+        "counter",
+        "CSV writers closed for the open-file limit or for sitting idle.",
+        cache.evictions,
+    );
+
+    if let Some(pool) = state.repo.pool_stats() {
+        sample(
+            &mut out,
+            "backend_db_pool_connections",
+            "gauge",
+            "Database connections open, idle or in use.",
+            pool.size,
+        );
+        sample(
+            &mut out,
+            "backend_db_pool_idle_connections",
+            "gauge",
+            "Database connections waiting for a query.",
+            pool.idle,
+        );
+        sample(
+            &mut out,
+            "backend_db_pool_max_connections",
+            "gauge",
+            "How many connections the pool may open.",
+            pool.max,
+        );
+    }
+
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn sample(out: &mut String, name: &str, kind: &str, help: &str, value: impl std::fmt::Display) {
+    header(out, name, kind, help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn request_labels(key: &RequestKey) -> String {
+    format!(
+        "method=\"{}\",route=\"{}\",status=\"{}\"",
+        escape_label(&key.method),
+        escape_label(&key.route),
+        key.status
+    )
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -1,151 +1,199 @@
-use csv::Writer;
-use sqlx::{migrate::MigrateDatabase, Pool, Sqlite, SqlitePool};
-use std::{
-    collections::HashMap,
-    fs::{self, File},
-    path::PathBuf,
-    sync::{Arc, RwLock},
+use chrono::Utc;
+use sqlx::{migrate::MigrateDatabase, Sqlite, SqlitePool};
+use std::{fs, path::PathBuf, sync::Arc};
+use thiserror::Error;
+
+use super::codebook::{self, Codebook, LoadCodebookError};
+use crate::{
+    config::Config,
+    csv::{
+        rotation::{CsvSettings, CsvSettingsError},
+        writer::CsvExporter,
+    },
+    db::{
+        migrations::{run_migrations, MigrationError},
+        repository::StateRepository,
+        sqlite::SqliteRepository,
+    },
+    live::LiveFeed,
+    log_writer::{LogWriter, LogWriterSettings, LogWriterSettingsError},
+    metrics::Metrics,
+    recorder::Recorder,
 };
-use tokio::sync::Mutex;
+
+#[derive(Debug, Error)]
+pub enum StartupError {
+    #[error("failed to create {}: {source}", path.display())]
+    Directory {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error(transparent)]
+    Migration(#[from] MigrationError),
+    #[error(transparent)]
+    Codebook(#[from] LoadCodebookError),
+    #[error(transparent)]
+    CsvSettings(#[from] CsvSettingsError),
+    #[error(transparent)]
+    LogWriterSettings(#[from] LogWriterSettingsError),
+    #[error("unsupported database URL '{0}' (this build supports {SUPPORTED_DATABASES})")]
+    UnsupportedDatabase(String),
+}
+
+#[cfg(feature = "postgres")]
+const SUPPORTED_DATABASES: &str = "sqlite:// and postgres://";
+#[cfg(not(feature = "postgres"))]
+const SUPPORTED_DATABASES: &str = "sqlite:// only; enable the `postgres` feature for postgres://";
 
 pub struct AppState {
-    pub db: Pool<Sqlite>,
-    pub csv_writers: Arc<RwLock<HashMap<String, Arc<Mutex<Writer<File>>>>>>,
+    pub repo: Arc<dyn StateRepository>,
+    pub csv: Arc<CsvExporter>,
+    pub log_writer: LogWriter,
     pub data_dir: PathBuf,
+    pub codebook: Arc<Codebook>,
+    pub recorder: Recorder,
+    pub live: LiveFeed,
+    pub metrics: Metrics,
 }
 
 impl AppState {
-    pub async fn new() -> Result<Self, sqlx::Error> {
-        // Print current working directory for debugging
-        let current_dir = std::env::current_dir().unwrap_or_default();
-        println!("Current working directory: {}", current_dir.display());
-        
-        // Create data directory with absolute path if it doesn't exist
-        let data_dir = current_dir.join("data");
-        println!("Data directory path: {}", data_dir.display());
-        
-        // Create directories with error reporting
-        match fs::create_dir_all(&data_dir) {
-            Ok(_) => println!("Data directory created or already exists"),
-            Err(e) => eprintln!("Failed to create data directory: {}", e),
-        };
-        
-        // Create CSV directory if it doesn't exist
-        let csv_dir = data_dir.join("csv");
-        match fs::create_dir_all(&csv_dir) {
-            Ok(_) => println!("CSV directory created or already exists"),
-            Err(e) => eprintln!("Failed to create CSV directory: {}", e),
-        };
-        
-        // Generate the database file path with absolute path
-        let db_path = data_dir.join("app.db");
-        println!("Database path: {}", db_path.display());
-        
-        // Check if we can create a test file in the directory
-        let test_file_path = data_dir.join("test_write.txt");
-        match std::fs::File::create(&test_file_path) {
-            Ok(_) => {
-                println!("Write test successful");
-                // Clean up
-                let _ = std::fs::remove_file(test_file_path);
-            },
-            Err(e) => eprintln!("Write test failed: {}", e),
-        }
-        
-        // Construct proper SQLite URL with absolute path
-        let db_path = data_dir.join("app.db");
-        let db_url = format!("sqlite://{}", db_path.to_string_lossy());
-        println!("Database URL: {}", db_url);
-
-        if !Sqlite::database_exists(&db_url).await.unwrap_or(false) {
-            println!("Creating database {}", &db_url);
-            match Sqlite::create_database(&db_url).await {
-                Ok(_) => println!("Create db success"),
-                Err(error) => panic!("error: {}", error),
-            }
+    pub async fn new(config: &Config) -> Result<Self, StartupError> {
+        for dir in [&config.data_dir, &config.csv_dir] {
+            fs::create_dir_all(dir).map_err(|source| StartupError::Directory {
+                path: dir.clone(),
+                source,
+            })?;
         }
-        // Initialize the database connection
-        println!("Attempting to connect to the database...");
-        let db = SqlitePool::connect(&db_url).await?;
-        println!("Database connection established successfully");
-
-        // Initialize the database schema
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS user_states (
-                username TEXT PRIMARY KEY,
-                text_entry TEXT NOT NULL,
-                category1 TEXT NOT NULL,
-                category2 TEXT NOT NULL,
-                category3 TEXT NOT NULL,
-                category4 TEXT NOT NULL,
-                is_recording BOOLEAN NOT NULL,
-                last_saved TEXT,
-                last_data TEXT
-            );
-            
-            CREATE TABLE IF NOT EXISTS data_logs (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                username TEXT NOT NULL,
-                text_entry TEXT NOT NULL,
-                category1 TEXT NOT NULL,
-                category2 TEXT NOT NULL,
-                category3 TEXT NOT NULL,
-                category4 TEXT NOT NULL,
-                timestamp TEXT NOT NULL,
-                FOREIGN KEY(username) REFERENCES user_states(username)
-            );
-            "#,
-        )
-        .execute(&db)
-        .await?;
+        tracing::info!(
+            "Data in {}, CSV files in {}",
+            config.data_dir.display(),
+            config.csv_dir.display()
+        );
+
+        let repo = connect_repository(&config.database_url).await?;
+
+        // Load the codebook that decides which category fields exist
+        let codebook_path = config.data_dir.join("codebook.json");
+        let codebook = codebook::load_or_create(&codebook_path)?;
+        tracing::info!(
+            "Loaded codebook with {} fields from {}",
+            codebook.fields.len(),
+            codebook_path.display()
+        );
+
+        let csv_settings = CsvSettings::from_env()?;
+        tracing::info!(
+            "CSV rotation: {:?}, compress closed files: {}, at most {} open for {:?} idle",
+            csv_settings.rotation,
+            csv_settings.compress,
+            csv_settings.max_open_writers,
+            csv_settings.writer_idle_timeout
+        );
+
+        let writer_settings = LogWriterSettings::from_env()?;
+        tracing::info!(
+            "Sample writes: {:?} sync, up to {} per batch",
+            writer_settings.sync, writer_settings.max_batch
+        );
+        let csv = Arc::new(CsvExporter::new(
+            config.csv_dir.clone(),
+            csv_settings,
+            codebook.csv_header(),
+        ));
+        let codebook = Arc::new(codebook);
+        let log_writer = LogWriter::spawn(
+            Arc::clone(&repo),
+            Arc::clone(&csv),
+            Arc::clone(&codebook),
+            writer_settings,
+        );
 
         Ok(Self {
-            db,
-            csv_writers: Arc::new(RwLock::new(HashMap::new())),
-            data_dir,
+            repo,
+            csv,
+            log_writer,
+            data_dir: config.data_dir.clone(),
+            codebook,
+            recorder: Recorder::new(config.sample_interval),
+            live: LiveFeed::default(),
+            metrics: Metrics::default(),
         })
     }
-    // In backend/src/models/app_state.rs, change the get_csv_writer method
-
-    pub async fn get_csv_writer(
-        &self,
-        username: &str,
-    ) -> std::io::Result<Arc<Mutex<Writer<File>>>> {
-        let writers = self.csv_writers.read().unwrap();
 
-        if let Some(writer) = writers.get(username) {
-            return Ok(writer.clone());
+    /// Winds down after the server has stopped taking requests: sampling stops, queued
+    /// samples are written, the recordings this server sampled are closed as interrupted,
+    /// every CSV file is flushed to disk and the database connections are closed. Each
+    /// step runs even if one before it failed.
+    pub async fn shutdown(&self) {
+        let sampled = self.recorder.stop_all().await;
+        if let Err(err) = self.log_writer.flush().await {
+            tracing::error!("Failed to write queued samples: {}", err);
         }
 
-        drop(writers); // Release the read lock
-
-        let mut writers = self.csv_writers.write().unwrap();
-        let csv_path = self.data_dir.join("csv").join(format!("{}.csv", username));
-
-        let file_exists = csv_path.exists();
-        let file = File::options().create(true).append(true).open(&csv_path)?;
+        let ended_at = Utc::now().to_rfc3339();
+        match self.repo.interrupt_recording_sessions(&sampled, &ended_at).await {
+            Ok(sessions) => {
+                let csv = Arc::clone(&self.csv);
+                let ended: Vec<_> = sessions
+                    .iter()
+                    .map(|session| (session.username.clone(), session.id))
+                    .collect();
+                let closed = tokio::task::spawn_blocking(move || {
+                    for (username, id) in ended {
+                        if let Err(err) = csv.end_session(&username, id) {
+                            tracing::warn!(
+                                "Failed to close the CSV file of session {}: {}",
+                                id,
+                                err
+                            );
+                        }
+                    }
+                });
+                if let Err(err) = closed.await {
+                    tracing::warn!("Failed to close the CSV files of ended sessions: {}", err);
+                }
+                if !sessions.is_empty() {
+                    tracing::info!("Interrupted {} open recording session(s)", sessions.len());
+                }
+            }
+            Err(err) => tracing::error!("Failed to close open recording sessions: {}", err),
+        }
 
-        let mut writer = csv::WriterBuilder::new().from_writer(file);
+        if let Err(err) = self.csv.flush_all(true) {
+            tracing::error!("Failed to flush CSV files: {}", err);
+        }
+        self.repo.close().await;
+    }
+}
 
-        // Write headers if the file is new
-        if !file_exists {
-            writer.write_record(&[
-                "username",
-                "text_entry",
-                "category1",
-                "category2",
-                "category3",
-                "category4",
-                "timestamp",
-            ])?;
-            writer.flush()?;
+/// Connects to the database behind `db_url` and brings its schema up to date.
+async fn connect_repository(db_url: &str) -> Result<Arc<dyn StateRepository>, StartupError> {
+    if db_url.starts_with("sqlite:") {
+        if !Sqlite::database_exists(db_url).await.unwrap_or(false) {
+            tracing::info!("Creating database {}", db_url);
+            Sqlite::create_database(db_url).await?;
         }
+        let db = SqlitePool::connect(db_url).await?;
+        tracing::info!("Connected to SQLite database {}", db_url);
 
-        // Create an Arc<Mutex<...>> instead of just Mutex<...>
-        let arc_mutex = Arc::new(Mutex::new(writer));
-        writers.insert(username.to_string(), arc_mutex.clone());
+        let schema_version = run_migrations(&db).await?;
+        tracing::info!("Database schema at version {}", schema_version);
+        return Ok(Arc::new(SqliteRepository::new(db)));
+    }
 
-        Ok(arc_mutex)
+    #[cfg(feature = "postgres")]
+    if db_url.starts_with("postgres:") || db_url.starts_with("postgresql:") {
+        let db = sqlx::PgPool::connect(db_url).await?;
+        tracing::info!("Connected to PostgreSQL database");
+
+        let schema_version = crate::db::migrations::run_postgres_migrations(&db).await?;
+        tracing::info!("Database schema at version {}", schema_version);
+        return Ok(Arc::new(crate::db::postgres::PostgresRepository::new(db)));
     }
+
+    // Only the scheme: the rest of a URL may hold a password
+    let scheme = db_url.split(':').next().unwrap_or_default();
+    Err(StartupError::UnsupportedDatabase(format!("{}:", scheme)))
 }
@@ -0,0 +1,27 @@
+pub use shared::codebook::{Codebook, CodebookError, CodebookField};
+
+use std::{fs, io, path::Path};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LoadCodebookError {
+    #[error("failed to read codebook: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to parse codebook: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error(transparent)]
+    Invalid(#[from] CodebookError),
+}
+
+/// Loads the codebook at `path`, writing the default one there first if it doesn't exist.
+pub fn load_or_create(path: &Path) -> Result<Codebook, LoadCodebookError> {
+    if !path.exists() {
+        let codebook = Codebook::default();
+        fs::write(path, serde_json::to_string_pretty(&codebook)?)?;
+        return Ok(codebook);
+    }
+
+    let codebook: Codebook = serde_json::from_str(&fs::read_to_string(path)?)?;
+    codebook.validate()?;
+    Ok(codebook)
+}
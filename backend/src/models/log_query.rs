@@ -0,0 +1,55 @@
+pub use shared::log_query::{
+    ExportParams, LogPage, LogQueryParams, SortOrder, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE,
+};
+
+use chrono::{DateTime, Utc};
+
+use super::username::Username;
+
+/// A validated log query, ready for the repository.
+#[derive(Clone, Debug, Default)]
+pub struct LogQuery {
+    /// `None` means every user
+    pub usernames: Option<Vec<Username>>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub category: Option<String>,
+    pub value: Option<String>,
+    pub text: Option<String>,
+    pub after: Option<LogCursor>,
+    pub sort: SortOrder,
+    pub limit: u32,
+}
+
+/// Position of the last row of a page; the next page starts right after it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogCursor {
+    pub timestamp: String,
+    pub id: i64,
+}
+
+impl LogCursor {
+    /// Cursors are opaque to clients: hex of `timestamp|id`.
+    pub fn encode(&self) -> String {
+        format!("{}|{}", self.timestamp, self.id)
+            .bytes()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        if !cursor.len().is_multiple_of(2) || !cursor.is_ascii() {
+            return None;
+        }
+        let bytes = (0..cursor.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&cursor[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        let decoded = String::from_utf8(bytes).ok()?;
+        let (timestamp, id) = decoded.rsplit_once('|')?;
+        Some(Self {
+            timestamp: timestamp.to_string(),
+            id: id.parse().ok()?,
+        })
+    }
+}
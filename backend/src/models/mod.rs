@@ -1,2 +1,8 @@
 pub mod user_state;
-pub mod app_state;
\ No newline at end of file
+pub mod app_state;
+pub mod codebook;
+pub mod log_query;
+pub mod user;
+
+// Wire types, defined once for the backend and frontend
+pub use shared::{live, recording_session, username, ws_message};
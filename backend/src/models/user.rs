@@ -0,0 +1,11 @@
+pub use shared::user::{Assignment, AuthResponse, Credentials, Role, RoleUpdate, UserSummary};
+
+use super::username::Username;
+
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct User {
+    pub username: Username,
+    pub password_hash: String,
+    pub role: Role,
+    pub created_at: String,
+}
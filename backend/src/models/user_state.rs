@@ -1,26 +1,9 @@
-use serde::{Deserialize, Serialize};
+pub use shared::user_state::{DataLog, UserState};
 
-#[derive(Clone, Debug, Serialize, Deserialize, sqlx::FromRow)]
-pub struct UserState {
-    pub username: String,
-    pub text_entry: String,
-    pub category1: String,
-    pub category2: String,
-    pub category3: String,
-    pub category4: String,
-    pub is_recording: bool,
-    pub last_saved: Option<String>,
-    pub last_data: Option<String>,
+/// A sample on its way into storage: its `data_logs` row, and the summary it leaves as
+/// the user's latest sample.
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub log: DataLog,
+    pub summary: String,
 }
-
-#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
-pub struct DataLog {
-    pub id: Option<i64>,
-    pub username: String,
-    pub text_entry: String,
-    pub category1: String,
-    pub category2: String,
-    pub category3: String,
-    pub category4: String,
-    pub timestamp: String,
-}
\ No newline at end of file
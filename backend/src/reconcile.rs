@@ -0,0 +1,207 @@
+//! Checks users' CSV files against `data_logs`, the record of truth, and rebuilds the
+//! files of users whose CSV has drifted from it.
+
+use serde::Serialize;
+use std::{collections::HashMap, sync::Arc};
+use thiserror::Error;
+
+use crate::{
+    csv::{
+        dialect::TimestampFormat,
+        index::CsvIndex,
+        writer::{CsvExporter, CsvRow},
+    },
+    db::repository::StateRepository,
+    models::{codebook::Codebook, log_query::{LogCursor, LogQuery, SortOrder}, username::Username},
+};
+
+#[derive(Debug, Error)]
+pub enum ReconcileError {
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("rebuild task failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
+}
+
+/// How one user's CSV files compare with their `data_logs` rows.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct CsvReport {
+    pub username: Username,
+    pub db_rows: usize,
+    pub csv_rows: usize,
+    /// Samples in the database without a CSV row
+    pub missing: usize,
+    /// CSV rows without a sample in the database
+    pub extra: usize,
+    /// CSV rows whose values differ from their sample's
+    pub changed: usize,
+    /// Whether the files were rebuilt from the database
+    pub rebuilt: bool,
+}
+
+impl CsvReport {
+    pub fn is_consistent(&self) -> bool {
+        self.missing == 0 && self.extra == 0 && self.changed == 0
+    }
+}
+
+/// Compares `username`'s CSV files with the database, rebuilding them if they disagree
+/// and `repair` is set.
+///
+/// Rows are matched by timestamp, as each file spells it. Values are only compared in
+/// files written under the current codebook, as older files have other columns.
+pub async fn reconcile_user(
+    repo: &dyn StateRepository,
+    csv: &Arc<CsvExporter>,
+    codebook: &Arc<Codebook>,
+    username: &Username,
+    repair: bool,
+) -> Result<CsvReport, ReconcileError> {
+    let logs = repo.query_logs(&user_logs(username)).await?;
+    let files = csv.read_rows(username)?;
+
+    let header = codebook.csv_header();
+    let mut report = CsvReport {
+        username: username.clone(),
+        db_rows: logs.len(),
+        csv_rows: files.iter().map(|(_, records)| records.len()).sum(),
+        missing: 0,
+        extra: 0,
+        changed: 0,
+        rebuilt: false,
+    };
+    // Indexes of the logs by their timestamp in each format the files use
+    let mut by_timestamp: HashMap<&TimestampFormat, HashMap<String, Vec<usize>>> = HashMap::new();
+    let mut matched = vec![false; logs.len()];
+    for (entry, records) in &files {
+        let dialect = &entry.dialect;
+        let lookup = by_timestamp
+            .entry(&dialect.timestamp_format)
+            .or_insert_with(|| {
+                let mut lookup: HashMap<_, Vec<_>> = HashMap::new();
+                for (at, log) in logs.iter().enumerate().rev() {
+                    let timestamp = dialect.timestamp_format.format(&log.timestamp);
+                    lookup.entry(timestamp).or_default().push(at);
+                }
+                lookup
+            });
+        for record in records {
+            let candidates = record.last().and_then(|timestamp| lookup.get_mut(timestamp));
+            // The oldest sample with that timestamp that no other row took yet
+            let found = candidates.and_then(|candidates| {
+                std::iter::from_fn(|| candidates.pop()).find(|&at| !matched[at])
+            });
+            let Some(at) = found else {
+                report.extra += 1;
+                continue;
+            };
+            matched[at] = true;
+            let expected = dialect.record(codebook.csv_record(&logs[at]));
+            if entry.columns == header && *record != expected {
+                report.changed += 1;
+            }
+        }
+    }
+    report.missing = matched.iter().filter(|&&matched| !matched).count();
+
+    if repair && !report.is_consistent() {
+        rebuild_user(repo, csv, codebook, username).await?;
+        report.rebuilt = true;
+    }
+    Ok(report)
+}
+
+/// What rebuilding one user's files produced.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct CsvRebuild {
+    pub username: Username,
+    pub files: usize,
+    pub rows: u64,
+}
+
+impl CsvRebuild {
+    pub fn new(username: Username, index: &CsvIndex) -> Self {
+        Self {
+            username,
+            files: index.files.len(),
+            rows: index.files.iter().map(|entry| entry.rows).sum(),
+        }
+    }
+}
+
+/// Rewrites all of `username`'s CSV files from their `data_logs` rows.
+///
+/// Safe while the server runs, in it or in another process: the user's files stay
+/// locked from the last check for new logs until the new files are in place, and
+/// samples written meanwhile are appended to the new files afterwards.
+pub async fn rebuild_user(
+    repo: &dyn StateRepository,
+    csv: &Arc<CsvExporter>,
+    codebook: &Arc<Codebook>,
+    username: &Username,
+) -> Result<CsvIndex, ReconcileError> {
+    let mut logs = repo.query_logs(&user_logs(username)).await?;
+    // Waiting for another process's lock mustn't hold up a runtime thread
+    let lock = {
+        let (csv, username) = (csv.clone(), username.clone());
+        tokio::task::spawn_blocking(move || csv.lock_user(&username)).await??
+    };
+    // Samples committed before the lock was taken may not have reached the files yet
+    let after = logs.last().and_then(|last| {
+        last.id.map(|id| LogCursor {
+            timestamp: last.timestamp.clone(),
+            id,
+        })
+    });
+    let newer = LogQuery {
+        after,
+        ..user_logs(username)
+    };
+    logs.extend(repo.query_logs(&newer).await?);
+    let newest = logs.iter().filter_map(|log| log.id).max();
+
+    let index = {
+        let (csv, codebook, username) = (csv.clone(), codebook.clone(), username.clone());
+        tokio::task::spawn_blocking(move || {
+            let rows = logs.iter().map(|log| CsvRow::from_log(log, &codebook));
+            let index = csv.rebuild(&username, rows);
+            drop(lock);
+            index
+        })
+        .await??
+    };
+
+    // Whatever the outbox still owed up to here is in the new files
+    if let Some(newest) = newest {
+        repo.clear_user_csv_outbox(username, newest).await?;
+    }
+    Ok(index)
+}
+
+/// Rebuilds the files of every user, one after the other.
+pub async fn rebuild_all(
+    repo: &dyn StateRepository,
+    csv: &Arc<CsvExporter>,
+    codebook: &Arc<Codebook>,
+) -> Result<Vec<CsvRebuild>, ReconcileError> {
+    let mut rebuilt = Vec::new();
+    for user in repo.list_users().await? {
+        let index = rebuild_user(repo, csv, codebook, &user.username).await?;
+        rebuilt.push(CsvRebuild::new(user.username, &index));
+    }
+    Ok(rebuilt)
+}
+
+/// All of `username`'s logs, oldest first like the files.
+fn user_logs(username: &Username) -> LogQuery {
+    LogQuery {
+        usernames: Some(vec![username.clone()]),
+        sort: SortOrder::Asc,
+        limit: u32::MAX,
+        ..LogQuery::default()
+    }
+}
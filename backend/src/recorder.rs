@@ -0,0 +1,289 @@
+//! Server-side recording clock.
+//!
+//! While a recording session is open, a per-user task snapshots the user's latest
+//! saved `UserState` into `data_logs` and their CSV file on a fixed schedule, through
+//! the shared `LogWriter`. Clients
+//! only push state changes, so sampling keeps its pace when a browser tab is throttled,
+//! asleep or closed.
+//!
+//! Servers sharing a database each sample only the sessions they hold a lease on. The
+//! lease is renewed with every sample, so the sessions of a server that goes away are
+//! adopted by another once it runs out, and never sampled by two at once.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::{
+    sync::oneshot,
+    task::JoinHandle,
+    time::MissedTickBehavior,
+};
+
+use crate::{
+    auth::token::generate_token,
+    log_writer::WriteError,
+    models::{
+        app_state::AppState,
+        user_state::{DataLog, Sample, UserState},
+        username::Username,
+    },
+};
+
+/// How often an open session is sampled.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How many sample intervals a lease outlasts the sample that renewed it, so a slow
+/// sample or two doesn't hand the session to another server.
+const LEASE_INTERVALS: u32 = 3;
+
+#[derive(Debug, Error)]
+pub enum SampleError {
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error(transparent)]
+    Write(#[from] WriteError),
+}
+
+struct SamplingTask {
+    session_id: i64,
+    stop: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+/// Owns one sampling task per user with an open recording session leased to this server.
+pub struct Recorder {
+    interval: Duration,
+    /// Names this server in session leases
+    owner: Arc<str>,
+    tasks: Mutex<HashMap<Username, SamplingTask>>,
+}
+
+impl Recorder {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            owner: format!("{}-{}", std::process::id(), &generate_token()[..16]).into(),
+            tasks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How long a session stays leased to this server after its latest sample.
+    pub fn lease(&self) -> Duration {
+        self.interval * LEASE_INTERVALS
+    }
+
+    /// Leases `session_id` to this server and starts sampling `username` into it; the
+    /// first sample is taken right away. Returns `false`, sampling nothing, if the session
+    /// has ended or another server holds its lease. A task already running for the same
+    /// session is left alone; one for an older session is told to stop.
+    pub async fn start(
+        &self,
+        state: Arc<AppState>,
+        username: &Username,
+        session_id: i64,
+    ) -> Result<bool, sqlx::Error> {
+        if self.is_sampling_session(username, session_id) {
+            return Ok(true);
+        }
+        if !claim(&state, &self.owner, self.lease(), session_id).await? {
+            return Ok(false);
+        }
+
+        let mut tasks = self.tasks.lock().unwrap();
+        if tasks
+            .get(username)
+            .is_some_and(|task| task.session_id == session_id && !task.handle.is_finished())
+        {
+            return Ok(true);
+        }
+
+        let (stop, stopped) = oneshot::channel();
+        let handle = tokio::spawn(sample_loop(
+            state,
+            username.clone(),
+            session_id,
+            Arc::clone(&self.owner),
+            self.interval,
+            stopped,
+        ));
+        let previous = tasks.insert(
+            username.clone(),
+            SamplingTask {
+                session_id,
+                stop,
+                handle,
+            },
+        );
+        if let Some(previous) = previous {
+            let _ = previous.stop.send(());
+        }
+        Ok(true)
+    }
+
+    /// Stops sampling `username`, waiting for a sample that is being written to finish.
+    pub async fn stop(&self, username: &Username) {
+        let task = self.tasks.lock().unwrap().remove(username);
+        if let Some(task) = task {
+            let _ = task.stop.send(());
+            let _ = task.handle.await;
+        }
+    }
+
+    /// Stops sampling everyone, waiting for samples being written to finish. Returns the
+    /// sessions that were being sampled, leaving out those that ended or went to another
+    /// server on their own.
+    pub async fn stop_all(&self) -> Vec<i64> {
+        let tasks: Vec<_> = self.tasks.lock().unwrap().drain().collect();
+        let mut session_ids = Vec::with_capacity(tasks.len());
+        for (_, task) in tasks {
+            let running = !task.handle.is_finished();
+            let _ = task.stop.send(());
+            let _ = task.handle.await;
+            if running {
+                session_ids.push(task.session_id);
+            }
+        }
+        session_ids
+    }
+
+    /// How many users are being sampled right now.
+    pub fn active(&self) -> usize {
+        self.tasks
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|task| !task.handle.is_finished())
+            .count()
+    }
+
+    pub fn is_sampling(&self, username: &Username) -> bool {
+        self.tasks
+            .lock()
+            .unwrap()
+            .get(username)
+            .is_some_and(|task| !task.handle.is_finished())
+    }
+
+    fn is_sampling_session(&self, username: &Username, session_id: i64) -> bool {
+        self.tasks
+            .lock()
+            .unwrap()
+            .get(username)
+            .is_some_and(|task| task.session_id == session_id && !task.handle.is_finished())
+    }
+
+    /// Picks sampling back up for every open session no server holds a lease on, e.g.
+    /// after a restart or once another server stopped renewing. Returns how many it took.
+    pub async fn resume_open_sessions(&self, state: Arc<AppState>) -> Result<usize, sqlx::Error> {
+        let sessions = state.repo.open_recording_sessions().await?;
+        let mut resumed = 0;
+        for session in &sessions {
+            if self.is_sampling_session(&session.username, session.id) {
+                continue;
+            }
+            if self.start(Arc::clone(&state), &session.username, session.id).await? {
+                resumed += 1;
+            }
+        }
+        Ok(resumed)
+    }
+}
+
+/// Takes or renews `owner`'s lease on `session_id`, for `lease` from now.
+async fn claim(
+    state: &AppState,
+    owner: &str,
+    lease: Duration,
+    session_id: i64,
+) -> Result<bool, sqlx::Error> {
+    let now = Utc::now();
+    let expires_at = now + chrono::Duration::from_std(lease).unwrap_or(chrono::Duration::MAX);
+    state
+        .repo
+        .claim_recording_session(session_id, owner, &lease_time(now), &lease_time(expires_at))
+        .await
+}
+
+/// Lease times compare as text, so they always carry milliseconds and a `Z`.
+fn lease_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+async fn sample_loop(
+    state: Arc<AppState>,
+    username: Username,
+    session_id: i64,
+    owner: Arc<str>,
+    interval: Duration,
+    mut stopped: oneshot::Receiver<()>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    // Stay on the original grid rather than bunching up after a stall
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let lease = interval * LEASE_INTERVALS;
+
+    loop {
+        tokio::select! {
+            _ = &mut stopped => break,
+            _ = ticker.tick() => {
+                // Only the lease holder samples; losing it means the session was stopped
+                // or another server took it over
+                match claim(&state, &owner, lease, session_id).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        tracing::info!(
+                            "Session {} of {} ended or moved to another server",
+                            session_id,
+                            username
+                        );
+                        break;
+                    }
+                    Err(err) => {
+                        tracing::warn!("Failed to renew lease on session {}: {}", session_id, err);
+                        continue;
+                    }
+                }
+                if let Err(err) = take_sample(&state, &username, session_id).await {
+                    tracing::warn!("Failed to sample {} (session {}): {}", username, session_id, err);
+                }
+            }
+        }
+    }
+}
+
+/// Writes one sample of `username`'s current state to `data_logs` and their CSV file.
+pub async fn take_sample(
+    state: &AppState,
+    username: &Username,
+    session_id: i64,
+) -> Result<(), SampleError> {
+    let Some(user_state) = state.repo.get_user_state(username).await? else {
+        return Ok(());
+    };
+    let log = DataLog {
+        id: None,
+        username: username.clone(),
+        text_entry: user_state.text_entry.clone(),
+        categories: user_state.categories.clone(),
+        timestamp: Utc::now().to_rfc3339(),
+        session_id: Some(session_id),
+    };
+    // Also records this as the user's latest sample
+    let summary = summarize(state, &user_state);
+    state.log_writer.write(Sample { log, summary }).await?;
+    state.live.notify();
+    Ok(())
+}
+
+/// The short description of a sample shown on the data entry screen.
+fn summarize(state: &AppState, user_state: &UserState) -> String {
+    format!(
+        "Text: {}, Categories: {}",
+        user_state.text_entry,
+        state.codebook.ordered_values(&user_state.categories).join(", ")
+    )
+}
@@ -0,0 +1,62 @@
+//! Gives every request an id, sent back as `x-request-id` and in error bodies, and
+//! attached to everything logged while the request is handled.
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tracing::Instrument;
+
+use crate::auth::token::to_hex;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Ids longer than this from a proxy are replaced rather than echoed.
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// The id of the request being handled, if called while handling one.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(String::clone).ok()
+}
+
+/// Middleware: keeps the id a proxy in front already assigned, or makes one up.
+pub async fn assign_request_id(mut request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_string)
+        .unwrap_or_else(generate);
+    let header = HeaderValue::from_str(&id).expect("request ids are visible ASCII");
+    request.headers_mut().insert(REQUEST_ID_HEADER, header.clone());
+
+    let span = tracing::info_span!(
+        "request",
+        id = %id,
+        method = %request.method(),
+        path = %request.uri().path(),
+    );
+    let mut response = REQUEST_ID.scope(id, next.run(request)).instrument(span).await;
+    response.headers_mut().insert(REQUEST_ID_HEADER, header);
+    response
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+fn generate() -> String {
+    let mut bytes = [0u8; 8];
+    OsRng.fill_bytes(&mut bytes);
+    to_hex(&bytes)
+}
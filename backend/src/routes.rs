@@ -0,0 +1,102 @@
+use axum::{
+    middleware,
+    routing::{any, get, post, put},
+    Router,
+};
+use std::sync::Arc;
+
+use crate::{
+    auth::permissions::{require_admin, require_observer},
+    handlers::{
+        admin_handlers::{
+            assign_participant, download_csv, download_csv_file, list_assignments, list_csv_files,
+            list_users, live_recordings, rebuild_all_csv, rebuild_csv, set_user_role,
+            unassign_participant,
+        },
+        auth_handlers::{login, logout, register},
+        error::unknown_endpoint,
+        health_handlers::{healthz, metrics, readyz},
+        log_handlers::{export_logs, query_logs},
+        session_handlers::{
+            get_session, get_session_samples, list_sessions, start_session, stop_session,
+        },
+        site_handlers::serve_site,
+        state_handlers::{
+            get_codebook, get_participant_state, get_user_state, list_participants,
+            update_participant_state, update_user_state,
+        },
+        ws_handlers::ws_connect,
+    },
+    metrics::track_requests,
+    models::app_state::AppState,
+    request_id::assign_request_id,
+    site::Site,
+};
+
+/// All `/api` routes, with the role checks applied in front of them, plus the probes and
+/// `/metrics`. Every request is counted and gets a request id.
+pub fn api_routes(state: Arc<AppState>) -> Router {
+    // For load balancers and monitoring; no login
+    let ops_routes = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(metrics));
+
+    // Any logged-in user, acting on their own data
+    let own_routes = Router::new()
+        .route("/api/auth/register", post(register))
+        .route("/api/auth/login", post(login))
+        .route("/api/auth/logout", post(logout))
+        .route("/api/codebook", get(get_codebook))
+        .route("/api/logs", get(query_logs))
+        .route("/api/logs/export", get(export_logs))
+        .route("/api/sessions", get(list_sessions).post(start_session))
+        .route("/api/sessions/{id}", get(get_session))
+        .route("/api/sessions/{id}/samples", get(get_session_samples))
+        .route("/api/sessions/{id}/stop", post(stop_session))
+        .route("/api/state", get(get_user_state).post(update_user_state))
+        .route("/api/ws", get(ws_connect))
+        .route("/api/{*path}", any(unknown_endpoint));
+
+    // Observers and admins, acting on behalf of participants
+    let observer_routes = Router::new()
+        .route("/api/participants", get(list_participants))
+        .route(
+            "/api/state/{username}",
+            get(get_participant_state).post(update_participant_state),
+        )
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_observer));
+
+    let admin_routes = Router::new()
+        .route("/api/admin/users", get(list_users))
+        .route("/api/admin/users/{username}/role", put(set_user_role))
+        .route(
+            "/api/admin/assignments",
+            get(list_assignments)
+                .post(assign_participant)
+                .delete(unassign_participant),
+        )
+        .route("/api/admin/csv/{username}", get(download_csv))
+        .route("/api/admin/csv/{username}/files", get(list_csv_files))
+        .route(
+            "/api/admin/csv/{username}/files/{file}",
+            get(download_csv_file),
+        )
+        .route("/api/admin/csv/{username}/rebuild", post(rebuild_csv))
+        .route("/api/admin/rebuild-csv", post(rebuild_all_csv))
+        .route("/api/admin/live", get(live_recordings))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_admin));
+
+    own_routes
+        .merge(observer_routes)
+        .merge(admin_routes)
+        .merge(ops_routes)
+        .layer(middleware::from_fn_with_state(state.clone(), track_requests))
+        .layer(middleware::from_fn(assign_request_id))
+        .with_state(state)
+}
+
+/// The compiled frontend, for whatever `api_routes` doesn't answer; merge it after them.
+pub fn site_routes(site: Site) -> Router {
+    Router::new().fallback_service(get(serve_site).with_state(Arc::new(site)))
+}
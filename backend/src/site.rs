@@ -0,0 +1,123 @@
+//! The compiled frontend: the site root cargo-leptos builds the WASM package and the
+//! `public/` assets into, read from disk or, with the `embed-site` feature, from the binary.
+
+use std::{borrow::Cow, fmt, path::PathBuf, time::UNIX_EPOCH};
+
+use crate::config::Config;
+
+/// Where `cargo leptos build` puts the site, relative to the workspace.
+pub const DEFAULT_SITE_ROOT: &str = "target/site";
+
+/// The page every client-side route is answered with.
+pub const INDEX: &str = "index.html";
+
+/// The site-relative directory of the WASM, JS and CSS cargo-leptos compiles.
+pub const PKG_DIR: &str = "pkg";
+
+#[cfg(feature = "embed-site")]
+#[derive(rust_embed::RustEmbed)]
+#[folder = "../target/site"]
+#[allow_missing = true]
+struct EmbeddedSite;
+
+#[derive(Clone, Debug)]
+pub enum Site {
+    /// Files read from this directory as they are asked for
+    Dir(PathBuf),
+    /// The site root as it was when the binary was compiled
+    #[cfg(feature = "embed-site")]
+    Embedded,
+}
+
+/// A file of the site, with an entity tag that changes whenever its contents do.
+pub struct Asset {
+    pub contents: Cow<'static, [u8]>,
+    pub etag: String,
+}
+
+impl Site {
+    /// The configured site root; without one, the copy built into the binary if there is
+    /// one, or `target/site`.
+    pub fn from_config(config: &Config) -> Self {
+        match &config.site_root {
+            Some(dir) => Site::Dir(dir.clone()),
+            #[cfg(feature = "embed-site")]
+            None => Site::Embedded,
+            #[cfg(not(feature = "embed-site"))]
+            None => Site::Dir(PathBuf::from(DEFAULT_SITE_ROOT)),
+        }
+    }
+
+    /// The file at `path`, relative to the site root and as returned by `asset_path`.
+    pub async fn load(&self, path: &str) -> Option<Asset> {
+        match self {
+            Site::Dir(dir) => {
+                let file = dir.join(path);
+                let metadata = tokio::fs::metadata(&file).await.ok()?;
+                if !metadata.is_file() {
+                    return None;
+                }
+                let contents = tokio::fs::read(&file).await.ok()?;
+                let modified = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .unwrap_or_default();
+                Some(Asset {
+                    etag: format!("\"{:x}-{:x}\"", contents.len(), modified.as_nanos()),
+                    contents: Cow::Owned(contents),
+                })
+            }
+            #[cfg(feature = "embed-site")]
+            Site::Embedded => {
+                let file = EmbeddedSite::get(path)?;
+                let hash: String = file.metadata.sha256_hash()[..16]
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect();
+                Some(Asset {
+                    etag: format!("\"{}\"", hash),
+                    contents: file.data,
+                })
+            }
+        }
+    }
+
+    /// Whether there is a frontend to serve at all.
+    pub async fn has_index(&self) -> bool {
+        self.load(INDEX).await.is_some()
+    }
+}
+
+impl fmt::Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Site::Dir(dir) => write!(f, "{}", dir.display()),
+            #[cfg(feature = "embed-site")]
+            Site::Embedded => write!(f, "the copy built into the binary"),
+        }
+    }
+}
+
+/// The site-relative file a request path names: `index.html` for a directory, and `None`
+/// for a path that would step outside the site root or into a hidden file.
+pub fn asset_path(request_path: &str) -> Option<String> {
+    let path = request_path.trim_start_matches('/');
+    let path = if path.is_empty() || path.ends_with('/') {
+        format!("{}{}", path, INDEX)
+    } else {
+        path.to_string()
+    };
+    let safe = path
+        .split('/')
+        .all(|segment| !segment.is_empty() && !segment.starts_with('.') && !segment.contains('\\'));
+    safe.then_some(path)
+}
+
+/// Whether a request path names a file, like `/pkg/app.js`, rather than a page of the app.
+pub fn names_file(request_path: &str) -> bool {
+    request_path
+        .rsplit('/')
+        .next()
+        .is_some_and(|last| last.contains('.'))
+}
@@ -0,0 +1,249 @@
+use std::sync::Arc;
+
+use leptos::prelude::*;
+use leptos::task::spawn_local;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{Blob, BlobPropertyBag, HtmlAnchorElement, Url};
+
+use shared::codebook::Codebook;
+use shared::user::{Assignment, Role, UserSummary};
+use shared::username::Username;
+
+use super::live_dashboard::LiveDashboard;
+use super::log_browser::LogBrowser;
+use crate::models::live::ReceivedSnapshot;
+use crate::services::api_service::ApiService;
+
+#[component]
+pub fn AdminScreen(
+    api: Arc<ApiService>,
+    #[prop(into)] username: Signal<String>,
+    #[prop(into)] codebook: Signal<Codebook>,
+    live: RwSignal<Option<ReceivedSnapshot>>,
+    on_logout: Callback<()>,
+) -> impl IntoView {
+    let users = RwSignal::new(Vec::<UserSummary>::new());
+    let assignments = RwSignal::new(Vec::<Assignment>::new());
+    let new_observer = RwSignal::new(String::new());
+    let new_participant = RwSignal::new(String::new());
+    let message = RwSignal::new(None::<String>);
+
+    // Reload users and assignments
+    let api_refresh = Arc::clone(&api);
+    let refresh = Callback::new(move |_: ()| {
+        let api = Arc::clone(&api_refresh);
+        spawn_local(async move {
+            match api.list_users().await {
+                Ok(list) => users.set(list),
+                Err(err) => message.set(Some(err.to_string())),
+            }
+            match api.list_assignments().await {
+                Ok(list) => assignments.set(list),
+                Err(err) => message.set(Some(err.to_string())),
+            }
+        });
+    });
+
+    let api_role = Arc::clone(&api);
+    let change_role = Callback::new(move |(user, role): (Username, Role)| {
+        let api = Arc::clone(&api_role);
+        spawn_local(async move {
+            if let Err(err) = api.set_role(&user, role).await {
+                message.set(Some(err.to_string()));
+            }
+            refresh.run(());
+        });
+    });
+
+    let api_assign = Arc::clone(&api);
+    let add_assignment = Callback::new(move |_: ()| {
+        // Both are picked from lists of valid names, or still unset
+        let (Ok(observer), Ok(participant)) = (
+            Username::parse(&new_observer.get()),
+            Username::parse(&new_participant.get()),
+        ) else {
+            return;
+        };
+        let assignment = Assignment { observer, participant };
+        let api = Arc::clone(&api_assign);
+        spawn_local(async move {
+            if let Err(err) = api.assign(&assignment).await {
+                message.set(Some(err.to_string()));
+            }
+            refresh.run(());
+        });
+    });
+
+    let api_unassign = Arc::clone(&api);
+    let remove_assignment = Callback::new(move |assignment: Assignment| {
+        let api = Arc::clone(&api_unassign);
+        spawn_local(async move {
+            if let Err(err) = api.unassign(&assignment).await {
+                message.set(Some(err.to_string()));
+            }
+            refresh.run(());
+        });
+    });
+
+    let api_csv = Arc::clone(&api);
+    let download_csv = Callback::new(move |user: Username| {
+        let api = Arc::clone(&api_csv);
+        spawn_local(async move {
+            match api.download_csv(&user).await {
+                Ok((filename, contents)) => {
+                    if save_bytes(&filename, &contents).is_err() {
+                        message.set(Some("Could not start the download".to_string()));
+                    }
+                }
+                Err(err) => message.set(Some(err.to_string())),
+            }
+        });
+    });
+
+    refresh.run(());
+
+    let users_with_role = move |role: Role| {
+        users
+            .get()
+            .into_iter()
+            .filter(|user| user.role == role)
+            .map(|user| user.username.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    view! {
+        <div class="admin-container">
+            <h1>"Administration"</h1>
+            <p class="welcome-message">"Signed in as " {move || username.get()}</p>
+            <button class="logout-button" on:click=move |_| on_logout.run(())>"Log Out"</button>
+
+            <Show when=move || message.get().is_some()>
+                <p class="login-error" on:click=move |_| message.set(None)>
+                    {move || message.get().unwrap_or_default()}
+                </p>
+            </Show>
+
+            <section class="admin-section">
+                <h3>"Recording Now"</h3>
+                <LiveDashboard api=Arc::clone(&api) snapshot=live codebook=codebook />
+            </section>
+
+            <section class="admin-section">
+                <h3>"Users"</h3>
+                <table class="admin-table">
+                    <thead>
+                        <tr><th>"Username"</th><th>"Role"</th><th>"Created"</th><th></th></tr>
+                    </thead>
+                    <tbody>
+                        <For
+                            each=move || users.get()
+                            key=|user| (user.username.clone(), user.role)
+                            let:user
+                        >
+                            {
+                                let role_user = user.username.clone();
+                                let csv_user = user.username.clone();
+                                view! {
+                                    <tr>
+                                        <td>{user.username.to_string()}</td>
+                                        <td>
+                                            <select
+                                                prop:value=user.role.as_str()
+                                                on:change=move |ev| {
+                                                    if let Some(role) = Role::parse(&event_target_value(&ev)) {
+                                                        change_role.run((role_user.clone(), role));
+                                                    }
+                                                }
+                                            >
+                                                {Role::ALL.iter().map(|role| view! {
+                                                    <option value=role.as_str()>{role.as_str()}</option>
+                                                }).collect_view()}
+                                            </select>
+                                        </td>
+                                        <td>{user.created_at.clone()}</td>
+                                        <td>
+                                            <button on:click=move |_| download_csv.run(csv_user.clone())>
+                                                "Download CSV"
+                                            </button>
+                                        </td>
+                                    </tr>
+                                }
+                            }
+                        </For>
+                    </tbody>
+                </table>
+            </section>
+
+            <section class="admin-section">
+                <h3>"Observer Assignments"</h3>
+                <ul class="assignment-list">
+                    <For
+                        each=move || assignments.get()
+                        key=|assignment| (assignment.observer.clone(), assignment.participant.clone())
+                        let:assignment
+                    >
+                        {
+                            let label = format!("{} → {}", assignment.observer, assignment.participant);
+                            view! {
+                                <li>
+                                    {label}
+                                    <button
+                                        class="link-button"
+                                        on:click=move |_| remove_assignment.run(assignment.clone())
+                                    >
+                                        "Remove"
+                                    </button>
+                                </li>
+                            }
+                        }
+                    </For>
+                </ul>
+                <div class="assignment-form">
+                    <select on:change=move |ev| new_observer.set(event_target_value(&ev))>
+                        <option value="">"-- Observer --"</option>
+                        {move || users_with_role(Role::Observer).into_iter().map(|name| view! {
+                            <option value=name.clone()>{name.clone()}</option>
+                        }).collect_view()}
+                    </select>
+                    <select on:change=move |ev| new_participant.set(event_target_value(&ev))>
+                        <option value="">"-- Participant --"</option>
+                        {move || users_with_role(Role::Participant).into_iter().map(|name| view! {
+                            <option value=name.clone()>{name.clone()}</option>
+                        }).collect_view()}
+                    </select>
+                    <button on:click=move |_| add_assignment.run(())>"Assign"</button>
+                </div>
+            </section>
+
+            <section class="admin-section">
+                <h3>"Data Logs"</h3>
+                <LogBrowser api=Arc::clone(&api) codebook=codebook />
+            </section>
+        </div>
+    }
+}
+
+/// Hands `contents` to the browser as a file download.
+pub(super) fn save_file(filename: &str, contents: &str) -> Result<(), JsValue> {
+    let parts = js_sys::Array::of1(&JsValue::from_str(contents));
+    let options = BlobPropertyBag::new();
+    options.set_type("text/csv");
+    save_blob(filename, &Blob::new_with_str_sequence_and_options(&parts, &options)?)
+}
+
+/// Hands raw bytes, such as a gzipped CSV, to the browser as a file download.
+fn save_bytes(filename: &str, contents: &[u8]) -> Result<(), JsValue> {
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(contents));
+    save_blob(filename, &Blob::new_with_u8_array_sequence(&parts)?)
+}
+
+fn save_blob(filename: &str, blob: &Blob) -> Result<(), JsValue> {
+    let url = Url::create_object_url_with_blob(blob)?;
+
+    let anchor: HtmlAnchorElement = document().create_element("a")?.dyn_into()?;
+    anchor.set_href(&url);
+    anchor.set_download(filename);
+    anchor.click();
+
+    Url::revoke_object_url(&url)
+}
@@ -1,8 +1,17 @@
 use std::sync::Arc;
 
+use shared::codebook::Codebook;
+use shared::user::{Credentials, Role};
+use shared::user_state::UserState;
+use shared::username::Username;
+use shared::ws_message::ServerMessage;
+
+use super::admin_screen::AdminScreen;
 use super::data_entry_screen::DataEntryScreen;
 use super::login_screen::LoginScreen;
-use crate::models::user_state::UserState;
+use super::participant_picker::ParticipantPicker;
+use crate::models::live::ReceivedSnapshot;
+use crate::models::user_state::StateField;
 use crate::services::api_service::ApiService;
 use leptos::prelude::*;
 use leptos::task::spawn_local;
@@ -11,105 +20,297 @@ use leptos::task::spawn_local;
 pub fn App() -> impl IntoView {
     // Main state signals
     let is_logged_in = RwSignal::new(false);
-    let current_state = RwSignal::new(UserState::default());
+    let login_error = RwSignal::new(None::<String>);
+    let username = RwSignal::new(String::new());
+    let role = RwSignal::new(Role::Participant);
+    // Whoever is being coded, once logged in or picked
+    let current_state = RwSignal::new(None::<UserState>);
+    // No fields until the server's codebook arrives
+    let codebook = RwSignal::new(Codebook { fields: Vec::new() });
+    // Observers (and admins) code on behalf of someone else
+    let participants = RwSignal::new(Vec::<Username>::new());
+    let coding_for = RwSignal::new(None::<Username>);
+    // The open recording session of whoever is being coded
+    let active_session = RwSignal::new(None::<i64>);
+    // Pushed to admins over the WebSocket
+    let live_snapshot = RwSignal::new(None::<ReceivedSnapshot>);
     let api_service: Arc<ApiService> = Arc::new(ApiService::new());
 
-    // Login logic
+    // Login logic, shared by logging in and registering
     let api_service_login = Arc::clone(&api_service);
-    let handle_login = Callback::new(move |username: String| {
-        let username_clone = username.clone();
+    let authenticate = move |credentials: Credentials, register: bool| {
         let api = Arc::clone(&api_service_login);  // Clone the one owned by this closure
         spawn_local(async move {
-            let mut state = UserState::default();
-            state.username = username_clone.clone();
-
-            // Try to load existing state
-            if let Ok(Some(loaded_state)) = api.load_state(&username_clone).await {
-                current_state.set(loaded_state);
+            let result = if register {
+                api.register(&credentials).await
             } else {
-                current_state.set(state);
+                api.login(&credentials).await
+            };
+
+            let auth = match result {
+                Ok(auth) => auth,
+                Err(err) => {
+                    login_error.set(Some(err.to_string()));
+                    return;
+                }
+            };
+
+            username.set(auth.username.to_string());
+            role.set(auth.role);
+            coding_for.set(None);
+
+            api.connect_live(move |message| match message {
+                ServerMessage::Ack { seq, saved_at, state } => {
+                    log::debug!("Update {} saved at {}", seq, saved_at);
+                    apply_saved(current_state, state);
+                }
+                ServerMessage::Error { seq, error } => {
+                    log::warn!("Update {:?} rejected: {}", seq, error);
+                }
+                ServerMessage::Live(snapshot) => live_snapshot.set(Some(snapshot.into())),
+            });
+
+            match api.load_codebook().await {
+                Ok(loaded) => codebook.set(loaded),
+                Err(err) => log::warn!("Could not load codebook: {}", err),
             }
 
+            match auth.role {
+                Role::Participant => {
+                    let state = UserState::blank(auth.username.clone());
+
+                    // Try to load existing state
+                    match api.load_state().await {
+                        Ok(Some(loaded_state)) => {
+                            if loaded_state.is_recording {
+                                active_session.set(open_session(&api, &auth.username).await);
+                            }
+                            current_state.set(Some(loaded_state));
+                        }
+                        Ok(None) => current_state.set(Some(state)),
+                        Err(err) => {
+                            log::warn!("Could not load state: {}", err);
+                            current_state.set(Some(state));
+                        }
+                    }
+                }
+                Role::Observer => match api.list_participants().await {
+                    Ok(list) => participants.set(list),
+                    Err(err) => log::warn!("Could not load participants: {}", err),
+                },
+                Role::Admin => {}
+            }
+
+            login_error.set(None);
             is_logged_in.set(true);
         });
+    };
+    let authenticate_register = authenticate.clone();
+    let handle_login = Callback::new(move |credentials: Credentials| {
+        authenticate(credentials, false);
+    });
+    let handle_register = Callback::new(move |credentials: Credentials| {
+        authenticate_register(credentials, true);
     });
 
-    // Recording interval setup
-    let interval_handle = RwSignal::new(None::<IntervalHandle>);
+    // Switching participants leaves the previous one's recording to the server
+    let api_service_select = Arc::clone(&api_service);
+    let select_participant = Callback::new(move |participant: String| {
+        // The names on offer are valid, so this is the "select a participant" entry
+        let Ok(participant) = Username::parse(&participant) else {
+            coding_for.set(None);
+            return;
+        };
 
-    // Start/stop recording function
-    let api_service_recording = Arc::clone(&api_service); 
-    let toggle_recording = Callback::new(move |start: bool| {
-        let mut state = current_state.get();
-        state.is_recording = start;
-        current_state.set(state);
+        coding_for.set(Some(participant.clone()));
+        active_session.set(None);
+        let api = Arc::clone(&api_service_select);
+        spawn_local(async move {
+            let state = match api.load_state_for(&participant).await {
+                Ok(Some(loaded_state)) => {
+                    if loaded_state.is_recording {
+                        active_session.set(open_session(&api, &participant).await);
+                    }
+                    loaded_state
+                }
+                _ => UserState::blank(participant),
+            };
+            current_state.set(Some(state));
+        });
+    });
 
+    // Start/stop recording opens and closes a recording session; the server takes the samples
+    let api_service_recording = Arc::clone(&api_service);
+    let toggle_recording = Callback::new(move |start: bool| {
+        let api = Arc::clone(&api_service_recording); // Clone the one owned by this closure
         if start {
-            let api = Arc::clone(&api_service_recording); // Clone the one owned by this closure
-            let handle = set_interval_with_handle(
-                move || {
-                    let state = current_state.get();
-                    let state_clone = state.clone();
-                    let api_clone = Arc::clone(&api);
-                    spawn_local(async move {
-                        if let Ok(()) = api_clone.save_state(&state_clone).await {
-                            let timestamp =
-                                js_sys::Date::new_0().to_iso_string().as_string().unwrap();
-                            let data_summary = format!(
-                                "Text: {}, Categories: {}, {}, {}, {}",
-                                state_clone.text_entry,
-                                state_clone.category1,
-                                state_clone.category2,
-                                state_clone.category3,
-                                state_clone.category4
-                            );
-
-                            let mut updated_state = current_state.get();
-                            updated_state.last_saved = Some(timestamp);
-                            updated_state.last_data = Some(data_summary);
-                            current_state.set(updated_state);
-                        }
-                    });
-                },
-                std::time::Duration::from_secs(5),
-            )
-            .unwrap();
-
-            interval_handle.set(Some(handle));
-        } else if let Some(handle) = interval_handle.get() {
-            handle.clear();
-            interval_handle.set(None);
+            spawn_local(async move {
+                match api.start_session(coding_for.get().as_ref()).await {
+                    Ok(session) => {
+                        active_session.set(Some(session.id));
+                        set_recording(current_state, true);
+                    }
+                    Err(err) => log::warn!("Could not start recording: {}", err),
+                }
+            });
+        } else {
+            set_recording(current_state, false);
+            if let Some(id) = active_session.get() {
+                active_session.set(None);
+                spawn_local(async move {
+                    if let Err(err) = api.stop_session(id).await {
+                        log::warn!("Could not stop recording: {}", err);
+                    }
+                });
+            }
         }
     });
 
-    // Function to update a field in the state
-    let update_field = Callback::new(move |(field, value): (&'static str, String)| {
-        let mut state = current_state.get();
-        match field {
-            "text_entry" => state.text_entry = value,
-            "category1" => state.category1 = value,
-            "category2" => state.category2 = value,
-            "category3" => state.category3 = value,
-            "category4" => state.category4 = value,
-            _ => {}
+    // Logout stops any recording and drops the session token
+    let api_service_logout = Arc::clone(&api_service);
+    let handle_logout = Callback::new(move |_: ()| {
+        let api = Arc::clone(&api_service_logout);
+        spawn_local(async move {
+            if let Some(id) = active_session.get_untracked()
+                && let Err(err) = api.stop_session(id).await
+            {
+                log::warn!("Could not stop recording: {}", err);
+            }
+            if let Err(err) = api.logout().await {
+                log::warn!("Logout request failed: {}", err);
+            }
+            current_state.set(None);
+            active_session.set(None);
+            live_snapshot.set(None);
+            participants.set(Vec::new());
+            coding_for.set(None);
+            is_logged_in.set(false);
+        });
+    });
+
+    // Every edit is pushed to the server, which samples whatever it last received
+    let api_service_update = Arc::clone(&api_service);
+    let update_field = Callback::new(move |(field, value): (StateField, String)| {
+        let Some(mut state) = current_state.get() else {
+            return;
+        };
+        match &field {
+            StateField::TextEntry => state.text_entry = value.clone(),
+            StateField::Category(key) => {
+                state.categories.insert(key.clone(), value.clone());
+            }
         }
-        current_state.set(state);
+        current_state.set(Some(state.clone()));
+
+        let api = Arc::clone(&api_service_update);
+        spawn_local(async move {
+            let target = coding_for.get_untracked();
+            match api.push_field(target.as_ref(), &field, &value, &state).await {
+                Ok(Some(saved)) => apply_saved(current_state, saved),
+                // Sent over the WebSocket; the acknowledgement arrives separately
+                Ok(None) => {}
+                Err(err) => log::warn!("Could not save state: {}", err),
+            }
+        });
     });
 
+    // Each role gets its own screen
+    let api_service_admin = Arc::clone(&api_service);
+    let role_screen = move || match role.get() {
+        Role::Admin => view! {
+            <AdminScreen
+                api=Arc::clone(&api_service_admin)
+                username=username
+                codebook=codebook
+                live=live_snapshot
+                on_logout=handle_logout
+            />
+        }
+        .into_any(),
+        Role::Observer => view! {
+            <div class="observer-bar">
+                <ParticipantPicker
+                    participants=participants
+                    selected=coding_for
+                    on_select=select_participant
+                />
+                <button class="logout-button" on:click=move |_| handle_logout.run(())>
+                    "Log Out"
+                </button>
+            </div>
+            <Show when=move || coding_for.get().is_some()>
+                <DataEntryScreen
+                    state=current_state
+                    codebook=codebook
+                    on_toggle_recording=toggle_recording
+                    on_update_field=update_field
+                />
+            </Show>
+        }
+        .into_any(),
+        Role::Participant => view! {
+            <DataEntryScreen
+                state=current_state
+                codebook=codebook
+                on_toggle_recording=toggle_recording
+                on_update_field=update_field
+                on_logout=handle_logout
+            />
+        }
+        .into_any(),
+    };
+
     view! {
         <div>
             <Show
                 when=move || is_logged_in.get()
                 fallback=move || view! {
-                    <LoginScreen on_login=handle_login />
+                    <LoginScreen
+                        on_login=handle_login
+                        on_register=handle_register
+                        error=login_error
+                    />
                 }
             >
-                <DataEntryScreen
-                    state=current_state
-                    on_toggle_recording=toggle_recording
-                    on_update_field=update_field
-                />
+                {role_screen.clone()}
             </Show>
         </div>
     }
 }
+
+/// The id of `username`'s open recording session, if there is one.
+async fn open_session(api: &ApiService, username: &str) -> Option<i64> {
+    match api.list_sessions(username).await {
+        Ok(sessions) => sessions
+            .into_iter()
+            .find(|session| session.ended_at.is_none())
+            .map(|session| session.id),
+        Err(err) => {
+            log::warn!("Could not load recording sessions: {}", err);
+            None
+        }
+    }
+}
+
+/// Flips the recording flag of the state on screen, if there is one.
+fn set_recording(current_state: RwSignal<Option<UserState>>, recording: bool) {
+    current_state.update(|state| {
+        if let Some(state) = state {
+            state.is_recording = recording;
+        }
+    });
+}
+
+/// Takes over the fields the server owns from a state it just stored: the recording flag
+/// and the last sample. Ignored if the user on screen has changed in the meantime.
+fn apply_saved(current_state: RwSignal<Option<UserState>>, saved: UserState) {
+    current_state.update(|state| {
+        if let Some(state) = state
+            && state.username == saved.username
+        {
+            state.is_recording = saved.is_recording;
+            state.last_saved = saved.last_saved;
+            state.last_data = saved.last_data;
+        }
+    });
+}
@@ -1,88 +1,69 @@
 use leptos::prelude::*;
-use crate::models::user_state::UserState;
+use shared::codebook::Codebook;
+use shared::user_state::UserState;
+use crate::models::user_state::StateField;
 use super::dropdown_select::DropdownSelect;
 
 #[component]
 pub fn DataEntryScreen(
-    state: RwSignal<UserState>,
+    #[prop(into)] state: Signal<Option<UserState>>,
+    #[prop(into)] codebook: Signal<Codebook>,
     on_toggle_recording: Callback<bool>,
-    on_update_field: Callback<(&'static str, String)>,
+    on_update_field: Callback<(StateField, String)>,
+    #[prop(optional, into)] on_logout: Option<Callback<()>>,
 ) -> impl IntoView {
-    // Category options (replace these with your actual categories)
-    let category1_options = vec!["Option 1A", "Option 1B", "Option 1C"];
-    let category2_options = vec!["Option 2A", "Option 2B", "Option 2C"];
-    let category3_options = vec!["Option 3A", "Option 3B", "Option 3C"];
-    let category4_options = vec!["Option 4A", "Option 4B", "Option 4C"];
-    
-    // Create individual callbacks for each dropdown
-    let on_category1_change = Callback::new(move |v: String| {
-        on_update_field.run(("category1", v));
-    });
-    
-    let on_category2_change = Callback::new(move |v: String| {
-        on_update_field.run(("category2", v));
-    });
-    
-    let on_category3_change = Callback::new(move |v: String| {
-        on_update_field.run(("category3", v));
-    });
-    
-    let on_category4_change = Callback::new(move |v: String| {
-        on_update_field.run(("category4", v));
-    });
-    
+    let username = move || read(state, |state| state.username.to_string());
+
     view! {
         <div class="data-entry-container">
             <h1>"Data Logger"</h1>
-            <p class="welcome-message">"Welcome, " {move || state.get().username}</p>
+            <p class="welcome-message">"Welcome, " {username}</p>
+            {on_logout.map(|on_logout| view! {
+                <button class="logout-button" on:click=move |_| on_logout.run(())>"Log Out"</button>
+            })}
             
             <div class="input-container">
                 <div class="input-group">
                     <label for="text-entry">"Text Entry:"</label>
                     <textarea 
                         id="text-entry"
-                        prop:value=move || state.get().text_entry
-                        on:input=move |ev| on_update_field.run(("text_entry", event_target_value(&ev)))
+                        prop:value=move || read(state, |state| state.text_entry.clone())
+                        on:input=move |ev| on_update_field.run((StateField::TextEntry, event_target_value(&ev)))
                     ></textarea>
                 </div>
                 
                 <div class="dropdown-container">
-                    <DropdownSelect 
-                        id="category1"
-                        label="Category 1"
-                        options=category1_options
-                        value=Memo::new(move |_| state.get().category1.clone())
-                        on_change=on_category1_change
-                    />
-                    
-                    <DropdownSelect 
-                        id="category2"
-                        label="Category 2"
-                        options=category2_options
-                        value=Memo::new(move |_| state.get().category2.clone())
-                        on_change=on_category2_change
-                    />
-                    
-                    <DropdownSelect 
-                        id="category3"
-                        label="Category 3"
-                        options=category3_options
-                        value=Memo::new(move |_| state.get().category3.clone())
-                        on_change=on_category3_change
-                    />
-                    
-                    <DropdownSelect 
-                        id="category4"
-                        label="Category 4"
-                        options=category4_options
-                        value=Memo::new(move |_| state.get().category4.clone())
-                        on_change=on_category4_change
-                    />
+                    // One dropdown per codebook field
+                    <For
+                        each=move || codebook.get().fields
+                        key=|field| field.key.clone()
+                        let:field
+                    >
+                        {
+                            let value_key = field.key.clone();
+                            let change_key = field.key.clone();
+                            view! {
+                                <DropdownSelect
+                                    id=field.key.clone()
+                                    label=field.label.clone()
+                                    options=field.options.clone()
+                                    value=Memo::new(move |_| {
+                                        read(state, |state| {
+                                            state.categories.get(&value_key).cloned().unwrap_or_default()
+                                        })
+                                    })
+                                    on_change=Callback::new(move |v: String| {
+                                        on_update_field.run((StateField::Category(change_key.clone()), v));
+                                    })
+                                />
+                            }
+                        }
+                    </For>
                 </div>
                 
                 <div class="button-container">
                     {move || {
-                        let is_recording = state.get().is_recording;
+                        let is_recording = read(state, |state| state.is_recording);
                         view! {
                             <button 
                                 class="start-button"
@@ -108,10 +89,7 @@ pub fn DataEntryScreen(
             <div class="status-container">
                 <h3>"Recording Status"</h3>
                 <Show
-                    when=move || {
-                        let current = state.get();
-                        current.last_saved.is_some() && current.last_data.is_some()
-                    }
+                    when=move || read(state, |state| state.last_saved.is_some() && state.last_data.is_some())
                     fallback=move || {
                         view! {
                             <p class="no-data">"No data saved yet"</p>
@@ -119,11 +97,8 @@ pub fn DataEntryScreen(
                     }
                 >
                     {move || {
-                        let current = state.get();
-                        // We can safely unwrap here because the Show component only renders this
-                        // when both last_saved and last_data are Some
-                        let timestamp = current.last_saved.clone().unwrap();
-                        let data = current.last_data.clone().unwrap();
+                        let timestamp = read(state, |state| state.last_saved.clone().unwrap_or_default());
+                        let data = read(state, |state| state.last_data.clone().unwrap_or_default());
                         
                         view! {
                             <div class="status-info">
@@ -136,4 +111,9 @@ pub fn DataEntryScreen(
             </div>
         </div>
     }
-}
\ No newline at end of file
+}
+
+/// `field` of the state on screen, or its default while there is none.
+fn read<T: Default>(state: Signal<Option<UserState>>, field: impl Fn(&UserState) -> T) -> T {
+    state.with(|state| state.as_ref().map(field).unwrap_or_default())
+}
@@ -3,27 +3,28 @@ use leptos::prelude::*;
 
 #[component]
 pub fn DropdownSelect(
-    id: &'static str,
-    label: &'static str,
-    options: Vec<&'static str>,
+    #[prop(into)] id: String,
+    #[prop(into)] label: String,
+    options: Vec<String>,
     value: Memo<String>,
     #[prop(into)] on_change: Callback<String>,
 ) -> impl IntoView {
     view! {
         <div class="dropdown-group">
-            <label for={id}>{label}</label>
+            <label for=id.clone()>{label.clone()}</label>
             <select
-                id={id}
+                id=id
                 prop:value=move || value.get()
                 on:change=move |ev| on_change.run(event_target_value(&ev))
             >
                 <option value="">"-- Select a " {label} " --"</option>
-                {options.iter().map(|option| {
+                {options.into_iter().map(|option| {
+                    let text = option.clone();
                     view! {
-                        <option value={*option}>{*option}</option>
+                        <option value=option>{text}</option>
                     }
                 }).collect_view()}
             </select>
         </div>
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,126 @@
+use std::sync::Arc;
+
+use chrono::DateTime;
+use leptos::prelude::*;
+use leptos::task::spawn_local;
+
+use shared::codebook::Codebook;
+use shared::live::LiveRecording;
+
+use crate::models::live::ReceivedSnapshot;
+use crate::services::api_service::ApiService;
+
+/// Everyone who is recording right now, kept current by `live` pushes over `/api/ws`.
+#[component]
+pub fn LiveDashboard(
+    api: Arc<ApiService>,
+    snapshot: RwSignal<Option<ReceivedSnapshot>>,
+    #[prop(into)] codebook: Signal<Codebook>,
+) -> impl IntoView {
+    // Pushes only arrive over the WebSocket; fetch once so the table isn't empty until then
+    api.watch_live();
+    let api_initial = Arc::clone(&api);
+    spawn_local(async move {
+        match api_initial.live_recordings().await {
+            Ok(initial) if snapshot.get_untracked().is_none() => snapshot.set(Some(initial.into())),
+            Ok(_) => {}
+            Err(err) => log::warn!("Could not load live recordings: {}", err),
+        }
+    });
+
+    // Elapsed times tick along between pushes
+    let now_ms = RwSignal::new(js_sys::Date::now());
+    if let Ok(handle) = set_interval_with_handle(
+        move || now_ms.set(js_sys::Date::now()),
+        std::time::Duration::from_secs(1),
+    ) {
+        on_cleanup(move || handle.clear());
+    }
+
+    let rows = move || {
+        snapshot
+            .get()
+            .map(|received| received.snapshot.recordings)
+            .unwrap_or_default()
+    };
+    // Server time now, estimated from the latest snapshot
+    let server_now_ms = move || {
+        let now = now_ms.get();
+        snapshot.get().and_then(|received| {
+            parse_ms(&received.snapshot.generated_at).map(|generated| generated + (now - received.received_at_ms))
+        })
+    };
+
+    view! {
+        <Show
+            when=move || !rows().is_empty()
+            fallback=|| view! { <p>"Nobody is recording."</p> }
+        >
+            <table class="admin-table live-table">
+                <thead>
+                    <tr>
+                        <th>"User"</th>
+                        <th>"Session"</th>
+                        {move || codebook.get().fields.into_iter().map(|field| view! {
+                            <th>{field.label}</th>
+                        }).collect_view()}
+                        <th>"Text"</th>
+                        <th>"Last sample"</th>
+                        <th>"Duration"</th>
+                    </tr>
+                </thead>
+                <tbody>
+                    {move || rows().into_iter().map(|recording| {
+                        let values = codebook
+                            .get()
+                            .fields
+                            .iter()
+                            .map(|field| recording.categories.get(&field.key).cloned().unwrap_or_default())
+                            .collect::<Vec<_>>();
+                        let last_sample = elapsed_since(server_now_ms(), recording.last_saved.as_deref());
+                        let duration = elapsed_since(server_now_ms(), Some(&recording.started_at));
+                        view! {
+                            <tr>
+                                <td>{recording.username.to_string()}</td>
+                                <td>{session_name(&recording)}</td>
+                                {values.into_iter().map(|value| view! { <td>{value}</td> }).collect_view()}
+                                <td>{recording.text_entry.clone()}</td>
+                                <td>{last_sample.map(|ago| format!("{} ago", ago)).unwrap_or_else(|| "none yet".to_string())}</td>
+                                <td>{duration.unwrap_or_default()}</td>
+                            </tr>
+                        }
+                    }).collect_view()}
+                </tbody>
+            </table>
+        </Show>
+    }
+}
+
+fn session_name(recording: &LiveRecording) -> String {
+    match &recording.label {
+        Some(label) => label.clone(),
+        None => format!("#{}", recording.session_id),
+    }
+}
+
+fn parse_ms(timestamp: &str) -> Option<f64> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|time| time.timestamp_millis() as f64)
+}
+
+fn elapsed_since(now_ms: Option<f64>, timestamp: Option<&str>) -> Option<String> {
+    let elapsed_ms = now_ms? - parse_ms(timestamp?)?;
+    Some(format_duration((elapsed_ms.max(0.0) / 1000.0) as u64))
+}
+
+fn format_duration(seconds: u64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h {:02}m {:02}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
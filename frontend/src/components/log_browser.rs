@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use leptos::prelude::*;
+use leptos::task::spawn_local;
+
+use shared::codebook::Codebook;
+use shared::log_query::{ExportParams, LogQueryParams, SortOrder};
+use shared::user_state::DataLog;
+use super::admin_screen::save_file;
+use crate::services::api_service::ApiService;
+
+const PAGE_SIZE: u32 = 50;
+
+/// Browses `data_logs` history through `GET /api/logs`, one page at a time.
+#[component]
+pub fn LogBrowser(
+    api: Arc<ApiService>,
+    #[prop(into)] codebook: Signal<Codebook>,
+) -> impl IntoView {
+    let logs = RwSignal::new(Vec::<DataLog>::new());
+    let next_cursor = RwSignal::new(None::<String>);
+    let error = RwSignal::new(None::<String>);
+
+    let username = RwSignal::new(String::new());
+    let text = RwSignal::new(String::new());
+    let category = RwSignal::new(String::new());
+    let value = RwSignal::new(String::new());
+    let oldest_first = RwSignal::new(false);
+
+    let non_empty = |signal: RwSignal<String>| {
+        let value = signal.get();
+        (!value.is_empty()).then_some(value)
+    };
+
+    let api_export = Arc::clone(&api);
+
+    // `append` continues from the current cursor instead of starting over
+    let load = Callback::new(move |append: bool| {
+        let filter = LogQueryParams {
+            username: non_empty(username),
+            category: non_empty(category),
+            value: non_empty(value),
+            q: non_empty(text),
+            cursor: if append { next_cursor.get() } else { None },
+            limit: Some(PAGE_SIZE),
+            sort: if oldest_first.get() { SortOrder::Asc } else { SortOrder::Desc },
+            ..LogQueryParams::default()
+        };
+        let api = Arc::clone(&api);
+        spawn_local(async move {
+            match api.query_logs(&filter).await {
+                Ok(page) => {
+                    if append {
+                        logs.update(|logs| logs.extend(page.items));
+                    } else {
+                        logs.set(page.items);
+                    }
+                    next_cursor.set(page.next_cursor);
+                    error.set(None);
+                }
+                Err(err) => error.set(Some(err.to_string())),
+            }
+        });
+    });
+
+    load.run(false);
+
+    // Exports cover the whole history of the selected user, not just the loaded pages
+    let include_id = RwSignal::new(false);
+    let export = move |_| {
+        let options = ExportParams {
+            users: non_empty(username),
+            include_id: include_id.get(),
+            ..ExportParams::default()
+        };
+        let filename = format!("{}.csv", options.users.as_deref().unwrap_or("export"));
+        let api = Arc::clone(&api_export);
+        spawn_local(async move {
+            match api.export_logs(&options).await {
+                Ok(contents) => {
+                    if save_file(&filename, &contents).is_err() {
+                        error.set(Some("Could not save the export".to_string()));
+                    }
+                }
+                Err(err) => error.set(Some(err.to_string())),
+            }
+        });
+    };
+
+    view! {
+        <div class="log-browser">
+            <div class="log-filter">
+                <input
+                    type="text"
+                    placeholder="Username"
+                    prop:value=move || username.get()
+                    on:input=move |ev| username.set(event_target_value(&ev))
+                />
+                <input
+                    type="text"
+                    placeholder="Text contains"
+                    prop:value=move || text.get()
+                    on:input=move |ev| text.set(event_target_value(&ev))
+                />
+                <select on:change=move |ev| category.set(event_target_value(&ev))>
+                    <option value="">"Any category"</option>
+                    {move || codebook.get().fields.into_iter().map(|field| view! {
+                        <option value=field.key>{field.label}</option>
+                    }).collect_view()}
+                </select>
+                <input
+                    type="text"
+                    placeholder="Category value"
+                    prop:value=move || value.get()
+                    on:input=move |ev| value.set(event_target_value(&ev))
+                />
+                <label class="inline-label">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || oldest_first.get()
+                        on:change=move |ev| oldest_first.set(event_target_checked(&ev))
+                    />
+                    "Oldest first"
+                </label>
+                <button on:click=move |_| load.run(false)>"Search"</button>
+                <label class="inline-label">
+                    <input
+                        type="checkbox"
+                        prop:checked=move || include_id.get()
+                        on:change=move |ev| include_id.set(event_target_checked(&ev))
+                    />
+                    "With ids"
+                </label>
+                <button on:click=export>"Export CSV"</button>
+            </div>
+
+            <Show when=move || error.get().is_some()>
+                <p class="login-error">{move || error.get().unwrap_or_default()}</p>
+            </Show>
+
+            <table class="admin-table">
+                <thead>
+                    <tr>
+                        <th>"Time"</th><th>"User"</th><th>"Text"</th><th>"Categories"</th>
+                    </tr>
+                </thead>
+                <tbody>
+                    <For
+                        each=move || logs.get()
+                        key=|log| log.id
+                        let:log
+                    >
+                        <tr>
+                            <td>{log.timestamp.clone()}</td>
+                            <td>{log.username.to_string()}</td>
+                            <td>{log.text_entry.clone()}</td>
+                            <td>{format_categories(&log.categories)}</td>
+                        </tr>
+                    </For>
+                </tbody>
+            </table>
+
+            <Show when=move || next_cursor.get().is_some()>
+                <button class="load-more" on:click=move |_| load.run(true)>"Load more"</button>
+            </Show>
+        </div>
+    }
+}
+
+fn format_categories(categories: &BTreeMap<String, String>) -> String {
+    categories
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(key, value)| format!("{}: {}", key, value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -1,23 +1,55 @@
 use leptos::*;
 use leptos::prelude::*;
+use shared::user::Credentials;
+use shared::username::Username;
 
 #[component]
 pub fn LoginScreen(
-    #[prop(into)] on_login: Callback<String>,
+    #[prop(into)] on_login: Callback<Credentials>,
+    #[prop(into)] on_register: Callback<Credentials>,
+    #[prop(into)] error: Signal<Option<String>>,
 ) -> impl IntoView {
     let username = RwSignal::new(String::new());
+    let password = RwSignal::new(String::new());
+    let is_registering = RwSignal::new(false);
+    // A name the server would refuse anyway is caught before sending it
+    let invalid_username = RwSignal::new(None::<String>);
+    let message = move || invalid_username.get().or_else(|| error.get());
     
     let handle_submit = move |ev: ev::SubmitEvent| {
         ev.prevent_default();
-        if !username.get().is_empty() {
-            on_login.run(username.get());
+        if username.get().is_empty() || password.get().is_empty() {
+            return;
+        }
+
+        let credentials = match Username::parse(&username.get()) {
+            Ok(name) => Credentials {
+                username: name,
+                password: password.get(),
+            },
+            Err(err) => {
+                invalid_username.set(Some(err.to_string()));
+                return;
+            }
+        };
+        invalid_username.set(None);
+        if is_registering.get() {
+            on_register.run(credentials);
+        } else {
+            on_login.run(credentials);
         }
     };
     
     view! {
         <div class="login-container">
             <h1>"Welcome to Data Logger"</h1>
-            <p>"Please enter your username to continue"</p>
+            <p>
+                {move || if is_registering.get() {
+                    "Choose a username and password to create an account"
+                } else {
+                    "Please log in to continue"
+                }}
+            </p>
             
             <form on:submit=handle_submit>
                 <div class="input-group">
@@ -25,13 +57,40 @@ pub fn LoginScreen(
                     <input 
                         id="username"
                         type="text"
+                        autocomplete="username"
                         prop:value=move || username.get()
                         on:input=move |ev| username.set(event_target_value(&ev))
                         required
                     />
                 </div>
-                <button type="submit">"Login"</button>
+                <div class="input-group">
+                    <label for="password">"Password:"</label>
+                    <input
+                        id="password"
+                        type="password"
+                        autocomplete=move || if is_registering.get() { "new-password" } else { "current-password" }
+                        prop:value=move || password.get()
+                        on:input=move |ev| password.set(event_target_value(&ev))
+                        required
+                    />
+                </div>
+                <Show when=move || message().is_some()>
+                    <p class="login-error">{move || message().unwrap_or_default()}</p>
+                </Show>
+                <button type="submit">
+                    {move || if is_registering.get() { "Create Account" } else { "Login" }}
+                </button>
             </form>
+            <button
+                class="link-button"
+                on:click=move |_| is_registering.update(|registering| *registering = !*registering)
+            >
+                {move || if is_registering.get() {
+                    "Already have an account? Log in"
+                } else {
+                    "New here? Create an account"
+                }}
+            </button>
         </div>
     }
-}
\ No newline at end of file
+}
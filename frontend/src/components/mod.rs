@@ -1,4 +1,8 @@
+pub mod admin_screen;
 pub mod app;
 pub mod login_screen;
 pub mod data_entry_screen;
-pub mod dropdown_select;
\ No newline at end of file
+pub mod dropdown_select;
+pub mod live_dashboard;
+pub mod log_browser;
+pub mod participant_picker;
\ No newline at end of file
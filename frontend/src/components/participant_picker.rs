@@ -0,0 +1,33 @@
+use leptos::prelude::*;
+use shared::username::Username;
+
+/// Lets observers and admins choose whose state they are coding.
+#[component]
+pub fn ParticipantPicker(
+    #[prop(into)] participants: Signal<Vec<Username>>,
+    #[prop(into)] selected: Signal<Option<Username>>,
+    #[prop(into)] on_select: Callback<String>,
+) -> impl IntoView {
+    view! {
+        <div class="participant-picker">
+            <label for="participant">"Coding for:"</label>
+            <select
+                id="participant"
+                prop:value=move || selected.get().map(String::from).unwrap_or_default()
+                on:change=move |ev| on_select.run(event_target_value(&ev))
+            >
+                <option value="">"-- Select a participant --"</option>
+                <For
+                    each=move || participants.get()
+                    key=|participant| participant.clone()
+                    let:participant
+                >
+                    <option value=participant.to_string()>{participant.to_string()}</option>
+                </For>
+            </select>
+            <Show when=move || participants.get().is_empty()>
+                <p class="no-data">"No participants have been assigned to you yet"</p>
+            </Show>
+        </div>
+    }
+}
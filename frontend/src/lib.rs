@@ -6,6 +6,8 @@ mod components;
 mod models;
 mod services;
 
-fn main() {
+/// Runs as soon as the WASM module is loaded by the site's `index.html`.
+#[wasm_bindgen::prelude::wasm_bindgen(start)]
+pub fn start() {
     mount_to_body(|| view! { <App /> })
 }
\ No newline at end of file
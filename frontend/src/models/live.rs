@@ -0,0 +1,18 @@
+use shared::live::LiveSnapshot;
+
+/// A `live` snapshot and the browser time it arrived, in milliseconds; lets elapsed times
+/// follow the server clock.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReceivedSnapshot {
+    pub snapshot: LiveSnapshot,
+    pub received_at_ms: f64,
+}
+
+impl From<LiveSnapshot> for ReceivedSnapshot {
+    fn from(snapshot: LiveSnapshot) -> Self {
+        Self {
+            snapshot,
+            received_at_ms: js_sys::Date::now(),
+        }
+    }
+}
@@ -1 +1,2 @@
-pub mod user_state;
\ No newline at end of file
+pub mod live;
+pub mod user_state;
@@ -1,40 +1,356 @@
-use reqwest::Client;
-use crate::models::user_state::UserState;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::{Arc, RwLock};
+
+use reqwest::{Client, RequestBuilder, Response};
+use send_wrapper::SendWrapper;
+use serde::de::DeserializeOwned;
+
+use shared::codebook::Codebook;
+use shared::live::LiveSnapshot;
+use shared::log_query::{ExportParams, LogPage, LogQueryParams};
+use shared::problem::{codes, ProblemDetails};
+use shared::recording_session::{RecordingSession, StartSession};
+use shared::user::{AuthResponse, Assignment, Credentials, Role, RoleUpdate, UserSummary};
+use shared::user_state::UserState;
+use shared::username::Username;
+use shared::ws_message::ServerMessage;
+
+use super::ws_transport::WsTransport;
+use crate::models::user_state::StateField;
+
+#[derive(Debug)]
+pub enum ApiError {
+    /// The server answered, but refused the request (bad password, missing permission, ...)
+    Rejected(ProblemDetails),
+    Network(reqwest::Error),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // Failures on the server's side are worth reporting, so name the request
+            ApiError::Rejected(ProblemDetails {
+                status: 500..,
+                detail,
+                request_id: Some(request_id),
+                ..
+            }) => write!(f, "{} (request {})", detail, request_id),
+            ApiError::Rejected(problem) => write!(f, "{}", problem.detail),
+            ApiError::Network(err) => write!(f, "Could not reach the server: {}", err),
+        }
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(err: reqwest::Error) -> Self {
+        ApiError::Network(err)
+    }
+}
+
+/// The server that served the page, which also answers the API, e.g. `https://study.example.org`.
+fn page_origin() -> String {
+    web_sys::window()
+        .and_then(|window| window.location().origin().ok())
+        .unwrap_or_else(|| "http://localhost:3000".to_string())
+}
 
 #[derive(Clone)]
 pub struct ApiService {
     client: Client,
     base_url: String,
+    token: Arc<RwLock<Option<String>>>,
+    /// Live channel for field changes; the browser is single-threaded, hence the wrapper
+    live: SendWrapper<Rc<WsTransport>>,
 }
 
 impl ApiService {
     pub fn new() -> Self {
+        let base_url = format!("{}/api", page_origin());
+        let ws_url = format!("{}/ws", base_url.replacen("http", "ws", 1));
         Self {
             client: Client::new(),
-            base_url: "http://localhost:3000/api".to_string(),
+            base_url,
+            token: Arc::new(RwLock::new(None)),
+            live: SendWrapper::new(WsTransport::new(ws_url)),
         }
     }
 
-    pub async fn save_state(&self, state: &UserState) -> Result<(), reqwest::Error> {
-        self.client
-            .post(&format!("{}/state", self.base_url))
-            .json(state)
+    /// Opens the WebSocket for the logged-in user; it reconnects by itself until logout.
+    pub fn connect_live(&self, on_message: impl Fn(ServerMessage) + 'static) {
+        if let Some(token) = self.token.read().unwrap().clone() {
+            self.live.connect(token, on_message);
+        }
+    }
+
+    /// Has the WebSocket deliver live dashboard snapshots to `connect_live`'s handler.
+    pub fn watch_live(&self) {
+        self.live.subscribe_live();
+    }
+
+    pub async fn live_recordings(&self) -> Result<LiveSnapshot, ApiError> {
+        self.get_json(format!("{}/admin/live", self.base_url)).await
+    }
+
+    /// Pushes one field change over the WebSocket, whose acknowledgement arrives through
+    /// `connect_live`'s handler. While the socket is down the whole `state` goes over
+    /// HTTP instead, and what the server stored is returned directly.
+    pub async fn push_field(
+        &self,
+        target: Option<&Username>,
+        field: &StateField,
+        value: &str,
+        state: &UserState,
+    ) -> Result<Option<UserState>, ApiError> {
+        let sent = self.live.send_update(
+            target.cloned(),
+            field.key().to_string(),
+            value.to_string(),
+        );
+        if sent {
+            return Ok(None);
+        }
+
+        let saved = match target {
+            Some(participant) => self.save_state_for(participant, state).await?,
+            None => self.save_state(state).await?,
+        };
+        Ok(Some(saved))
+    }
+
+    pub async fn login(&self, credentials: &Credentials) -> Result<AuthResponse, ApiError> {
+        self.authenticate("login", credentials).await
+    }
+
+    pub async fn register(&self, credentials: &Credentials) -> Result<AuthResponse, ApiError> {
+        self.authenticate("register", credentials).await
+    }
+
+    pub async fn logout(&self) -> Result<(), reqwest::Error> {
+        let result = self
+            .authorized(self.client.post(format!("{}/auth/logout", self.base_url)))
             .send()
-            .await?;
+            .await;
+        // Forget the token even if the server could not be reached
+        *self.token.write().unwrap() = None;
+        self.live.disconnect();
+        result?;
         Ok(())
     }
 
-    pub async fn load_state(&self, username: &str) -> Result<Option<UserState>, reqwest::Error> {
-        let response = self.client
-            .get(&format!("{}/state/{}", self.base_url, username))
+    /// Pushes the current state; the server answers with what it stored.
+    pub async fn save_state(&self, state: &UserState) -> Result<UserState, ApiError> {
+        self.post_state(format!("{}/state", self.base_url), state).await
+    }
+
+    pub async fn load_state(&self) -> Result<Option<UserState>, ApiError> {
+        self.fetch_state(format!("{}/state", self.base_url)).await
+    }
+
+    /// Saves a participant's state on their behalf (observers and admins).
+    pub async fn save_state_for(&self, username: &str, state: &UserState) -> Result<UserState, ApiError> {
+        self.post_state(format!("{}/state/{}", self.base_url, username), state).await
+    }
+
+    pub async fn load_state_for(&self, username: &str) -> Result<Option<UserState>, ApiError> {
+        self.fetch_state(format!("{}/state/{}", self.base_url, username)).await
+    }
+
+    /// Starts recording for `username`, or for the logged-in user if `None`.
+    pub async fn start_session(&self, username: Option<&Username>) -> Result<RecordingSession, ApiError> {
+        let request = StartSession {
+            username: username.cloned(),
+            label: None,
+        };
+        let response = self
+            .authorized(self.client.post(format!("{}/sessions", self.base_url)))
+            .json(&request)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(ApiError::Rejected(problem(response).await));
+        }
+        Ok(response.json().await?)
+    }
+
+    pub async fn stop_session(&self, id: i64) -> Result<(), ApiError> {
+        let response = self
+            .authorized(self.client.post(format!("{}/sessions/{}/stop", self.base_url, id)))
+            .send()
+            .await?;
+        expect_success(response).await
+    }
+
+    pub async fn list_sessions(&self, username: &str) -> Result<Vec<RecordingSession>, ApiError> {
+        self.get_json(format!("{}/sessions?username={}", self.base_url, username)).await
+    }
+
+    pub async fn load_codebook(&self) -> Result<Codebook, ApiError> {
+        self.get_json(format!("{}/codebook", self.base_url)).await
+    }
+
+    pub async fn list_participants(&self) -> Result<Vec<Username>, ApiError> {
+        self.get_json(format!("{}/participants", self.base_url)).await
+    }
+
+    pub async fn list_users(&self) -> Result<Vec<UserSummary>, ApiError> {
+        self.get_json(format!("{}/admin/users", self.base_url)).await
+    }
+
+    pub async fn set_role(&self, username: &str, role: Role) -> Result<(), ApiError> {
+        let response = self
+            .authorized(self.client.put(format!("{}/admin/users/{}/role", self.base_url, username)))
+            .json(&RoleUpdate { role })
+            .send()
+            .await?;
+        expect_success(response).await
+    }
+
+    pub async fn list_assignments(&self) -> Result<Vec<Assignment>, ApiError> {
+        self.get_json(format!("{}/admin/assignments", self.base_url)).await
+    }
+
+    pub async fn assign(&self, assignment: &Assignment) -> Result<(), ApiError> {
+        let response = self
+            .authorized(self.client.post(format!("{}/admin/assignments", self.base_url)))
+            .json(assignment)
             .send()
             .await?;
-        
+        expect_success(response).await
+    }
+
+    pub async fn unassign(&self, assignment: &Assignment) -> Result<(), ApiError> {
+        let response = self
+            .authorized(self.client.delete(format!("{}/admin/assignments", self.base_url)))
+            .json(assignment)
+            .send()
+            .await?;
+        expect_success(response).await
+    }
+
+    pub async fn query_logs(&self, filter: &LogQueryParams) -> Result<LogPage, ApiError> {
+        let response = self
+            .authorized(self.client.get(format!("{}/logs", self.base_url)))
+            .query(filter)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(ApiError::Rejected(problem(response).await));
+        }
+        Ok(response.json().await?)
+    }
+
+    pub async fn export_logs(&self, options: &ExportParams) -> Result<String, ApiError> {
+        let response = self
+            .authorized(self.client.get(format!("{}/logs/export", self.base_url)))
+            .query(options)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(ApiError::Rejected(problem(response).await));
+        }
+        Ok(response.text().await?)
+    }
+
+    /// Fetches a user's newest CSV file, with the name the server gives it. Rotated
+    /// files may arrive gzipped.
+    pub async fn download_csv(&self, username: &str) -> Result<(String, Vec<u8>), ApiError> {
+        let response = self
+            .authorized(self.client.get(format!("{}/admin/csv/{}", self.base_url, username)))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(ApiError::Rejected(problem(response).await));
+        }
+        let filename = response
+            .headers()
+            .get(reqwest::header::CONTENT_DISPOSITION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split("filename=\"").nth(1))
+            .and_then(|rest| rest.split('"').next())
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{}.csv", username));
+        Ok((filename, response.bytes().await?.to_vec()))
+    }
+
+    /// `None` if nothing has been saved for the user yet.
+    async fn fetch_state(&self, url: String) -> Result<Option<UserState>, ApiError> {
+        let response = self.authorized(self.client.get(url)).send().await?;
         if response.status().is_success() {
-            let state = response.json::<UserState>().await?;
-            Ok(Some(state))
-        } else {
-            Ok(None)
+            return Ok(Some(response.json::<UserState>().await?));
+        }
+        match problem(response).await {
+            problem if problem.code == codes::NOT_FOUND => Ok(None),
+            problem => Err(ApiError::Rejected(problem)),
+        }
+    }
+
+    async fn post_state(&self, url: String, state: &UserState) -> Result<UserState, ApiError> {
+        let response = self.authorized(self.client.post(url)).json(state).send().await?;
+        if !response.status().is_success() {
+            return Err(ApiError::Rejected(problem(response).await));
+        }
+        Ok(response.json().await?)
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, url: String) -> Result<T, ApiError> {
+        let response = self.authorized(self.client.get(url)).send().await?;
+        if !response.status().is_success() {
+            return Err(ApiError::Rejected(problem(response).await));
+        }
+        Ok(response.json::<T>().await?)
+    }
+
+    async fn authenticate(
+        &self,
+        action: &str,
+        credentials: &Credentials,
+    ) -> Result<AuthResponse, ApiError> {
+        let response = self
+            .client
+            .post(format!("{}/auth/{}", self.base_url, action))
+            .json(credentials)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(ApiError::Rejected(problem(response).await));
         }
+
+        let auth = response.json::<AuthResponse>().await?;
+        *self.token.write().unwrap() = Some(auth.token.clone());
+        Ok(auth)
     }
-}
\ No newline at end of file
+
+    fn authorized(&self, request: RequestBuilder) -> RequestBuilder {
+        match self.token.read().unwrap().as_ref() {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
+
+async fn expect_success(response: Response) -> Result<(), ApiError> {
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(ApiError::Rejected(problem(response).await))
+    }
+}
+
+/// What a failed response says went wrong. One without a problem body, e.g. from a proxy
+/// in between, gets a stand-in made up from its status.
+async fn problem(response: Response) -> ProblemDetails {
+    let status = response.status();
+    response
+        .json::<ProblemDetails>()
+        .await
+        .unwrap_or_else(|_| ProblemDetails {
+            kind: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or("Error").to_string(),
+            status: status.as_u16(),
+            detail: format!("Request failed with status {}", status),
+            code: "http_error".to_string(),
+            request_id: None,
+        })
+}
@@ -1 +1,2 @@
-pub mod api_service;
\ No newline at end of file
+pub mod api_service;
+pub mod ws_transport;
\ No newline at end of file
@@ -0,0 +1,191 @@
+use std::cell::{Cell, RefCell};
+use std::rc::{Rc, Weak};
+
+use gloo_timers::callback::Timeout;
+use wasm_bindgen::prelude::*;
+use web_sys::{CloseEvent, MessageEvent, WebSocket};
+
+use shared::username::Username;
+use shared::ws_message::{ClientMessage, ServerMessage};
+
+const INITIAL_RETRY_MS: u32 = 1_000;
+const MAX_RETRY_MS: u32 = 30_000;
+
+type MessageHandler = Rc<dyn Fn(ServerMessage)>;
+
+/// Keeps the browser-side callbacks alive for as long as their socket is in use.
+struct Connection {
+    socket: WebSocket,
+    _on_open: Closure<dyn FnMut()>,
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+    _on_close: Closure<dyn FnMut(CloseEvent)>,
+}
+
+/// A `/api/ws` connection that reconnects on its own, backing off up to 30 seconds.
+///
+/// Sending fails fast while the socket is down so callers can fall back to HTTP.
+pub struct WsTransport {
+    url: String,
+    token: RefCell<Option<String>>,
+    on_message: RefCell<Option<MessageHandler>>,
+    connection: RefCell<Option<Connection>>,
+    retry_ms: Cell<u32>,
+    next_seq: Cell<u64>,
+    /// Re-sent on every (re)connect once asked for
+    live_subscribed: Cell<bool>,
+}
+
+impl WsTransport {
+    pub fn new(url: String) -> Rc<Self> {
+        Rc::new(Self {
+            url,
+            token: RefCell::new(None),
+            on_message: RefCell::new(None),
+            connection: RefCell::new(None),
+            retry_ms: Cell::new(INITIAL_RETRY_MS),
+            next_seq: Cell::new(1),
+            live_subscribed: Cell::new(false),
+        })
+    }
+
+    pub fn connect(self: &Rc<Self>, token: String, on_message: impl Fn(ServerMessage) + 'static) {
+        self.disconnect();
+        *self.token.borrow_mut() = Some(token);
+        *self.on_message.borrow_mut() = Some(Rc::new(on_message));
+        self.retry_ms.set(INITIAL_RETRY_MS);
+        self.open();
+    }
+
+    /// Closes the socket and stops reconnecting.
+    pub fn disconnect(&self) {
+        self.token.borrow_mut().take();
+        self.on_message.borrow_mut().take();
+        self.live_subscribed.set(false);
+        if let Some(connection) = self.connection.borrow_mut().take() {
+            connection.socket.set_onclose(None);
+            let _ = connection.socket.close();
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.connection
+            .borrow()
+            .as_ref()
+            .is_some_and(|connection| connection.socket.ready_state() == WebSocket::OPEN)
+    }
+
+    fn is_connecting_or_open(&self) -> bool {
+        self.connection.borrow().as_ref().is_some_and(|connection| {
+            matches!(connection.socket.ready_state(), WebSocket::CONNECTING | WebSocket::OPEN)
+        })
+    }
+
+    /// Sends one field change. Returns `false`, sending nothing, if the socket is down.
+    pub fn send_update(&self, username: Option<Username>, field: String, value: String) -> bool {
+        if !self.is_open() {
+            return false;
+        }
+        let seq = self.next_seq.get();
+        self.next_seq.set(seq + 1);
+        self.send(&ClientMessage::Update {
+            seq,
+            username,
+            field,
+            value,
+        })
+    }
+
+    /// Asks for live dashboard snapshots, now and after every reconnect.
+    pub fn subscribe_live(&self) {
+        self.live_subscribed.set(true);
+        if self.is_open() {
+            self.send(&ClientMessage::Subscribe);
+        }
+    }
+
+    fn send(&self, message: &ClientMessage) -> bool {
+        let Ok(text) = serde_json::to_string(message) else {
+            return false;
+        };
+        self.connection
+            .borrow()
+            .as_ref()
+            .is_some_and(|connection| connection.socket.send_with_str(&text).is_ok())
+    }
+
+    fn open(self: &Rc<Self>) {
+        let Some(token) = self.token.borrow().clone() else {
+            return;
+        };
+        let socket = match WebSocket::new(&format!("{}?token={}", self.url, token)) {
+            Ok(socket) => socket,
+            Err(err) => {
+                log::warn!("Could not open WebSocket: {:?}", err);
+                self.schedule_reconnect();
+                return;
+            }
+        };
+
+        let transport = Rc::downgrade(self);
+        let on_open = Closure::<dyn FnMut()>::new(move || {
+            if let Some(transport) = transport.upgrade() {
+                transport.retry_ms.set(INITIAL_RETRY_MS);
+                if transport.live_subscribed.get() {
+                    transport.send(&ClientMessage::Subscribe);
+                }
+            }
+        });
+
+        let transport = Rc::downgrade(self);
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            let Some(text) = event.data().as_string() else {
+                return;
+            };
+            let handler = transport
+                .upgrade()
+                .and_then(|transport| transport.on_message.borrow().clone());
+            match (serde_json::from_str::<ServerMessage>(&text), handler) {
+                (Ok(message), Some(handler)) => handler(message),
+                (Err(err), _) => log::warn!("Unreadable WebSocket message: {}", err),
+                _ => {}
+            }
+        });
+
+        // The closed connection is replaced by the reconnect, not dropped here:
+        // this closure belongs to it
+        let transport: Weak<Self> = Rc::downgrade(self);
+        let on_close = Closure::<dyn FnMut(CloseEvent)>::new(move |_: CloseEvent| {
+            if let Some(transport) = transport.upgrade() {
+                transport.schedule_reconnect();
+            }
+        });
+
+        socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+        socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+        socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+        *self.connection.borrow_mut() = Some(Connection {
+            socket,
+            _on_open: on_open,
+            _on_message: on_message,
+            _on_close: on_close,
+        });
+    }
+
+    fn schedule_reconnect(self: &Rc<Self>) {
+        if self.token.borrow().is_none() {
+            return;
+        }
+        let delay = self.retry_ms.get();
+        self.retry_ms.set((delay * 2).min(MAX_RETRY_MS));
+
+        let transport = Rc::downgrade(self);
+        Timeout::new(delay, move || {
+            if let Some(transport) = transport.upgrade()
+                && !transport.is_connecting_or_open()
+            {
+                transport.open();
+            }
+        })
+        .forget();
+    }
+}
@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+use crate::user_state::DataLog;
+
+/// Column names used by every CSV and log row, which codebook fields can't shadow.
+const RESERVED_KEYS: [&str; 5] = ["id", "username", "text_entry", "timestamp", "session_id"];
+
+#[derive(Debug, Error)]
+pub enum CodebookError {
+    #[error("invalid codebook: {0}")]
+    Invalid(String),
+}
+
+/// The set of category fields participants code, loaded from `codebook.json`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Codebook {
+    pub fields: Vec<CodebookField>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CodebookField {
+    /// Stable identifier, used as the CSV column name and the key in `categories`
+    pub key: String,
+    pub label: String,
+    pub options: Vec<String>,
+}
+
+impl Default for Codebook {
+    fn default() -> Self {
+        let fields = (1..=4)
+            .map(|n| CodebookField {
+                key: format!("category{}", n),
+                label: format!("Category {}", n),
+                options: ["A", "B", "C"]
+                    .iter()
+                    .map(|suffix| format!("Option {}{}", n, suffix))
+                    .collect(),
+            })
+            .collect();
+        Self { fields }
+    }
+}
+
+impl Codebook {
+    pub fn validate(&self) -> Result<(), CodebookError> {
+        if self.fields.is_empty() {
+            return Err(CodebookError::Invalid("at least one field is required".into()));
+        }
+
+        let mut seen = Vec::new();
+        for field in &self.fields {
+            let key = field.key.as_str();
+            let well_formed = !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+            if !well_formed {
+                return Err(CodebookError::Invalid(format!(
+                    "field key '{}' may only contain a-z, 0-9 and '_'",
+                    key
+                )));
+            }
+            if RESERVED_KEYS.contains(&key) {
+                return Err(CodebookError::Invalid(format!("field key '{}' is reserved", key)));
+            }
+            if seen.contains(&key) {
+                return Err(CodebookError::Invalid(format!("duplicate field key '{}'", key)));
+            }
+            if field.options.is_empty() {
+                return Err(CodebookError::Invalid(format!("field '{}' has no options", key)));
+            }
+            seen.push(key);
+        }
+
+        Ok(())
+    }
+
+    /// Checks submitted category values. An empty value means "not selected yet".
+    pub fn check_values(&self, categories: &BTreeMap<String, String>) -> Result<(), String> {
+        for (key, value) in categories {
+            let field = self
+                .fields
+                .iter()
+                .find(|field| &field.key == key)
+                .ok_or_else(|| format!("Unknown category field '{}'", key))?;
+            if !value.is_empty() && !field.options.contains(value) {
+                return Err(format!("'{}' is not an option for '{}'", value, field.label));
+            }
+        }
+        Ok(())
+    }
+
+    /// The CSV header for files written under this codebook.
+    pub fn csv_header(&self) -> Vec<String> {
+        let mut header = vec!["username".to_string(), "text_entry".to_string()];
+        header.extend(self.fields.iter().map(|field| field.key.clone()));
+        header.push("timestamp".to_string());
+        header
+    }
+
+    /// A sample as a row under `csv_header`.
+    pub fn csv_record(&self, log: &DataLog) -> Vec<String> {
+        let mut record = Vec::with_capacity(self.fields.len() + 3);
+        record.push(log.username.to_string());
+        record.push(log.text_entry.clone());
+        record.extend(self.ordered_values(&log.categories).into_iter().map(str::to_string));
+        record.push(log.timestamp.clone());
+        record
+    }
+
+    /// Category values in codebook order, with blanks for unselected fields.
+    pub fn ordered_values<'a>(&'a self, categories: &'a BTreeMap<String, String>) -> Vec<&'a str> {
+        self.fields
+            .iter()
+            .map(|field| categories.get(&field.key).map(String::as_str).unwrap_or(""))
+            .collect()
+    }
+}
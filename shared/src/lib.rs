@@ -0,0 +1,14 @@
+//! Types the backend and frontend exchange over `/api`, defined once for both, so a change
+//! to the wire format breaks the build on either side.
+//!
+//! The `sqlx` feature adds the database derives the backend reads rows into.
+
+pub mod codebook;
+pub mod live;
+pub mod log_query;
+pub mod problem;
+pub mod recording_session;
+pub mod user;
+pub mod user_state;
+pub mod username;
+pub mod ws_message;
@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::username::Username;
+
+/// A user with an open recording session, as shown on the live dashboard.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct LiveRecording {
+    pub username: Username,
+    pub text_entry: String,
+    #[cfg_attr(feature = "sqlx", sqlx(json))]
+    pub categories: BTreeMap<String, String>,
+    /// When the latest sample was taken, if any yet
+    pub last_saved: Option<String>,
+    pub session_id: i64,
+    pub label: Option<String>,
+    pub started_at: String,
+}
+
+/// Everyone recording at `generated_at` (server time, RFC 3339).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LiveSnapshot {
+    pub generated_at: String,
+    pub recordings: Vec<LiveRecording>,
+}
@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+/// The body of every error response, after RFC 7807 (`application/problem+json`).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProblemDetails {
+    /// Always `about:blank`: `code` tells problems apart
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The status's reason phrase
+    pub title: String,
+    pub status: u16,
+    /// What went wrong, fit to show to the user
+    pub detail: String,
+    /// One of `codes`
+    pub code: String,
+    /// Also sent as `x-request-id`, and logged with everything the request did
+    pub request_id: Option<String>,
+}
+
+/// The `code`s of `ProblemDetails`, which clients can match on. They never change once
+/// released.
+pub mod codes {
+    pub const BAD_REQUEST: &str = "bad_request";
+    pub const UNAUTHORIZED: &str = "unauthorized";
+    pub const FORBIDDEN: &str = "forbidden";
+    pub const NOT_FOUND: &str = "not_found";
+    pub const CONFLICT: &str = "conflict";
+    /// Well-formed, but breaking a rule (an unknown field, ...)
+    pub const UNPROCESSABLE: &str = "unprocessable";
+    pub const INVALID_USERNAME: &str = "invalid_username";
+    pub const INVALID_CSV_DIALECT: &str = "invalid_csv_dialect";
+    /// A body, query string or path that didn't parse
+    pub const INVALID_REQUEST: &str = "invalid_request";
+    pub const INTERNAL_ERROR: &str = "internal_error";
+}
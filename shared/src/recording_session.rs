@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+
+use crate::username::Username;
+
+/// One start-to-stop span of recording. `ended_at` is `None` while it is still open.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct RecordingSession {
+    pub id: i64,
+    pub username: Username,
+    pub label: Option<String>,
+    pub started_at: String,
+    pub ended_at: Option<String>,
+    /// Closed by the server shutting down rather than by a stop request
+    #[serde(default)]
+    pub interrupted: bool,
+}
+
+/// Body of `POST /api/sessions`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StartSession {
+    /// Whose session to start; the caller's own if omitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<Username>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// Query string accepted by `GET /api/sessions`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionListParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+}
@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+
+use crate::username::Username;
+
+/// What an account is allowed to do.
+///
+/// Participants edit only their own state, observers may also code on behalf of the
+/// participants assigned to them, and admins can read and manage everything.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "sqlx",
+    derive(sqlx::Type),
+    sqlx(type_name = "TEXT", rename_all = "lowercase")
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    Participant,
+    Observer,
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Participant, Role::Observer, Role::Admin];
+
+    /// As spelled on the wire and in the database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Participant => "participant",
+            Role::Observer => "observer",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        Role::ALL.into_iter().find(|role| role.as_str() == value)
+    }
+}
+
+/// An account as shown to admins, without the password hash.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct UserSummary {
+    pub username: Username,
+    pub role: Role,
+    pub created_at: String,
+}
+
+/// Body of `POST /api/auth/register` and `POST /api/auth/login`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Credentials {
+    pub username: Username,
+    pub password: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthResponse {
+    pub token: String,
+    pub username: Username,
+    pub role: Role,
+}
+
+/// Body of `PUT /api/admin/users/{username}/role`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoleUpdate {
+    pub role: Role,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct Assignment {
+    pub observer: Username,
+    pub participant: Username,
+}
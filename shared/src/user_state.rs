@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::username::Username;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct UserState {
+    pub username: Username,
+    pub text_entry: String,
+    /// Codebook field key -> selected option
+    #[cfg_attr(feature = "sqlx", sqlx(json))]
+    pub categories: BTreeMap<String, String>,
+    pub is_recording: bool,
+    pub last_saved: Option<String>,
+    pub last_data: Option<String>,
+}
+
+impl UserState {
+    /// Nothing entered or selected yet, and not recording.
+    pub fn blank(username: Username) -> Self {
+        Self {
+            username,
+            text_entry: String::new(),
+            categories: BTreeMap::new(),
+            is_recording: false,
+            last_saved: None,
+            last_data: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct DataLog {
+    pub id: Option<i64>,
+    pub username: Username,
+    pub text_entry: String,
+    #[cfg_attr(feature = "sqlx", sqlx(json))]
+    pub categories: BTreeMap<String, String>,
+    pub timestamp: String,
+    /// The recording session this sample was taken in
+    #[serde(default)]
+    pub session_id: Option<i64>,
+}
@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt, ops::Deref, str::FromStr};
+use thiserror::Error;
+use unicode_normalization::UnicodeNormalization;
+
+pub const MIN_USERNAME_LENGTH: usize = 3;
+pub const MAX_USERNAME_LENGTH: usize = 32;
+
+/// Names Windows refuses as file names, whatever the extension.
+const RESERVED_FILE_STEMS: [&str; 22] = [
+    "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8",
+    "com9", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum UsernameError {
+    #[error("username must be {MIN_USERNAME_LENGTH} to {MAX_USERNAME_LENGTH} characters long")]
+    Length,
+    #[error("username must start with a letter or digit")]
+    Start,
+    #[error("username may only contain letters, digits, '_', '-' and '.', not {0:?}")]
+    Character(char),
+}
+
+/// A validated account name.
+///
+/// Input is NFKC-normalized and trimmed, so lookalike spellings of the same name
+/// compare equal. Letters and digits from any script are allowed, plus `_`, `-` and
+/// `.` after the first character. Names read back from the database are trusted as is.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "sqlx", derive(sqlx::Type), sqlx(transparent))]
+#[serde(try_from = "String", into = "String")]
+pub struct Username(String);
+
+impl Username {
+    pub fn parse(raw: &str) -> Result<Self, UsernameError> {
+        let normalized: String = raw.nfkc().collect();
+        let name = normalized.trim();
+
+        let length = name.chars().count();
+        if !(MIN_USERNAME_LENGTH..=MAX_USERNAME_LENGTH).contains(&length) {
+            return Err(UsernameError::Length);
+        }
+        if !name.starts_with(char::is_alphanumeric) {
+            return Err(UsernameError::Start);
+        }
+        if let Some(bad) = name
+            .chars()
+            .find(|&c| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.')))
+        {
+            return Err(UsernameError::Character(bad));
+        }
+
+        Ok(Self(name.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// A file name stem unique to this user that is safe on any filesystem.
+    ///
+    /// Lowercase ASCII letters, digits, `_` and `-` are kept; every other character
+    /// becomes `~` and six hex digits of its code point, so names differing only in case
+    /// still get different files on case-insensitive filesystems. Windows device names
+    /// get a trailing `~`, which no escape can produce.
+    pub fn file_stem(&self) -> String {
+        let mut stem = String::with_capacity(self.0.len());
+        for c in self.0.chars() {
+            if c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-') {
+                stem.push(c);
+            } else {
+                stem.push_str(&format!("~{:06x}", u32::from(c)));
+            }
+        }
+        if RESERVED_FILE_STEMS.contains(&stem.as_str()) {
+            stem.push('~');
+        }
+        stem
+    }
+}
+
+impl FromStr for Username {
+    type Err = UsernameError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        Self::parse(raw)
+    }
+}
+
+impl TryFrom<String> for Username {
+    type Error = UsernameError;
+
+    fn try_from(raw: String) -> Result<Self, Self::Error> {
+        Self::parse(&raw)
+    }
+}
+
+impl From<Username> for String {
+    fn from(username: Username) -> Self {
+        username.0
+    }
+}
+
+impl Deref for Username {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for Username {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Username {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl PartialEq<str> for Username {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Username {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
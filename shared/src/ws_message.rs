@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{live::LiveSnapshot, user_state::UserState, username::Username};
+
+/// Messages a client sends over `/api/ws`, as JSON text frames.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// One field changed. `field` is `text_entry` or a codebook field key.
+    Update {
+        /// Echoed back in the acknowledgement
+        seq: u64,
+        /// Whose state to change; the caller's own if omitted
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        username: Option<Username>,
+        field: String,
+        value: String,
+    },
+    /// Admins only: receive a `live` snapshot now and whenever recordings change.
+    Subscribe,
+}
+
+/// Messages the server sends over `/api/ws`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// An update was saved at `saved_at` (server time, RFC 3339); `state` is what was stored.
+    Ack {
+        seq: u64,
+        saved_at: String,
+        state: UserState,
+    },
+    Error {
+        seq: Option<u64>,
+        error: String,
+    },
+    Live(LiveSnapshot),
+}
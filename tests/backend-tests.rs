@@ -6,16 +6,78 @@ mod backend_tests {
         response::Response,
     };
     use axum_backend::{
-        models::{app_state::AppState, user_state::UserState},
-        handlers::state_handlers::{get_user_state, update_user_state},
+        config::{Config, ConfigError, ConfigLayer, CorsOrigins},
+        db::{
+            memory::InMemoryRepository,
+            migrations::{latest_version, run_migrations, MigrationError},
+            repository::StateRepository,
+            sqlite::SqliteRepository,
+        },
+        csv::{
+            dialect::{CsvDialect, LineEnding, QuoteStyle, TimestampFormat},
+            index::CsvIndex,
+            rotation::{CsvSettings, RotationPolicy},
+            writer::{CsvExporter, CsvRow},
+        },
+        handlers::error::ProblemDetails,
+        models::{
+            app_state::AppState,
+            codebook::{Codebook, CodebookField},
+            live::LiveSnapshot,
+            log_query::{LogCursor, LogPage, LogQuery, LogQueryParams, SortOrder},
+            recording_session::RecordingSession,
+            user::Role,
+            user_state::{DataLog, Sample, UserState},
+            username::Username,
+        },
+        live::LiveFeed,
+        log_writer::{LogWriter, LogWriterSettings, SyncPolicy},
+        metrics::Metrics,
+        reconcile::{rebuild_user, reconcile_user},
+        recorder::Recorder,
+        routes::{api_routes, site_routes},
+        site::Site,
     };
     use sqlx::SqlitePool;
-    use std::{path::Path, sync::Arc};
+    use std::{collections::BTreeMap, sync::Arc, time::Duration};
     use tempfile::tempdir;
     use tower::ServiceExt;
 
-    // Helper function to create a test app state with a temporary directory and database
+    const TEST_SAMPLE_INTERVAL: Duration = Duration::from_millis(20);
+
+    // Helper function to create a test app state with a temporary directory, kept in memory
     async fn create_test_app_state() -> (Arc<AppState>, tempfile::TempDir) {
+        create_test_app_state_with_codebook(Codebook::default()).await
+    }
+
+    async fn create_test_app_state_with_codebook(
+        codebook: Codebook,
+    ) -> (Arc<AppState>, tempfile::TempDir) {
+        create_test_app_state_with(Arc::new(InMemoryRepository::new()), codebook)
+    }
+
+    // Helper function to create a test app state backed by an in-memory SQLite database
+    async fn create_sqlite_app_state() -> (Arc<AppState>, tempfile::TempDir) {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+
+        // Initialize schema through the same migrations the server runs
+        run_migrations(&db).await.unwrap();
+
+        create_test_app_state_with(Arc::new(SqliteRepository::new(db)), Codebook::default())
+    }
+
+    fn create_test_app_state_with(
+        repo: Arc<dyn StateRepository>,
+        codebook: Codebook,
+    ) -> (Arc<AppState>, tempfile::TempDir) {
+        create_test_app_state_with_csv(repo, codebook, CsvSettings::default())
+    }
+
+    fn create_test_app_state_with_csv(
+        repo: Arc<dyn StateRepository>,
+        codebook: Codebook,
+        csv_settings: CsvSettings,
+    ) -> (Arc<AppState>, tempfile::TempDir) {
         let temp_dir = tempdir().expect("Failed to create temp directory");
         let temp_path = temp_dir.path();
         
@@ -26,71 +88,147 @@ mod backend_tests {
         let csv_dir = data_dir.join("csv");
         std::fs::create_dir_all(&csv_dir).expect("Failed to create CSV directory");
         
-        // Create in-memory database for testing
-        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
-        
-        // Initialize schema
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS user_states (
-                username TEXT PRIMARY KEY,
-                text_entry TEXT NOT NULL,
-                category1 TEXT NOT NULL,
-                category2 TEXT NOT NULL,
-                category3 TEXT NOT NULL,
-                category4 TEXT NOT NULL,
-                is_recording BOOLEAN NOT NULL,
-                last_saved TEXT,
-                last_data TEXT
-            );
-            
-            CREATE TABLE IF NOT EXISTS data_logs (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                username TEXT NOT NULL,
-                text_entry TEXT NOT NULL,
-                category1 TEXT NOT NULL,
-                category2 TEXT NOT NULL,
-                category3 TEXT NOT NULL,
-                category4 TEXT NOT NULL,
-                timestamp TEXT NOT NULL,
-                FOREIGN KEY(username) REFERENCES user_states(username)
-            );
-            "#,
-        )
-        .execute(&db)
-        .await
-        .unwrap();
-        
+        let csv = Arc::new(CsvExporter::new(csv_dir, csv_settings, codebook.csv_header()));
+        let codebook = Arc::new(codebook);
+        let log_writer = LogWriter::spawn(
+            Arc::clone(&repo),
+            Arc::clone(&csv),
+            Arc::clone(&codebook),
+            LogWriterSettings::default(),
+        );
         let app_state = Arc::new(AppState {
-            db,
-            csv_writers: Arc::new(std::sync::RwLock::new(std::collections::HashMap::new())),
+            repo,
+            csv,
+            log_writer,
             data_dir,
+            codebook,
+            recorder: Recorder::new(TEST_SAMPLE_INTERVAL),
+            live: LiveFeed::default(),
+            metrics: Metrics::default(),
         });
         
         (app_state, temp_dir)
     }
+
+    // Helper function to give `username` a blank saved state, which their logs refer to
+    async fn save_blank_state(state: &AppState, username: &str) {
+        let user_state = UserState {
+            username: name(username),
+            text_entry: String::new(),
+            categories: BTreeMap::new(),
+            is_recording: false,
+            last_saved: None,
+            last_data: None,
+        };
+        state.repo.save_user_state(&user_state).await.unwrap();
+    }
+
+    // Helper function to build a sample of `text` taken now
+    fn sample(username: &str, text: &str) -> Sample {
+        Sample {
+            log: DataLog {
+                id: None,
+                username: name(username),
+                text_entry: text.to_string(),
+                categories: BTreeMap::new(),
+                timestamp: chrono::Utc::now().to_rfc3339(),
+                session_id: None,
+            },
+            summary: format!("Text: {}", text),
+        }
+    }
+
+    // Helper function to count the samples logged for `username`, or for everyone
+    async fn count_logs(state: &AppState, username: Option<&str>) -> i64 {
+        let query = LogQuery {
+            usernames: username.map(|username| vec![name(username)]),
+            limit: u32::MAX,
+            ..LogQuery::default()
+        };
+        state.repo.query_logs(&query).await.unwrap().len() as i64
+    }
     
+    // Helper function to build a username that is known to be valid
+    fn name(raw: &str) -> Username {
+        Username::parse(raw).unwrap()
+    }
+
     // Helper function to create a test router
     fn app(state: Arc<AppState>) -> axum::Router {
-        axum::Router::new()
-            .route("/api/state/:username", axum::routing::get(get_user_state))
-            .route("/api/state", axum::routing::post(update_user_state))
-            .with_state(state)
+        api_routes(state)
     }
-    
-    #[sqlx::test]
+
+    // Helper function to read a JSON response body
+    async fn json_body<T: serde::de::DeserializeOwned>(response: Response) -> T {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    // Helper function to register a user and return their bearer token
+    async fn register_user(app: &axum::Router, username: &str, password: &str) -> String {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/auth/register")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(
+                        serde_json::json!({ "username": username, "password": password })
+                            .to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let body: serde_json::Value = json_body(response).await;
+        body["token"].as_str().unwrap().to_string()
+    }
+
+    // Helper function to build an authenticated request
+    fn authed(method: &str, uri: &str, token: &str, body: Option<serde_json::Value>) -> Request<Body> {
+        let builder = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Authorization", format!("Bearer {}", token));
+        match body {
+            Some(body) => builder
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+            None => builder.body(Body::empty()).unwrap(),
+        }
+    }
+
+    // Helper function to wait until at least `count` samples are logged for `username`
+    async fn wait_for_samples(state: &AppState, username: &str, count: i64) {
+        for _ in 0..250 {
+            let logged = count_logs(state, Some(username)).await;
+            if logged >= count {
+                return;
+            }
+            tokio::time::sleep(TEST_SAMPLE_INTERVAL).await;
+        }
+        panic!("recorder never logged {} samples for {}", count, username);
+    }
+
+    #[tokio::test]
     async fn test_update_and_get_user_state() {
         let (state, _temp_dir) = create_test_app_state().await;
         let app = app(state.clone());
+        let token = register_user(&app, "testuser", "correct horse").await;
         
         // Create a test user state
         let test_state = UserState {
-            username: "testuser".to_string(),
+            username: name("testuser"),
             text_entry: "test text".to_string(),
-            category1: "option1a".to_string(),
-            category2: "option2a".to_string(),
-            category3: "option3a".to_string(),
-            category4: "option4a".to_string(),
+            categories: BTreeMap::from([
+                ("category1".to_string(), "Option 1A".to_string()),
+                ("category2".to_string(), "Option 2A".to_string()),
+            ]),
             is_recording: false,
             last_saved: None,
             last_data: None,
@@ -98,11 +236,13 @@ mod backend_tests {
         
         // Update the user state
         let response = app
+            .clone()
             .oneshot(
                 Request::builder()
                     .method("POST")
                     .uri("/api/state")
                     .header("Content-Type", "application/json")
+                    .header("Authorization", format!("Bearer {}", token))
                     .body(Body::from(serde_json::to_string(&test_state).unwrap()))
                     .unwrap(),
             )
@@ -115,7 +255,8 @@ mod backend_tests {
         let response = app
             .oneshot(
                 Request::builder()
-                    .uri("/api/state/testuser")
+                    .uri("/api/state")
+                    .header("Authorization", format!("Bearer {}", token))
                     .body(Body::empty())
                     .unwrap(),
             )
@@ -125,40 +266,44 @@ mod backend_tests {
         assert_eq!(response.status(), StatusCode::OK);
         
         // Check the response body
-        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
-        let retrieved_state: UserState = serde_json::from_slice(&body).unwrap();
+        let retrieved_state: UserState = json_body(response).await;
         
         assert_eq!(retrieved_state.username, test_state.username);
         assert_eq!(retrieved_state.text_entry, test_state.text_entry);
-        assert_eq!(retrieved_state.category1, test_state.category1);
+        assert_eq!(retrieved_state.categories, test_state.categories);
         assert_eq!(retrieved_state.is_recording, test_state.is_recording);
     }
     
-    #[sqlx::test]
+    #[tokio::test]
     async fn test_recording_state() {
         let (state, temp_dir) = create_test_app_state().await;
         let app = app(state.clone());
+        let token = register_user(&app, "recordinguser", "correct horse").await;
         
         // Create a test user state with recording enabled
         let test_state = UserState {
-            username: "recordinguser".to_string(),
+            username: name("recordinguser"),
             text_entry: "recording text".to_string(),
-            category1: "option1b".to_string(),
-            category2: "option2b".to_string(),
-            category3: "option3b".to_string(),
-            category4: "option4b".to_string(),
+            categories: BTreeMap::from([
+                ("category1".to_string(), "Option 1B".to_string()),
+                ("category2".to_string(), "Option 2B".to_string()),
+                ("category3".to_string(), "Option 3B".to_string()),
+                ("category4".to_string(), "Option 4B".to_string()),
+            ]),
             is_recording: true,
             last_saved: Some("2023-01-01T00:00:00Z".to_string()),
             last_data: Some("test data".to_string()),
         };
-        
+
         // Update the user state
         let response = app
+            .clone()
             .oneshot(
                 Request::builder()
                     .method("POST")
                     .uri("/api/state")
                     .header("Content-Type", "application/json")
+                    .header("Authorization", format!("Bearer {}", token))
                     .body(Body::from(serde_json::to_string(&test_state).unwrap()))
                     .unwrap(),
             )
@@ -166,20 +311,2175 @@ mod backend_tests {
             .unwrap();
         
         assert_eq!(response.status(), StatusCode::OK);
+
+        // Recording starts with a session, not with the flag
+        let response = app
+            .clone()
+            .oneshot(authed("POST", "/api/sessions", &token, Some(serde_json::json!({}))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let session: RecordingSession = json_body(response).await;
+
+        // The server samples on its own clock, with no further requests
+        wait_for_samples(&state, "recordinguser", 2).await;
+        let uri = format!("/api/sessions/{}/stop", session.id);
+        let response = app.oneshot(authed("POST", &uri, &token, None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!state.recorder.is_sampling(&name("recordinguser")));
         
         // Check that a CSV file was created
-        let csv_path = temp_dir.path().join("data").join("csv").join("recordinguser.csv");
+        let csv_path = temp_dir.path().join("data/csv/recordinguser/recordinguser.csv");
         assert!(csv_path.exists());
+        let csv = std::fs::read_to_string(&csv_path).unwrap();
+        assert!(csv.lines().nth(1).unwrap().starts_with("recordinguser,recording text,Option 1B,"));
         
-        // Check that a database entry was created
-        let log_entries = sqlx::query!(
-            "SELECT COUNT(*) as count FROM data_logs WHERE username = ?",
-            "recordinguser"
+        // Check that database entries were created, and nothing after the stop
+        let log_entries = count_logs(&state, Some("recordinguser")).await;
+        assert_eq!(csv.lines().count() as i64, log_entries + 1);
+
+        tokio::time::sleep(TEST_SAMPLE_INTERVAL * 3).await;
+        let later = count_logs(&state, Some("recordinguser")).await;
+        assert_eq!(later, log_entries);
+
+        let saved = state.repo.get_user_state(&name("recordinguser")).await.unwrap().unwrap();
+        assert!(!saved.is_recording);
+        assert!(saved.last_data.unwrap().starts_with("Text: recording text"));
+    }
+
+    #[tokio::test]
+    async fn test_state_requires_valid_session() {
+        let (state, _temp_dir) = create_test_app_state().await;
+        let app = app(state.clone());
+        let token = register_user(&app, "victim", "correct horse").await;
+
+        // No token at all
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/api/state").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // Log out, then try to reuse the token
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/auth/logout")
+                    .header("Authorization", format!("Bearer {}", token))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/api/state")
+                    .header("Authorization", format!("Bearer {}", token))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_login_rejects_wrong_password() {
+        let (state, _temp_dir) = create_test_app_state().await;
+        let app = app(state.clone());
+        register_user(&app, "loginuser", "correct horse").await;
+
+        let login = |password: &str| {
+            Request::builder()
+                .method("POST")
+                .uri("/api/auth/login")
+                .header("Content-Type", "application/json")
+                .body(Body::from(
+                    serde_json::json!({ "username": "loginuser", "password": password })
+                        .to_string(),
+                ))
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(login("battery staple")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app.oneshot(login("correct horse")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_first_user_is_admin_and_participants_are_forbidden() {
+        let (state, _temp_dir) = create_test_app_state().await;
+        let app = app(state.clone());
+        let admin_token = register_user(&app, "admin", "correct horse").await;
+        let participant_token = register_user(&app, "participant", "correct horse").await;
+
+        let response = app
+            .clone()
+            .oneshot(authed("GET", "/api/admin/users", &admin_token, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(authed("GET", "/api/admin/users", &participant_token, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let body: serde_json::Value = json_body(response).await;
+        assert!(body["detail"].as_str().unwrap().contains("admin"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_first_registrations_make_one_admin() {
+        let db_dir = tempdir().unwrap();
+        let db_url = format!("sqlite://{}?mode=rwc", db_dir.path().join("app.db").display());
+        let db = SqlitePool::connect(&db_url).await.unwrap();
+        run_migrations(&db).await.unwrap();
+        let (state, _temp_dir) =
+            create_test_app_state_with(Arc::new(SqliteRepository::new(db)), Codebook::default());
+        let app = app(state.clone());
+
+        let registrations: Vec<_> = (0..8)
+            .map(|i| {
+                let app = app.clone();
+                tokio::spawn(async move {
+                    register_user(&app, &format!("user{}", i), "correct horse").await
+                })
+            })
+            .collect();
+        for registration in registrations {
+            registration.await.unwrap();
+        }
+
+        let users = state.repo.list_users().await.unwrap();
+        assert_eq!(users.len(), 8);
+        let admins = users.iter().filter(|user| user.role == Role::Admin).count();
+        assert_eq!(admins, 1);
+    }
+
+    #[tokio::test]
+    async fn test_errors_are_problem_details_with_a_request_id() {
+        let (state, _temp_dir) = create_test_app_state().await;
+        let app = app(state.clone());
+        register_user(&app, "admin", "correct horse").await;
+        let token = register_user(&app, "participant", "correct horse").await;
+
+        let response = app
+            .clone()
+            .oneshot(authed("GET", "/api/admin/users", &token, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.headers()["content-type"], "application/problem+json");
+        let request_id = response.headers()["x-request-id"].to_str().unwrap().to_string();
+        let problem: ProblemDetails = json_body(response).await;
+        assert_eq!(problem.kind, "about:blank");
+        assert_eq!(problem.title, "Forbidden");
+        assert_eq!(problem.status, 403);
+        assert_eq!(problem.code, "forbidden");
+        assert_eq!(problem.request_id.as_deref(), Some(request_id.as_str()));
+
+        // An id assigned by a proxy in front is kept
+        let mut request = authed("GET", "/api/sessions/not-a-number", &token, None);
+        request.headers_mut().insert("x-request-id", "edge-42".parse().unwrap());
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["x-request-id"], "edge-42");
+        let problem: ProblemDetails = json_body(response).await;
+        assert_eq!(problem.code, "invalid_request");
+        assert_eq!(problem.request_id.as_deref(), Some("edge-42"));
+
+        let response = app
+            .clone()
+            .oneshot(authed("POST", "/api/state", &token, Some(serde_json::json!([1, 2]))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let problem: ProblemDetails = json_body(response).await;
+        assert_eq!(problem.code, "invalid_request");
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/api/state").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let problem: ProblemDetails = json_body(response).await;
+        assert_eq!(problem.code, "unauthorized");
+
+        // Successful responses carry the id too, so any request can be found in the logs
+        let response = app
+            .oneshot(authed("GET", "/api/codebook", &token, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key("x-request-id"));
+    }
+
+    #[tokio::test]
+    async fn test_probes_and_metrics() {
+        let (state, temp_dir) = create_sqlite_app_state().await;
+        let app = app(state.clone());
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/healthz").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/readyz").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let readiness: serde_json::Value = json_body(response).await;
+        assert_eq!(readiness["ready"], true);
+        let checks: Vec<&str> = readiness["checks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|check| check["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(checks, ["database", "data_dir", "csv_dir"]);
+        for dir in [&state.data_dir, &state.data_dir.join("csv")] {
+            let left_behind = std::fs::read_dir(dir)
+                .unwrap()
+                .any(|entry| entry.unwrap().file_name().to_string_lossy().starts_with(".readyz"));
+            assert!(!left_behind, "probe file left in {}", dir.display());
+        }
+
+        let token = register_user(&app, "ann", "correct horse").await;
+        save_blank_state(&state, "ann").await;
+        let response = app
+            .clone()
+            .oneshot(authed("POST", "/api/sessions", &token, Some(serde_json::json!({}))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let session: RecordingSession = json_body(response).await;
+        wait_for_samples(&state, "ann", 2).await;
+        // Samples count once their batch is done, a moment after they can be queried
+        state.log_writer.flush().await.unwrap();
+        let uri = format!("/api/sessions/{}", session.id);
+        app.clone().oneshot(authed("GET", &uri, &token, None)).await.unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/plain"));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let metrics = String::from_utf8(body.to_vec()).unwrap();
+        let value = |series: &str| -> f64 {
+            metrics
+                .lines()
+                .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+                .unwrap_or_else(|| panic!("no {} in\n{}", series, metrics))
+                .parse()
+                .unwrap()
+        };
+        let register = r#"{method="POST",route="/api/auth/register",status="201"}"#;
+        assert_eq!(value(&format!("backend_http_requests_total{}", register)), 1.0);
+        // Ids are folded into the route pattern
+        let session_route = r#"{method="GET",route="/api/sessions/{id}",status="200"}"#;
+        assert_eq!(value(&format!("backend_http_requests_total{}", session_route)), 1.0);
+        assert_eq!(
+            value(&format!(
+                "backend_http_request_duration_seconds_count{}",
+                session_route
+            )),
+            1.0
+        );
+        assert!(value("backend_samples_logged_total") >= 2.0);
+        assert!(value("backend_samples_logged_per_second") > 0.0);
+        assert_eq!(value("backend_active_recorders"), 1.0);
+        assert!(value("backend_csv_writers_open") >= 1.0);
+        assert!(value("backend_db_pool_connections") >= 1.0);
+        assert!(metrics.contains("# TYPE backend_http_request_duration_seconds histogram"));
+
+        // A CSV directory that can't be written to takes the server out of rotation
+        state.recorder.stop(&name("ann")).await;
+        let csv_dir = temp_dir.path().join("data/csv");
+        std::fs::remove_dir_all(&csv_dir).unwrap();
+        std::fs::write(&csv_dir, "not a directory").unwrap();
+        let response = app
+            .oneshot(Request::builder().uri("/readyz").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let readiness: serde_json::Value = json_body(response).await;
+        assert_eq!(readiness["ready"], false);
+        assert_eq!(readiness["checks"][0]["ok"], true);
+        assert_eq!(readiness["checks"][2]["ok"], false);
+        assert!(readiness["checks"][2]["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_frontend_is_served_next_to_the_api() {
+        let (state, temp_dir) = create_test_app_state().await;
+        let site_root = temp_dir.path().join("site");
+        std::fs::create_dir_all(site_root.join("pkg")).unwrap();
+        std::fs::write(site_root.join("index.html"), "<!DOCTYPE html><title>app</title>").unwrap();
+        std::fs::write(site_root.join("pkg/app.wasm"), b"\0asm").unwrap();
+        std::fs::write(site_root.join("favicon.ico"), b"icon").unwrap();
+        std::fs::write(temp_dir.path().join("secret.txt"), "secret").unwrap();
+        let app = api_routes(state).merge(site_routes(Site::Dir(site_root)));
+        let get = |uri: &str| {
+            app.clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        };
+        let text = |response: Response| async {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        let response = get("/").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/html"));
+        assert_eq!(response.headers()["cache-control"], "no-cache");
+        let etag = response.headers()["etag"].clone();
+        assert!(text(response).await.contains("<title>app</title>"));
+
+        let response = get("/pkg/app.wasm").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "application/wasm");
+        assert_eq!(response.headers()["cache-control"], "no-cache");
+        let response = get("/favicon.ico").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()["cache-control"].to_str().unwrap().contains("max-age"));
+
+        // Pages of the frontend's own router get the app, missing files don't
+        let response = get("/admin/live").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(text(response).await.contains("<title>app</title>"));
+        assert_eq!(get("/pkg/missing.js").await.unwrap().status(), StatusCode::NOT_FOUND);
+        let response = get("/../secret.txt").await.unwrap();
+        assert!(!text(response).await.contains("secret"));
+
+        // Unknown API paths are still API errors
+        let response = get("/api/nothing/here").await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let problem: ProblemDetails = json_body(response).await;
+        assert_eq!(problem.code, "not_found");
+        assert_eq!(get("/healthz").await.unwrap().status(), StatusCode::OK);
+
+        let mut request = Request::builder().uri("/").body(Body::empty()).unwrap();
+        request.headers_mut().insert("if-none-match", etag.clone());
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()["etag"], etag);
+    }
+
+    #[tokio::test]
+    async fn test_observer_codes_only_for_assigned_participants() {
+        let (state, _temp_dir) = create_test_app_state().await;
+        let app = app(state.clone());
+        let admin_token = register_user(&app, "admin", "correct horse").await;
+        let observer_token = register_user(&app, "observer", "correct horse").await;
+        register_user(&app, "assigned", "correct horse").await;
+        register_user(&app, "stranger", "correct horse").await;
+
+        state.repo.set_user_role(&name("observer"), Role::Observer).await.unwrap();
+        let response = app
+            .clone()
+            .oneshot(authed(
+                "POST",
+                "/api/admin/assignments",
+                &admin_token,
+                Some(serde_json::json!({ "observer": "observer", "participant": "assigned" })),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let coded_state = |username: &str| {
+            serde_json::json!({
+                "username": username,
+                "text_entry": "coded by observer",
+                "categories": {},
+                "is_recording": false, "last_saved": null, "last_data": null
+            })
+        };
+
+        let response = app
+            .clone()
+            .oneshot(authed(
+                "POST",
+                "/api/state/assigned",
+                &observer_token,
+                Some(coded_state("assigned")),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(authed(
+                "POST",
+                "/api/state/stranger",
+                &observer_token,
+                Some(coded_state("stranger")),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_codebook_drives_validation_and_csv_columns() {
+        let codebook = Codebook {
+            fields: vec![
+                CodebookField {
+                    key: "mood".to_string(),
+                    label: "Mood".to_string(),
+                    options: vec!["Calm".to_string(), "Agitated".to_string()],
+                },
+                CodebookField {
+                    key: "activity".to_string(),
+                    label: "Activity".to_string(),
+                    options: vec!["Reading".to_string(), "Talking".to_string()],
+                },
+            ],
+        };
+        let (state, temp_dir) = create_test_app_state_with_codebook(codebook).await;
+        let app = app(state.clone());
+        let token = register_user(&app, "coder", "correct horse").await;
+
+        let coded_state = |mood: &str| {
+            serde_json::json!({
+                "username": "coder",
+                "text_entry": "",
+                "categories": { "mood": mood, "activity": "Talking" },
+                "is_recording": true, "last_saved": null, "last_data": null
+            })
+        };
+
+        // An option the codebook doesn't know is rejected
+        let response = app
+            .clone()
+            .oneshot(authed("POST", "/api/state", &token, Some(coded_state("Sleepy"))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let response = app
+            .clone()
+            .oneshot(authed("POST", "/api/state", &token, Some(coded_state("Calm"))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(authed("POST", "/api/sessions", &token, Some(serde_json::json!({}))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        wait_for_samples(&state, "coder", 1).await;
+        state.recorder.stop(&name("coder")).await;
+
+        let csv = std::fs::read_to_string(temp_dir.path().join("data/csv/coder/coder.csv")).unwrap();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("username,text_entry,mood,activity,timestamp"));
+        assert!(lines.next().unwrap().starts_with("coder,,Calm,Talking,"));
+    }
+
+    #[tokio::test]
+    async fn test_migrations_refuse_newer_database() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        assert_eq!(run_migrations(&db).await.unwrap(), latest_version());
+
+        // Running again is a no-op
+        assert_eq!(run_migrations(&db).await.unwrap(), latest_version());
+
+        // Pretend a newer binary already migrated this database
+        sqlx::query(
+            r#"
+            INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+            VALUES (?, 'from the future', 1, x'00', 0)
+            "#,
         )
-        .fetch_one(&state.db)
+        .bind(latest_version() + 1)
+        .execute(&db)
         .await
         .unwrap();
-        
-        assert_eq!(log_entries.count, 1);
+
+        assert!(matches!(
+            run_migrations(&db).await,
+            Err(MigrationError::DatabaseTooNew { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_migrations_carry_legacy_categories_over() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        // The category columns as they were before the codebook
+        sqlx::query(
+            r#"
+            CREATE TABLE user_states (
+                username TEXT PRIMARY KEY,
+                text_entry TEXT NOT NULL,
+                category1 TEXT NOT NULL,
+                category2 TEXT NOT NULL,
+                category3 TEXT NOT NULL,
+                category4 TEXT NOT NULL,
+                is_recording BOOLEAN NOT NULL,
+                last_saved TEXT,
+                last_data TEXT
+            );
+            CREATE TABLE data_logs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                username TEXT NOT NULL,
+                text_entry TEXT NOT NULL,
+                category1 TEXT NOT NULL,
+                category2 TEXT NOT NULL,
+                category3 TEXT NOT NULL,
+                category4 TEXT NOT NULL,
+                timestamp TEXT NOT NULL,
+                FOREIGN KEY(username) REFERENCES user_states(username)
+            );
+            INSERT INTO user_states
+            VALUES ('old', 'notes', 'Option 1A', 'Option 2B', '', 'Option 4C', FALSE, NULL, NULL);
+            INSERT INTO data_logs (username, text_entry, category1, category2, category3,
+                                   category4, timestamp)
+            VALUES ('old', 'notes', 'Option 1A', '', '', '', '2024-01-01T00:00:00+00:00');
+            "#,
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+
+        assert_eq!(run_migrations(&db).await.unwrap(), latest_version());
+
+        let repo = SqliteRepository::new(db.clone());
+        let state = repo.get_user_state(&name("old")).await.unwrap().unwrap();
+        assert_eq!(state.text_entry, "notes");
+        assert_eq!(state.categories["category1"], "Option 1A");
+        assert_eq!(state.categories["category2"], "Option 2B");
+        assert_eq!(state.categories["category3"], "");
+        assert_eq!(state.categories["category4"], "Option 4C");
+
+        let logged: String = sqlx::query_scalar("SELECT categories FROM data_logs")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        let logged: BTreeMap<String, String> = serde_json::from_str(&logged).unwrap();
+        assert_eq!(logged["category1"], "Option 1A");
+        let legacy_columns: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM pragma_table_info('data_logs') WHERE name LIKE 'category_'",
+        )
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        assert_eq!(legacy_columns, 0);
+    }
+
+    #[tokio::test]
+    async fn test_migrations_adopt_a_baseline_database() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        // The schema the server created inline before it had migrations
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS user_states (
+                username TEXT PRIMARY KEY,
+                text_entry TEXT NOT NULL,
+                category1 TEXT NOT NULL,
+                category2 TEXT NOT NULL,
+                category3 TEXT NOT NULL,
+                category4 TEXT NOT NULL,
+                is_recording BOOLEAN NOT NULL,
+                last_saved TEXT,
+                last_data TEXT
+            );
+            CREATE TABLE IF NOT EXISTS data_logs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                username TEXT NOT NULL,
+                text_entry TEXT NOT NULL,
+                category1 TEXT NOT NULL,
+                category2 TEXT NOT NULL,
+                category3 TEXT NOT NULL,
+                category4 TEXT NOT NULL,
+                timestamp TEXT NOT NULL,
+                FOREIGN KEY(username) REFERENCES user_states(username)
+            );
+            CREATE TABLE IF NOT EXISTS users (
+                username TEXT PRIMARY KEY,
+                password_hash TEXT NOT NULL,
+                role TEXT NOT NULL DEFAULT 'participant',
+                created_at TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS auth_sessions (
+                token_hash TEXT PRIMARY KEY,
+                username TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                expires_at TEXT NOT NULL,
+                FOREIGN KEY(username) REFERENCES users(username)
+            );
+            CREATE TABLE IF NOT EXISTS observer_assignments (
+                observer TEXT NOT NULL,
+                participant TEXT NOT NULL,
+                PRIMARY KEY(observer, participant),
+                FOREIGN KEY(observer) REFERENCES users(username),
+                FOREIGN KEY(participant) REFERENCES users(username)
+            );
+            INSERT INTO user_states
+            VALUES ('veteran', 'notes', 'Option 1B', '', '', '', FALSE, NULL, NULL);
+            INSERT INTO data_logs (username, text_entry, category1, category2, category3,
+                                   category4, timestamp)
+            VALUES ('veteran', 'notes', 'Option 1B', '', '', '', '2024-01-01T00:00:00+00:00');
+            "#,
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO users VALUES ('veteran', ?, 'participant', ?)")
+            .bind(axum_backend::auth::password::hash_password("correct horse").unwrap())
+            .bind("2024-01-01T00:00:00+00:00")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        assert_eq!(run_migrations(&db).await.unwrap(), latest_version());
+
+        let (state, _temp_dir) =
+            create_test_app_state_with(Arc::new(SqliteRepository::new(db)), Codebook::default());
+        let app = app(state);
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/auth/login")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(
+                        serde_json::json!({ "username": "veteran", "password": "correct horse" })
+                            .to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = json_body(response).await;
+        let token = body["token"].as_str().unwrap().to_string();
+
+        let response = app
+            .clone()
+            .oneshot(authed("GET", "/api/state", &token, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let user_state: UserState = json_body(response).await;
+        assert_eq!(user_state.categories["category1"], "Option 1B");
+
+        let response = app
+            .oneshot(authed("GET", "/api/logs?username=veteran", &token, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let page: LogPage = json_body(response).await;
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].categories["category1"], "Option 1B");
+    }
+
+    #[tokio::test]
+    async fn test_repositories_agree_on_log_queries() {
+        let sqlite = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        run_migrations(&sqlite).await.unwrap();
+        assert_repositories_agree(&[
+            Arc::new(SqliteRepository::new(sqlite)),
+            Arc::new(InMemoryRepository::new()),
+        ])
+        .await;
+    }
+
+    // Helper function to seed the same logs into every repository and compare query results
+    async fn assert_repositories_agree(repos: &[Arc<dyn StateRepository>]) {
+        for repo in repos {
+            for username in ["ada", "bea"] {
+                repo.save_user_state(&UserState {
+                    username: name(username),
+                    text_entry: String::new(),
+                    categories: BTreeMap::new(),
+                    is_recording: false,
+                    last_saved: None,
+                    last_data: None,
+                })
+                .await
+                .unwrap();
+            }
+            for minute in 0..6 {
+                let option = if minute % 3 == 0 { "Option 1A" } else { "Option 1B" };
+                let log = DataLog {
+                    id: None,
+                    username: name(if minute % 2 == 0 { "ada" } else { "bea" }),
+                    text_entry: format!("Note_{} 100%", minute),
+                    categories: BTreeMap::from([("category1".to_string(), option.to_string())]),
+                    // Two samples share each timestamp, so ties are broken by id
+                    timestamp: format!("2024-05-01T10:0{}:00+00:00", minute / 2),
+                    session_id: None,
+                };
+                repo.log_data_entry(&log).await.unwrap();
+            }
+        }
+
+        let from = "2024-05-01T10:01:00Z".parse().ok();
+        let queries = [
+            LogQuery { limit: 100, ..LogQuery::default() },
+            LogQuery { limit: 2, sort: SortOrder::Asc, ..LogQuery::default() },
+            LogQuery { usernames: Some(vec![name("bea")]), limit: 100, ..LogQuery::default() },
+            LogQuery { usernames: Some(Vec::new()), limit: 100, ..LogQuery::default() },
+            LogQuery { from, limit: 100, ..LogQuery::default() },
+            LogQuery {
+                category: Some("category1".to_string()),
+                value: Some("Option 1A".to_string()),
+                limit: 100,
+                ..LogQuery::default()
+            },
+            LogQuery { text: Some("note_3 100%".to_string()), limit: 100, ..LogQuery::default() },
+            LogQuery {
+                after: Some(LogCursor { timestamp: "2024-05-01T10:01:00+00:00".to_string(), id: 3 }),
+                limit: 100,
+                ..LogQuery::default()
+            },
+        ];
+        for query in &queries {
+            let mut results = Vec::new();
+            for repo in repos {
+                let logs = repo.query_logs(query).await.unwrap();
+                results.push(logs.into_iter().map(|log| log.id.unwrap()).collect::<Vec<_>>());
+            }
+            assert!(results.windows(2).all(|pair| pair[0] == pair[1]), "{:?}: {:?}", query, results);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_log_query_filters_and_paginates() {
+        let (state, _temp_dir) = create_sqlite_app_state().await;
+        let app = app(state.clone());
+        let token = register_user(&app, "historian", "correct horse").await;
+        let other_token = register_user(&app, "nosy", "correct horse").await;
+
+        // Seed five samples a minute apart
+        let mut user_state = UserState {
+            username: name("historian"),
+            text_entry: String::new(),
+            categories: BTreeMap::new(),
+            is_recording: true,
+            last_saved: None,
+            last_data: None,
+        };
+        state.repo.save_user_state(&user_state).await.unwrap();
+        for minute in 0..5 {
+            let option = if minute % 2 == 0 { "Option 1A" } else { "Option 1B" };
+            user_state.categories.insert("category1".to_string(), option.to_string());
+            let log = DataLog {
+                id: None,
+                username: name("historian"),
+                text_entry: format!("note {}", minute),
+                categories: user_state.categories.clone(),
+                timestamp: format!("2024-05-01T10:0{}:00+00:00", minute),
+                session_id: None,
+            };
+            state.repo.log_data_entry(&log).await.unwrap();
+        }
+
+        // Walk the history two rows at a time, oldest first
+        let mut seen = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let uri = match &cursor {
+                Some(cursor) => format!("/api/logs?sort=asc&limit=2&cursor={}", cursor),
+                None => "/api/logs?sort=asc&limit=2".to_string(),
+            };
+            let response = app.clone().oneshot(authed("GET", &uri, &token, None)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let page: LogPage = json_body(response).await;
+            seen.extend(page.items.into_iter().map(|log| log.text_entry));
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        assert_eq!(seen, ["note 0", "note 1", "note 2", "note 3", "note 4"]);
+
+        // Category value, text and time range filters combine
+        let response = app
+            .clone()
+            .oneshot(authed(
+                "GET",
+                "/api/logs?category=category1&value=Option%201A&from=2024-05-01T10:01:00Z",
+                &token,
+                None,
+            ))
+            .await
+            .unwrap();
+        let page: LogPage = json_body(response).await;
+        let texts: Vec<_> = page.items.iter().map(|log| log.text_entry.as_str()).collect();
+        assert_eq!(texts, ["note 4", "note 2"]);
+
+        // The frontend builds its query strings from the same parameters the handler reads
+        let params = LogQueryParams {
+            category: Some("category1".to_string()),
+            value: Some("Option 1A".to_string()),
+            from: Some("2024-05-01T10:01:00Z".parse().unwrap()),
+            sort: SortOrder::Asc,
+            ..LogQueryParams::default()
+        };
+        let uri = format!("/api/logs?{}", serde_urlencoded::to_string(&params).unwrap());
+        let response = app.clone().oneshot(authed("GET", &uri, &token, None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let page: LogPage = json_body(response).await;
+        let texts: Vec<_> = page.items.iter().map(|log| log.text_entry.as_str()).collect();
+        assert_eq!(texts, ["note 2", "note 4"]);
+
+        let response = app
+            .clone()
+            .oneshot(authed("GET", "/api/logs?q=TE%203", &token, None))
+            .await
+            .unwrap();
+        let page: LogPage = json_body(response).await;
+        assert_eq!(page.items.len(), 1);
+
+        // Other participants can't read this history
+        let response = app
+            .oneshot(authed("GET", "/api/logs?username=historian", &other_token, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_log_export_streams_csv() {
+        let (state, _temp_dir) = create_sqlite_app_state().await;
+        let app = app(state.clone());
+        let admin_token = register_user(&app, "admin", "correct horse").await;
+        let participant_token = register_user(&app, "alice", "correct horse").await;
+        register_user(&app, "bob", "correct horse").await;
+        register_user(&app, "carol", "correct horse").await;
+
+        for username in ["alice", "bob", "carol"] {
+            let user_state = UserState {
+                username: name(username),
+                text_entry: String::new(),
+                categories: BTreeMap::new(),
+                is_recording: true,
+                last_saved: None,
+                last_data: None,
+            };
+            state.repo.save_user_state(&user_state).await.unwrap();
+            for minute in 0..3 {
+                let mut categories = BTreeMap::new();
+                categories.insert("category2".to_string(), "Option 2B".to_string());
+                let log = DataLog {
+                    id: None,
+                    username: name(username),
+                    text_entry: format!("{}, minute {}", username, minute),
+                    categories,
+                    timestamp: format!("2024-05-01T10:0{}:00+00:00", minute),
+                    session_id: None,
+                };
+                state.repo.log_data_entry(&log).await.unwrap();
+            }
+        }
+
+        let response = app
+            .clone()
+            .oneshot(authed(
+                "GET",
+                "/api/logs/export?users=alice,bob&from=2024-05-01T10:01:00Z&to=2024-05-01T10:02:00Z",
+                &admin_token,
+                None,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/csv; charset=utf-8");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert_eq!(
+            body,
+            "username,text_entry,category1,category2,category3,category4,timestamp\n\
+             alice,\"alice, minute 1\",,Option 2B,,,2024-05-01T10:01:00+00:00\n\
+             bob,\"bob, minute 1\",,Option 2B,,,2024-05-01T10:01:00+00:00\n"
+        );
+
+        // Without a header, led by the row id
+        let response = app
+            .clone()
+            .oneshot(authed(
+                "GET",
+                "/api/logs/export?users=carol&header=false&include_id=true",
+                &admin_token,
+                None,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()["content-disposition"],
+            "attachment; filename=\"carol.csv\""
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let lines: Vec<_> = body.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("7,carol,"));
+
+        // Participants only export their own rows
+        let response = app
+            .clone()
+            .oneshot(authed("GET", "/api/logs/export", &participant_token, None))
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(String::from_utf8(body.to_vec()).unwrap().lines().count(), 4);
+
+        let response = app
+            .oneshot(authed("GET", "/api/logs/export?users=bob", &participant_token, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_recording_sessions_group_samples() {
+        let (state, _temp_dir) = create_sqlite_app_state().await;
+        let app = app(state.clone());
+        let admin_token = register_user(&app, "admin", "correct horse").await;
+        let token = register_user(&app, "sampler", "correct horse").await;
+
+        let coded_state = |text: &str| {
+            serde_json::json!({
+                "username": "sampler",
+                "text_entry": text,
+                "categories": {},
+                "is_recording": true, "last_saved": null, "last_data": null
+            })
+        };
+
+        // Without an open session the flag alone records nothing
+        let response = app
+            .clone()
+            .oneshot(authed("POST", "/api/state", &token, Some(coded_state("ignored"))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let saved = state.repo.get_user_state(&name("sampler")).await.unwrap().unwrap();
+        assert!(!saved.is_recording);
+
+        let mut sessions = Vec::new();
+        let mut logged = 0;
+        for label in ["first", "second"] {
+            let response = app
+                .clone()
+                .oneshot(authed("POST", "/api/state", &token, Some(coded_state(label))))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let response = app
+                .clone()
+                .oneshot(authed(
+                    "POST",
+                    "/api/sessions",
+                    &token,
+                    Some(serde_json::json!({ "label": label })),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+            let session: RecordingSession = json_body(response).await;
+            assert_eq!(session.label.as_deref(), Some(label));
+
+            // Only one session may be open at a time
+            let response = app
+                .clone()
+                .oneshot(authed("POST", "/api/sessions", &token, Some(serde_json::json!({}))))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CONFLICT);
+
+            logged += 2;
+            wait_for_samples(&state, "sampler", logged).await;
+
+            let uri = format!("/api/sessions/{}/stop", session.id);
+            let response = app.clone().oneshot(authed("POST", &uri, &token, None)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let stopped: RecordingSession = json_body(response).await;
+            assert!(stopped.ended_at.is_some());
+
+            let response = app.clone().oneshot(authed("POST", &uri, &token, None)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::CONFLICT);
+            sessions.push(session.id);
+            logged = count_logs(&state, None).await;
+        }
+        let saved = state.repo.get_user_state(&name("sampler")).await.unwrap().unwrap();
+        assert!(!saved.is_recording);
+
+        // Each session holds exactly its own samples
+        let uri = format!("/api/sessions/{}/samples", sessions[0]);
+        let response = app.clone().oneshot(authed("GET", &uri, &token, None)).await.unwrap();
+        let samples: Vec<DataLog> = json_body(response).await;
+        assert!(samples.len() >= 2);
+        assert!(samples.iter().all(|log| log.text_entry == "first"));
+        assert!(samples.iter().all(|log| log.session_id == Some(sessions[0])));
+
+        let response = app
+            .clone()
+            .oneshot(authed("GET", "/api/sessions?username=sampler", &admin_token, None))
+            .await
+            .unwrap();
+        let listed: Vec<RecordingSession> = json_body(response).await;
+        let ids: Vec<_> = listed.iter().map(|session| session.id).collect();
+        assert_eq!(ids, [sessions[1], sessions[0]]);
+
+        // Other participants can't see them
+        let other_token = register_user(&app, "other", "correct horse").await;
+        let response = app.oneshot(authed("GET", &uri, &other_token, None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_writes_everything_and_interrupts_recordings() {
+        let (state, temp_dir) = create_test_app_state().await;
+        let app = app(state.clone());
+        let token = register_user(&app, "ann", "correct horse").await;
+        save_blank_state(&state, "ann").await;
+        let response = app
+            .clone()
+            .oneshot(authed("POST", "/api/sessions", &token, Some(serde_json::json!({}))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let session: RecordingSession = json_body(response).await;
+        wait_for_samples(&state, "ann", 3).await;
+
+        // Another server's recording, which this one isn't sampling
+        let elsewhere = state
+            .repo
+            .start_recording_session(&name("bob"), None, &chrono::Utc::now().to_rfc3339())
+            .await
+            .unwrap()
+            .unwrap();
+
+        state.shutdown().await;
+        assert_eq!(state.recorder.active(), 0);
+
+        let interrupted = state.repo.get_recording_session(session.id).await.unwrap().unwrap();
+        assert!(interrupted.interrupted);
+        assert!(interrupted.ended_at.is_some());
+        let user_state = state.repo.get_user_state(&name("ann")).await.unwrap().unwrap();
+        assert!(!user_state.is_recording);
+        let untouched = state.repo.get_recording_session(elsewhere.id).await.unwrap().unwrap();
+        assert_eq!(untouched.ended_at, None);
+
+        // No sample lands after the session closed, and every one reached the CSV file
+        let logged = count_logs(&state, Some("ann")).await;
+        tokio::time::sleep(TEST_SAMPLE_INTERVAL * 3).await;
+        assert_eq!(count_logs(&state, Some("ann")).await, logged);
+        let csv = std::fs::read_to_string(temp_dir.path().join("data/csv/ann/ann.csv")).unwrap();
+        assert_eq!(csv.lines().count() as i64, 1 + logged);
+
+        // The connection pool is closed last
+        let (state, _temp_dir) = create_sqlite_app_state().await;
+        state.repo.ping().await.unwrap();
+        state.shutdown().await;
+        assert!(state.repo.ping().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_websocket_updates_are_acknowledged() {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::Message;
+
+        let (state, _temp_dir) = create_test_app_state().await;
+        let app = app(state.clone());
+        register_user(&app, "admin", "correct horse").await;
+        let token = register_user(&app, "typist", "correct horse").await;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        // A bad token never gets a socket
+        let url = format!("ws://{}/api/ws?token=nope", addr);
+        assert!(tokio_tungstenite::connect_async(url).await.is_err());
+
+        let url = format!("ws://{}/api/ws?token={}", addr, token);
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let mut exchange = async |message: serde_json::Value| -> serde_json::Value {
+            socket.send(Message::text(message.to_string())).await.unwrap();
+            loop {
+                if let Message::Text(text) = socket.next().await.unwrap().unwrap() {
+                    return serde_json::from_str(text.as_str()).unwrap();
+                }
+            }
+        };
+
+        let reply = exchange(serde_json::json!({
+            "type": "update", "seq": 1, "field": "text_entry", "value": "typed live"
+        }))
+        .await;
+        assert_eq!(reply["type"], "ack");
+        assert_eq!(reply["seq"], 1);
+        assert!(reply["saved_at"].as_str().unwrap().parse::<chrono::DateTime<chrono::Utc>>().is_ok());
+        assert_eq!(reply["state"]["text_entry"], "typed live");
+
+        let reply = exchange(serde_json::json!({
+            "type": "update", "seq": 2, "field": "category1", "value": "Option 1C"
+        }))
+        .await;
+        assert_eq!(reply["type"], "ack");
+        assert_eq!(reply["state"]["categories"]["category1"], "Option 1C");
+
+        // Values and fields are checked like any other save
+        let reply = exchange(serde_json::json!({
+            "type": "update", "seq": 3, "field": "category1", "value": "Not an option"
+        }))
+        .await;
+        assert_eq!(reply["type"], "error");
+        assert_eq!(reply["seq"], 3);
+
+        let reply = exchange(serde_json::json!({
+            "type": "update", "seq": 4, "username": "someone_else", "field": "text_entry", "value": "x"
+        }))
+        .await;
+        assert_eq!(reply["type"], "error");
+
+        let saved = state.repo.get_user_state(&name("typist")).await.unwrap().unwrap();
+        assert_eq!(saved.text_entry, "typed live");
+        assert_eq!(saved.categories.get("category1").map(String::as_str), Some("Option 1C"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_field_updates_all_land() {
+        let db_dir = tempdir().unwrap();
+        let db_url = format!("sqlite://{}?mode=rwc", db_dir.path().join("app.db").display());
+        let db = SqlitePool::connect(&db_url).await.unwrap();
+        run_migrations(&db).await.unwrap();
+        let repo: Arc<dyn StateRepository> = Arc::new(SqliteRepository::new(db));
+        repo.save_entry(&name("ann"), "", &BTreeMap::new()).await.unwrap();
+
+        // Every field written at once, as several open tabs would
+        let mut updates: Vec<_> = (1..=4)
+            .map(|n| {
+                let repo = repo.clone();
+                tokio::spawn(async move {
+                    let key = format!("category{}", n);
+                    let value = format!("Option {}A", n);
+                    repo.save_category(&name("ann"), &key, &value).await.unwrap();
+                })
+            })
+            .collect();
+        updates.push(tokio::spawn({
+            let repo = repo.clone();
+            async move {
+                repo.save_text_entry(&name("ann"), "typed").await.unwrap();
+            }
+        }));
+        for update in updates {
+            update.await.unwrap();
+        }
+
+        let saved = repo.get_user_state(&name("ann")).await.unwrap().unwrap();
+        assert_eq!(saved.text_entry, "typed");
+        assert_eq!(saved.categories.len(), 4);
+        for n in 1..=4 {
+            assert_eq!(saved.categories[&format!("category{}", n)], format!("Option {}A", n));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_live_dashboard_tracks_recordings() {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::Message;
+
+        let (state, _temp_dir) = create_test_app_state().await;
+        let app = app(state.clone());
+        let admin_token = register_user(&app, "supervisor", "correct horse").await;
+        let token = register_user(&app, "watched", "correct horse").await;
+
+        let response = app
+            .clone()
+            .oneshot(authed("GET", "/api/admin/live", &admin_token, None))
+            .await
+            .unwrap();
+        let snapshot: LiveSnapshot = json_body(response).await;
+        assert!(snapshot.recordings.is_empty());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn({
+            let app = app.clone();
+            async move { axum::serve(listener, app).await.unwrap() }
+        });
+        let connect = async |token: &str| {
+            let url = format!("ws://{}/api/ws?token={}", addr, token);
+            tokio_tungstenite::connect_async(url).await.unwrap().0
+        };
+        let subscribe = serde_json::json!({ "type": "subscribe" }).to_string();
+
+        // Participants don't get the feed
+        let mut socket = connect(&token).await;
+        socket.send(Message::text(subscribe.clone())).await.unwrap();
+        let Message::Text(text) = socket.next().await.unwrap().unwrap() else {
+            panic!("expected a text frame");
+        };
+        let reply: serde_json::Value = serde_json::from_str(text.as_str()).unwrap();
+        assert_eq!(reply["type"], "error");
+
+        let mut socket = connect(&admin_token).await;
+        socket.send(Message::text(subscribe)).await.unwrap();
+        let mut next_live = async || -> serde_json::Value {
+            loop {
+                let Message::Text(text) = socket.next().await.unwrap().unwrap() else {
+                    continue;
+                };
+                let message: serde_json::Value = serde_json::from_str(text.as_str()).unwrap();
+                if message["type"] == "live" {
+                    return message;
+                }
+            }
+        };
+        assert_eq!(next_live().await["recordings"], serde_json::json!([]));
+
+        // Starting a recording is pushed without asking
+        let response = app
+            .clone()
+            .oneshot(authed(
+                "POST",
+                "/api/sessions",
+                &token,
+                Some(serde_json::json!({ "label": "morning" })),
+            ))
+            .await
+            .unwrap();
+        let session: RecordingSession = json_body(response).await;
+        let live = loop {
+            let live = next_live().await;
+            if live["recordings"][0]["last_saved"].is_string() {
+                break live;
+            }
+        };
+        assert_eq!(live["recordings"][0]["username"], "watched");
+        assert_eq!(live["recordings"][0]["label"], "morning");
+        assert_eq!(live["recordings"][0]["session_id"], session.id);
+
+        // And so is stopping it
+        let uri = format!("/api/sessions/{}/stop", session.id);
+        app.clone().oneshot(authed("POST", &uri, &token, None)).await.unwrap();
+        loop {
+            if next_live().await["recordings"] == serde_json::json!([]) {
+                break;
+            }
+        }
+
+        // Once the admin logs out, the next change ends the feed instead of being pushed
+        let response = app
+            .clone()
+            .oneshot(authed("POST", "/api/auth/logout", &admin_token, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        app.clone()
+            .oneshot(authed("POST", "/api/sessions", &token, Some(serde_json::json!({}))))
+            .await
+            .unwrap();
+        let reply = loop {
+            match socket.next().await {
+                Some(Ok(Message::Text(text))) => {
+                    break serde_json::from_str::<serde_json::Value>(text.as_str()).unwrap();
+                }
+                Some(Ok(_)) => continue,
+                other => panic!("expected an error before the socket closed, got {:?}", other),
+            }
+        };
+        assert_eq!(reply["type"], "error");
+        assert!(matches!(
+            socket.next().await,
+            None | Some(Ok(Message::Close(_))) | Some(Err(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_usernames_are_rejected() {
+        let (state, _temp_dir) = create_test_app_state().await;
+        let app = app(state.clone());
+        let admin_token = register_user(&app, "admin", "correct horse").await;
+
+        for username in ["../../etc/x", "a/b", "nul\0byte", "x", " .hidden"] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("POST")
+                        .uri("/api/auth/register")
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(
+                            serde_json::json!({ "username": username, "password": "correct horse" })
+                                .to_string(),
+                        ))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY, "{:?}", username);
+            let body: serde_json::Value = json_body(response).await;
+            assert_eq!(body["code"], "invalid_request", "{:?}", username);
+            assert!(body["detail"].as_str().unwrap().contains("username"), "{:?}", username);
+        }
+
+        // Path segments are validated the same way
+        let response = app
+            .clone()
+            .oneshot(authed("GET", "/api/admin/csv/..%2F..%2Fetc%2Fpasswd", &admin_token, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: serde_json::Value = json_body(response).await;
+        assert_eq!(body["code"], "invalid_username");
+
+        // Full-width letters normalize to the name that is already taken
+        register_user(&app, "bob", "correct horse").await;
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/api/auth/register")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(
+                        serde_json::json!({ "username": "ｂｏｂ", "password": "correct horse" })
+                            .to_string(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_usernames_differing_in_case_get_separate_csv_files() {
+        let (state, temp_dir) = create_test_app_state().await;
+        let app = app(state.clone());
+        register_user(&app, "admin", "correct horse").await;
+
+        for username in ["Casey", "casey"] {
+            let token = register_user(&app, username, "correct horse").await;
+            let response = app
+                .clone()
+                .oneshot(authed("POST", "/api/sessions", &token, Some(serde_json::json!({}))))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+            wait_for_samples(&state, username, 1).await;
+            state.recorder.stop(&name(username)).await;
+        }
+
+        let csv_dir = temp_dir.path().join("data/csv");
+        let upper = std::fs::read_to_string(csv_dir.join("~000043asey/~000043asey.csv")).unwrap();
+        let lower = std::fs::read_to_string(csv_dir.join("casey/casey.csv")).unwrap();
+        assert!(upper.lines().nth(1).unwrap().starts_with("Casey,"));
+        assert!(lower.lines().nth(1).unwrap().starts_with("casey,"));
+    }
+
+    #[tokio::test]
+    async fn test_csv_files_rotate_per_session_and_compress() {
+        let settings = CsvSettings {
+            rotation: RotationPolicy::Session,
+            compress: true,
+            ..CsvSettings::default()
+        };
+        let (state, temp_dir) = create_test_app_state_with_csv(
+            Arc::new(InMemoryRepository::new()),
+            Codebook::default(),
+            settings,
+        );
+        let app = app(state.clone());
+        let admin_token = register_user(&app, "admin", "correct horse").await;
+        let token = register_user(&app, "rotator", "correct horse").await;
+
+        let mut session_ids = Vec::new();
+        for _ in 0..2 {
+            let sampled = count_logs(&state, Some("rotator")).await;
+            let response = app
+                .clone()
+                .oneshot(authed("POST", "/api/sessions", &token, Some(serde_json::json!({}))))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+            let session: RecordingSession = json_body(response).await;
+            session_ids.push(session.id);
+            wait_for_samples(&state, "rotator", sampled + 1).await;
+
+            let uri = format!("/api/sessions/{}/stop", session.id);
+            let response = app.clone().oneshot(authed("POST", &uri, &token, None)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        let response = app
+            .clone()
+            .oneshot(authed("GET", "/api/admin/csv/rotator/files", &admin_token, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let index: CsvIndex = json_body(response).await;
+        let files: Vec<_> = index.files.iter().map(|entry| entry.file.as_str()).collect();
+        assert_eq!(
+            files,
+            [
+                format!("rotator_session-{}.csv.gz", session_ids[0]),
+                format!("rotator_session-{}.csv.gz", session_ids[1]),
+            ]
+        );
+        assert!(index.files.iter().all(|entry| entry.compressed && entry.closed_at.is_some()));
+        let rows: u64 = index.files.iter().map(|entry| entry.rows).sum();
+        assert_eq!(rows as i64, count_logs(&state, Some("rotator")).await);
+
+        // The index is kept next to the files
+        let user_dir = temp_dir.path().join("data/csv/rotator");
+        assert!(user_dir.join("index.json").exists());
+        assert!(user_dir.join(files[0]).exists());
+
+        // Files are served as they are on disk, and only if the index lists them
+        let uri = format!("/api/admin/csv/rotator/files/{}", files[0]);
+        let response = app.clone().oneshot(authed("GET", &uri, &admin_token, None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "application/gzip");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..2], [0x1f, 0x8b]);
+
+        let response = app
+            .clone()
+            .oneshot(authed("GET", "/api/admin/csv/rotator/files/index.json", &admin_token, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // The plain download gives the newest file
+        let response = app
+            .oneshot(authed("GET", "/api/admin/csv/rotator", &admin_token, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-disposition"],
+            format!("attachment; filename=\"{}\"", files[1]).as_str()
+        );
+    }
+
+    #[test]
+    fn test_csv_writers_are_evicted_and_reopened_without_a_second_header() {
+        let temp_dir = tempdir().unwrap();
+        let settings = CsvSettings {
+            max_open_writers: 2,
+            writer_idle_timeout: Duration::ZERO,
+            ..CsvSettings::default()
+        };
+        let header = vec!["username".to_string(), "text_entry".to_string()];
+        let exporter = CsvExporter::new(temp_dir.path().to_path_buf(), settings, header);
+        let append = |username: &str| {
+            let row = CsvRow {
+                session_id: None,
+                timestamp: chrono::Utc::now(),
+                record: vec![username.to_string(), "sample".to_string()],
+            };
+            exporter.append(&name(username), &row).unwrap();
+        };
+
+        // The third user pushes out the least recently used one, and so on
+        for username in ["ann", "ben", "cat", "ann", "ann"] {
+            append(username);
+        }
+        let stats = exporter.writer_stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions, stats.open), (1, 4, 2, 2));
+
+        // Everything idle for the (zero) timeout is closed
+        assert_eq!(exporter.close_idle_writers(), 2);
+        assert_eq!(exporter.writer_stats().open, 0);
+
+        append("ann");
+        append("ben");
+        exporter.flush([&name("ann"), &name("ben")], false).unwrap();
+        let ann = std::fs::read_to_string(temp_dir.path().join("ann/ann.csv")).unwrap();
+        assert_eq!(ann, "username,text_entry\n".to_string() + &"ann,sample\n".repeat(4));
+        let ben = std::fs::read_to_string(temp_dir.path().join("ben/ben.csv")).unwrap();
+        assert_eq!(ben, "username,text_entry\nben,sample\nben,sample\n");
+        assert_eq!(exporter.index(&name("ann")).unwrap().files[0].rows, 4);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_samples_are_written_in_batches() {
+        let (state, temp_dir) = create_sqlite_app_state().await;
+        let users = ["ann", "ben", "cat", "dan"];
+        for username in users {
+            save_blank_state(&state, username).await;
+        }
+
+        let writes: Vec<_> = (0..200)
+            .map(|n| {
+                let state = Arc::clone(&state);
+                let sample = sample(users[n % users.len()], &format!("sample {}", n));
+                tokio::spawn(async move { state.log_writer.write(sample).await })
+            })
+            .collect();
+        for write in writes {
+            write.await.unwrap().unwrap();
+        }
+
+        // Every acknowledged sample is in the database and flushed to its CSV file
+        assert_eq!(count_logs(&state, None).await, 200);
+        for username in users {
+            let csv = std::fs::read_to_string(
+                temp_dir.path().join(format!("data/csv/{0}/{0}.csv", username)),
+            )
+            .unwrap();
+            assert_eq!(csv.lines().count(), 1 + 50);
+        }
+
+        let stats = state.log_writer.stats();
+        assert_eq!(stats.samples, 200);
+        assert!(stats.batches < stats.samples, "no batching in {:?}", stats);
+    }
+
+    // Two servers on one database, with a session left open by a third that went away:
+    // exactly one of them samples it, and the other adopts it once the lease runs out
+    async fn assert_one_server_samples_each_session(repo: Arc<dyn StateRepository>) {
+        let (first, _first_dir) = create_test_app_state_with(repo.clone(), Codebook::default());
+        let (second, _second_dir) = create_test_app_state_with(repo.clone(), Codebook::default());
+        register_user(&app(first.clone()), "ann", "correct horse").await;
+        let started_at = chrono::Utc::now().to_rfc3339();
+        repo.start_recording_session(&name("ann"), None, &started_at).await.unwrap().unwrap();
+
+        let (resumed_first, resumed_second) = tokio::join!(
+            first.recorder.resume_open_sessions(first.clone()),
+            second.recorder.resume_open_sessions(second.clone()),
+        );
+        assert_eq!(resumed_first.unwrap() + resumed_second.unwrap(), 1);
+        let (owner, other) = if first.recorder.is_sampling(&name("ann")) {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        assert!(!other.recorder.is_sampling(&name("ann")));
+        wait_for_samples(&owner, "ann", 2).await;
+
+        // The owner dies without closing anything; its lease runs out
+        owner.recorder.stop(&name("ann")).await;
+        tokio::time::sleep(owner.recorder.lease() * 2).await;
+        assert_eq!(other.recorder.resume_open_sessions(other.clone()).await.unwrap(), 1);
+        assert!(other.recorder.is_sampling(&name("ann")));
+
+        // And the old owner can't take it back while the new one renews it
+        assert_eq!(owner.recorder.resume_open_sessions(owner.clone()).await.unwrap(), 0);
+        other.recorder.stop(&name("ann")).await;
+    }
+
+    #[tokio::test]
+    async fn test_only_one_server_resumes_a_session() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        run_migrations(&db).await.unwrap();
+        assert_one_server_samples_each_session(Arc::new(SqliteRepository::new(db))).await;
+        assert_one_server_samples_each_session(Arc::new(InMemoryRepository::new())).await;
+    }
+
+    // Helper function to check that a session stopped through another server gets no
+    // samples after its end time from the server sampling it
+    async fn assert_stop_elsewhere_ends_sampling(repo: Arc<dyn StateRepository>) {
+        let (owner, _owner_dir) = create_test_app_state_with(repo.clone(), Codebook::default());
+        let (other, _other_dir) = create_test_app_state_with(repo.clone(), Codebook::default());
+        let token = register_user(&app(owner.clone()), "ann", "correct horse").await;
+        let started_at = chrono::Utc::now().to_rfc3339();
+        let session =
+            repo.start_recording_session(&name("ann"), None, &started_at).await.unwrap().unwrap();
+        assert!(owner.recorder.start(owner.clone(), &name("ann"), session.id).await.unwrap());
+        wait_for_samples(&owner, "ann", 3).await;
+
+        let uri = format!("/api/sessions/{}/stop", session.id);
+        let response =
+            app(other.clone()).oneshot(authed("POST", &uri, &token, None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let stopped: RecordingSession = json_body(response).await;
+        let ended_at = chrono::DateTime::parse_from_rfc3339(&stopped.ended_at.unwrap()).unwrap();
+
+        // The owner gives the session up at its next tick; a sample it took too late is dropped
+        tokio::time::sleep(owner.recorder.lease()).await;
+        assert!(!owner.recorder.is_sampling(&name("ann")));
+        let mut late = sample("ann", "late");
+        late.log.session_id = Some(session.id);
+        assert!(repo.record_samples(&[late]).await.unwrap().is_empty());
+
+        let logs = repo.session_logs(session.id).await.unwrap();
+        assert!(logs.len() >= 3);
+        for log in &logs {
+            assert!(chrono::DateTime::parse_from_rfc3339(&log.timestamp).unwrap() <= ended_at);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stopping_a_session_elsewhere_ends_its_sampling() {
+        let db = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        run_migrations(&db).await.unwrap();
+        assert_stop_elsewhere_ends_sampling(Arc::new(SqliteRepository::new(db))).await;
+        assert_stop_elsewhere_ends_sampling(Arc::new(InMemoryRepository::new())).await;
+    }
+
+    #[tokio::test]
+    async fn test_client_saves_leave_server_owned_fields_alone() {
+        let (state, _temp_dir) = create_sqlite_app_state().await;
+        let app = app(state.clone());
+        let token = register_user(&app, "ann", "correct horse").await;
+
+        // A session and a sample the client hasn't heard about yet
+        state
+            .repo
+            .start_recording_session(&name("ann"), None, &chrono::Utc::now().to_rfc3339())
+            .await
+            .unwrap();
+        let logs = state.repo.record_samples(&[sample("ann", "sampled")]).await.unwrap();
+
+        let stale = serde_json::json!({
+            "username": "ann", "text_entry": "typed", "categories": {},
+            "is_recording": false, "last_saved": null, "last_data": "stale"
+        });
+        let response = app
+            .oneshot(authed("POST", "/api/state", &token, Some(stale)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let returned: UserState = json_body(response).await;
+
+        let saved = state.repo.get_user_state(&name("ann")).await.unwrap().unwrap();
+        assert_eq!(returned, saved);
+        assert_eq!(saved.text_entry, "typed");
+        assert!(saved.is_recording);
+        assert_eq!(saved.last_data.as_deref(), Some("Text: sampled"));
+        assert_eq!(saved.last_saved, Some(logs[0].timestamp.clone()));
+    }
+
+    #[tokio::test]
+    async fn test_csv_rows_left_in_the_outbox_are_written_once() {
+        let (state, temp_dir) = create_sqlite_app_state().await;
+        save_blank_state(&state, "ann").await;
+
+        // Both samples are committed, but only the first reached the CSV before a crash
+        let logs = state
+            .repo
+            .record_samples(&[sample("ann", "first"), sample("ann", "second")])
+            .await
+            .unwrap();
+        state.csv.append(&name("ann"), &CsvRow::from_log(&logs[0], &state.codebook)).unwrap();
+        assert_eq!(state.repo.csv_outbox(10).await.unwrap().len(), 2);
+
+        state.log_writer.flush().await.unwrap();
+        assert!(state.repo.csv_outbox(10).await.unwrap().is_empty());
+        let csv = std::fs::read_to_string(temp_dir.path().join("data/csv/ann/ann.csv")).unwrap();
+        let rows: Vec<_> = csv.lines().skip(1).collect();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].starts_with("ann,first,"));
+        assert!(rows[1].starts_with("ann,second,"));
+
+        // The latest sample was saved in the same transaction
+        let saved = state.repo.get_user_state(&name("ann")).await.unwrap().unwrap();
+        assert_eq!(saved.last_data.as_deref(), Some("Text: second"));
+        assert_eq!(saved.last_saved, Some(logs[1].timestamp.clone()));
+    }
+
+    #[tokio::test]
+    async fn test_buffered_csv_rows_stay_in_the_outbox_until_flushed() {
+        let (state, temp_dir) = create_sqlite_app_state().await;
+        save_blank_state(&state, "ann").await;
+        let writer = LogWriter::spawn(
+            state.repo.clone(),
+            state.csv.clone(),
+            state.codebook.clone(),
+            LogWriterSettings {
+                sync: SyncPolicy::Buffered,
+                ..LogWriterSettings::default()
+            },
+        );
+
+        // Acknowledged, but the row may still be in a buffer a crash would lose
+        writer.write(sample("ann", "buffered")).await.unwrap();
+        assert_eq!(state.repo.csv_outbox(10).await.unwrap().len(), 1);
+
+        writer.flush().await.unwrap();
+        assert!(state.repo.csv_outbox(10).await.unwrap().is_empty());
+        let csv = std::fs::read_to_string(temp_dir.path().join("data/csv/ann/ann.csv")).unwrap();
+        let rows: Vec<_> = csv.lines().skip(1).collect();
+        assert_eq!(rows.len(), 1);
+        assert!(rows[0].starts_with("ann,buffered,"));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_rebuilds_csv_files_that_drifted() {
+        let (state, temp_dir) = create_sqlite_app_state().await;
+        save_blank_state(&state, "ann").await;
+        for text in ["one", "two", "three"] {
+            state.log_writer.write(sample("ann", text)).await.unwrap();
+        }
+        let ann = name("ann");
+        let reconcile = |repair| {
+            reconcile_user(state.repo.as_ref(), &state.csv, &state.codebook, &ann, repair)
+        };
+        assert!(reconcile(false).await.unwrap().is_consistent());
+
+        // One row edited by hand, another lost
+        let path = temp_dir.path().join("data/csv/ann/ann.csv");
+        let csv = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        let edited = format!("{}\n{}\n{}\n", lines[0], lines[1].replace(",one,", ",uno,"), lines[2]);
+        std::fs::write(&path, edited).unwrap();
+
+        let report = reconcile(false).await.unwrap();
+        assert_eq!(
+            (report.db_rows, report.csv_rows, report.missing, report.extra, report.changed),
+            (3, 2, 1, 0, 1)
+        );
+        assert!(!report.rebuilt);
+
+        let report = reconcile(true).await.unwrap();
+        assert!(report.rebuilt);
+        assert!(reconcile(false).await.unwrap().is_consistent());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), csv);
+
+        // Samples keep landing in the rebuilt file
+        state.log_writer.write(sample("ann", "four")).await.unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(csv.lines().count(), 5);
+        assert!(csv.lines().last().unwrap().starts_with("ann,four,"));
+    }
+
+    #[tokio::test]
+    async fn test_csv_files_are_rebuilt_while_the_server_writes_to_them() {
+        let (state, temp_dir) = create_sqlite_app_state().await;
+        let app = app(state.clone());
+        let admin_token = register_user(&app, "admin", "correct horse").await;
+        register_user(&app, "ann", "correct horse").await;
+        save_blank_state(&state, "ann").await;
+        for text in ["one", "two", "three"] {
+            state.log_writer.write(sample("ann", text)).await.unwrap();
+        }
+        let path = temp_dir.path().join("data/csv/ann/ann.csv");
+        let written = std::fs::read_to_string(&path).unwrap();
+
+        // A deleted file comes back as it was
+        std::fs::remove_file(&path).unwrap();
+        let response = app
+            .clone()
+            .oneshot(authed("POST", "/api/admin/csv/ann/rebuild", &admin_token, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let index: CsvIndex = json_body(response).await;
+        assert_eq!(index.files.len(), 1);
+        assert_eq!(index.files[0].rows, 3);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), written);
+
+        // A rebuild from another process, while a sample is committed but not yet appended
+        let committed = state.repo.record_samples(&[sample("ann", "four")]).await.unwrap();
+        let elsewhere = Arc::new(CsvExporter::new(
+            temp_dir.path().join("data/csv"),
+            CsvSettings::default(),
+            state.codebook.csv_header(),
+        ));
+        rebuild_user(state.repo.as_ref(), &elsewhere, &state.codebook, &name("ann"))
+            .await
+            .unwrap();
+        assert!(state.repo.csv_outbox(10).await.unwrap().is_empty());
+
+        // The server picks up the new directory and doesn't append that sample twice
+        let row = CsvRow::from_log(&committed[0], &state.codebook);
+        state.csv.append(&name("ann"), &row).unwrap();
+        state.log_writer.write(sample("ann", "five")).await.unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        let texts: Vec<_> = csv
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').nth(1).unwrap())
+            .collect();
+        assert_eq!(texts, ["one", "two", "three", "four", "five"]);
+        assert_eq!(state.csv.index(&name("ann")).unwrap().files[0].rows, 5);
+
+        let response = app
+            .oneshot(authed("POST", "/api/admin/rebuild-csv", &admin_token, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let rebuilt: serde_json::Value = json_body(response).await;
+        assert_eq!(
+            rebuilt,
+            serde_json::json!([
+                { "username": "admin", "files": 0, "rows": 0 },
+                { "username": "ann", "files": 1, "rows": 5 },
+            ])
+        );
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), csv);
+    }
+
+    #[tokio::test]
+    async fn test_csv_files_keep_the_dialect_they_were_started_with() {
+        let excel = CsvDialect {
+            delimiter: ';',
+            line_ending: LineEnding::Crlf,
+            bom: true,
+            timestamp_format: "%d.%m.%Y %H:%M:%S".parse().unwrap(),
+            ..CsvDialect::default()
+        };
+        let settings = CsvSettings {
+            dialect: excel.clone(),
+            ..CsvSettings::default()
+        };
+        let (state, temp_dir) = create_test_app_state_with_csv(
+            Arc::new(InMemoryRepository::new()),
+            Codebook::default(),
+            settings,
+        );
+        let app = app(state.clone());
+        let token = register_user(&app, "ann", "correct horse").await;
+        save_blank_state(&state, "ann").await;
+        for text in ["one", "two; three"] {
+            state.log_writer.write(sample("ann", text)).await.unwrap();
+        }
+        state.log_writer.flush().await.unwrap();
+
+        let user_dir = temp_dir.path().join("data/csv/ann");
+        let written = std::fs::read(user_dir.join("ann.csv")).unwrap();
+        assert!(written.starts_with(b"\xEF\xBB\xBFusername;text_entry;"));
+        let written = String::from_utf8(written).unwrap();
+        let rows: Vec<_> = written.split("\r\n").skip(1).collect();
+        assert!(rows[0].starts_with("ann;one;"));
+        assert!(rows[1].starts_with("ann;\"two; three\";"));
+        let timestamp = rows[0].rsplit(';').next().unwrap();
+        assert!(chrono::NaiveDateTime::parse_from_str(timestamp, "%d.%m.%Y %H:%M:%S").is_ok());
+        assert_eq!(state.csv.index(&name("ann")).unwrap().files[0].dialect, excel);
+
+        // Read back in their own dialect
+        let ann = name("ann");
+        let report = reconcile_user(state.repo.as_ref(), &state.csv, &state.codebook, &ann, false)
+            .await
+            .unwrap();
+        assert!(report.is_consistent(), "{:?}", report);
+
+        // After a change of dialect the old file is left as it is and a new one started
+        let restarted = CsvExporter::new(
+            temp_dir.path().join("data/csv"),
+            CsvSettings::default(),
+            state.codebook.csv_header(),
+        );
+        let log = state.repo.record_samples(&[sample("ann", "four")]).await.unwrap();
+        restarted.append(&name("ann"), &CsvRow::from_log(&log[0], &state.codebook)).unwrap();
+        restarted.flush([&name("ann")], false).unwrap();
+        assert_eq!(std::fs::read_to_string(user_dir.join("ann.csv")).unwrap(), written);
+        let index = restarted.index(&name("ann")).unwrap();
+        assert_eq!(index.files.len(), 2);
+        assert_eq!(index.files[1].dialect, CsvDialect::default());
+        let plain = std::fs::read_to_string(user_dir.join(&index.files[1].file)).unwrap();
+        assert!(plain.starts_with("username,text_entry,"));
+        assert!(plain.contains(&format!("ann,four,,,,,{}", log[0].timestamp)));
+
+        // Exports use the configured dialect unless asked otherwise
+        let response = app
+            .clone()
+            .oneshot(authed("GET", "/api/logs/export?users=ann", &token, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(body.starts_with(b"\xEF\xBB\xBFusername;text_entry;"));
+
+        let response = app
+            .clone()
+            .oneshot(authed(
+                "GET",
+                "/api/logs/export?users=ann&delimiter=tab&bom=false&line_ending=lf&timestamp_format=rfc3339",
+                &token,
+                None,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.starts_with("username\ttext_entry\t"));
+        assert!(body.ends_with(&format!("ann\tfour\t\t\t\t\t{}\n", log[0].timestamp)));
+
+        let response = app
+            .oneshot(authed("GET", "/api/logs/export?users=ann&delimiter=%3B%3B", &token, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_rotation_policies_parse() {
+        assert_eq!("none".parse::<RotationPolicy>().unwrap(), RotationPolicy::None);
+        assert_eq!("daily".parse::<RotationPolicy>().unwrap(), RotationPolicy::Day);
+        assert_eq!("session".parse::<RotationPolicy>().unwrap(), RotationPolicy::Session);
+        assert_eq!(
+            "size:10m".parse::<RotationPolicy>().unwrap(),
+            RotationPolicy::Size { max_bytes: 10 * 1024 * 1024 }
+        );
+        assert!("size:0".parse::<RotationPolicy>().is_err());
+        assert!("hourly".parse::<RotationPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_csv_dialect_options_parse() {
+        assert_eq!(CsvDialect::parse_delimiter(";").unwrap(), ';');
+        assert_eq!(CsvDialect::parse_delimiter("tab").unwrap(), '\t');
+        assert!(CsvDialect::parse_delimiter(";;").is_err());
+        assert!(CsvDialect::parse_delimiter("\"").is_err());
+        assert_eq!("non-numeric".parse::<QuoteStyle>().unwrap(), QuoteStyle::NonNumeric);
+        assert!("sometimes".parse::<QuoteStyle>().is_err());
+        assert_eq!("LF".parse::<LineEnding>().unwrap(), LineEnding::Lf);
+        assert!("cr".parse::<LineEnding>().is_err());
+
+        assert_eq!("rfc3339".parse::<TimestampFormat>().unwrap(), TimestampFormat::Rfc3339);
+        assert!("%Y-%m-%d %Q".parse::<TimestampFormat>().is_err());
+        let seconds: TimestampFormat = "%Y-%m-%d %H:%M:%S".parse().unwrap();
+        assert_eq!(seconds.format("2024-05-01T10:00:00.750+02:00"), "2024-05-01 08:00:00");
+        let timestamp = chrono::DateTime::parse_from_rfc3339("2024-05-01T08:00:00.750Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        assert_eq!(
+            seconds.as_written(timestamp),
+            seconds.parse("2024-05-01 08:00:00")
+        );
+    }
+
+    #[test]
+    fn test_config_layers_override_the_file_and_defaults() {
+        let defaults = Config::resolve(ConfigLayer::default()).unwrap();
+        assert_eq!(defaults.bind.to_string(), "127.0.0.1:3000");
+        assert_eq!(defaults.database_url, "sqlite://data/app.db");
+        assert_eq!(defaults.csv_dir, std::path::Path::new("data/csv"));
+        assert_eq!(defaults.cors_origins, CorsOrigins::Any);
+
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("backend.toml");
+        std::fs::write(
+            &path,
+            r#"
+bind = "0.0.0.0:8080"
+data_dir = "/srv/study"
+cors_origins = ["https://study.example.org"]
+sample_interval_secs = 0.5
+log_level = "debug"
+"#,
+        )
+        .unwrap();
+        let file = ConfigLayer::from_file(&path).unwrap();
+        let flags = ConfigLayer {
+            bind: Some("127.0.0.1:4000".parse().unwrap()),
+            log_level: Some("warn,axum_backend=debug".to_string()),
+            ..ConfigLayer::default()
+        };
+        let config = Config::resolve(flags.or(file)).unwrap();
+        assert_eq!(config.bind.to_string(), "127.0.0.1:4000");
+        assert_eq!(config.data_dir, std::path::Path::new("/srv/study"));
+        assert_eq!(config.database_url, "sqlite:///srv/study/app.db");
+        assert_eq!(config.csv_dir, std::path::Path::new("/srv/study/csv"));
+        assert_eq!(
+            config.cors_origins,
+            CorsOrigins::List(vec!["https://study.example.org".parse().unwrap()])
+        );
+        assert_eq!(config.sample_interval, Duration::from_millis(500));
+        assert_eq!(config.log_level, "warn,axum_backend=debug");
+    }
+
+    #[test]
+    fn test_invalid_config_is_rejected() {
+        let resolve = |layer: ConfigLayer| Config::resolve(layer).unwrap_err();
+        let origins = |origins: &[&str]| ConfigLayer {
+            cors_origins: Some(origins.iter().map(|origin| origin.to_string()).collect()),
+            ..ConfigLayer::default()
+        };
+        assert!(matches!(resolve(origins(&["study.example.org"])), ConfigError::CorsOrigin(_)));
+        assert!(matches!(
+            resolve(origins(&["https://study.example.org/app"])),
+            ConfigError::CorsOrigin(_)
+        ));
+        assert!(matches!(
+            resolve(origins(&["*", "https://study.example.org"])),
+            ConfigError::CorsOrigin(_)
+        ));
+        for secs in [0.0, -1.0, f64::NAN] {
+            let layer = ConfigLayer { sample_interval_secs: Some(secs), ..ConfigLayer::default() };
+            assert!(matches!(resolve(layer), ConfigError::SampleInterval(_)));
+        }
+        let layer = ConfigLayer {
+            log_level: Some("info,=[".to_string()),
+            ..ConfigLayer::default()
+        };
+        assert!(matches!(resolve(layer), ConfigError::LogLevel(..)));
+
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("backend.toml");
+        std::fs::write(&path, "bind_address = \"0.0.0.0:8080\"\n").unwrap();
+        assert!(matches!(ConfigLayer::from_file(&path), Err(ConfigError::Parse { .. })));
+        std::fs::write(&path, "sample_interval_secs = \"fast\"\n").unwrap();
+        assert!(matches!(ConfigLayer::from_file(&path), Err(ConfigError::Parse { .. })));
+        assert!(matches!(
+            Config::load(Some(&temp_dir.path().join("missing.toml")), ConfigLayer::default()),
+            Err(ConfigError::Read { .. })
+        ));
+    }
+
+    // Helper function to connect to the Postgres server in `TEST_DATABASE_URL`, if any,
+    // inside a fresh schema so runs don't see each other's rows
+    #[cfg(feature = "postgres")]
+    async fn postgres_test_pool() -> Option<(sqlx::PgPool, String)> {
+        use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+        use std::str::FromStr;
+
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL is not set; skipping PostgreSQL test");
+            return None;
+        };
+        let schema = format!(
+            "test_{}_{}",
+            std::process::id(),
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        );
+        let admin = sqlx::PgPool::connect(&url).await.unwrap();
+        sqlx::query(&format!("CREATE SCHEMA {}", schema))
+            .execute(&admin)
+            .await
+            .unwrap();
+        admin.close().await;
+
+        let options = PgConnectOptions::from_str(&url)
+            .unwrap()
+            .options([("search_path", schema.as_str())]);
+        let pool = PgPoolOptions::new().connect_with(options).await.unwrap();
+        assert_eq!(
+            axum_backend::db::migrations::run_postgres_migrations(&pool).await.unwrap(),
+            latest_version()
+        );
+        Some((pool, schema))
+    }
+
+    #[cfg(feature = "postgres")]
+    async fn drop_postgres_schema(pool: sqlx::PgPool, schema: &str) {
+        sqlx::query(&format!("DROP SCHEMA {} CASCADE", schema))
+            .execute(&pool)
+            .await
+            .unwrap();
+        pool.close().await;
+    }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    async fn test_postgres_repository_agrees_with_sqlite() {
+        use axum_backend::db::postgres::PostgresRepository;
+
+        let Some((pool, schema)) = postgres_test_pool().await else {
+            return;
+        };
+        let sqlite = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        run_migrations(&sqlite).await.unwrap();
+        assert_repositories_agree(&[
+            Arc::new(SqliteRepository::new(sqlite)),
+            Arc::new(PostgresRepository::new(pool.clone())),
+        ])
+        .await;
+
+        drop_postgres_schema(pool, &schema).await;
+    }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    async fn test_postgres_backs_recording_sessions() {
+        use axum_backend::db::postgres::PostgresRepository;
+
+        let Some((pool, schema)) = postgres_test_pool().await else {
+            return;
+        };
+        let (state, _temp_dir) = create_test_app_state_with(
+            Arc::new(PostgresRepository::new(pool.clone())),
+            Codebook::default(),
+        );
+        let app = app(state.clone());
+        register_user(&app, "admin", "correct horse").await;
+        let token = register_user(&app, "pgsampler", "correct horse").await;
+
+        let response = app
+            .clone()
+            .oneshot(authed(
+                "POST",
+                "/api/state",
+                &token,
+                Some(serde_json::json!({
+                    "username": "pgsampler",
+                    "text_entry": "on postgres",
+                    "categories": { "category1": "Option 1A" },
+                    "is_recording": false,
+                })),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let start = || authed("POST", "/api/sessions", &token, Some(serde_json::json!({})));
+        let response = app.clone().oneshot(start()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let session: RecordingSession = json_body(response).await;
+        let response = app.clone().oneshot(start()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        wait_for_samples(&state, "pgsampler", 2).await;
+        let uri = format!("/api/sessions/{}/stop", session.id);
+        let response = app.clone().oneshot(authed("POST", &uri, &token, None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let uri = format!("/api/sessions/{}/samples", session.id);
+        let response = app.clone().oneshot(authed("GET", &uri, &token, None)).await.unwrap();
+        let samples: Vec<DataLog> = json_body(response).await;
+        assert!(samples.len() >= 2);
+        assert!(samples.iter().all(|sample| sample.text_entry == "on postgres"));
+        assert_eq!(samples[0].categories["category1"], "Option 1A");
+
+        let saved = state.repo.get_user_state(&name("pgsampler")).await.unwrap().unwrap();
+        assert!(!saved.is_recording);
+        assert!(saved.last_saved.is_some());
+
+        drop_postgres_schema(pool, &schema).await;
+    }
+
+    #[cfg(feature = "postgres")]
+    #[tokio::test]
+    async fn test_postgres_servers_share_open_sessions() {
+        use axum_backend::db::postgres::PostgresRepository;
+
+        let Some((pool, schema)) = postgres_test_pool().await else {
+            return;
+        };
+        assert_one_server_samples_each_session(Arc::new(PostgresRepository::new(pool.clone())))
+            .await;
+        drop_postgres_schema(pool, &schema).await;
+
+        let Some((pool, schema)) = postgres_test_pool().await else {
+            return;
+        };
+        assert_stop_elsewhere_ends_sampling(Arc::new(PostgresRepository::new(pool.clone())))
+            .await;
+        drop_postgres_schema(pool, &schema).await;
     }
-}
\ No newline at end of file
+}
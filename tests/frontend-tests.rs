@@ -7,7 +7,12 @@ mod frontend_tests {
         data_entry_screen::DataEntryScreen,
         dropdown_select::DropdownSelect,
     };
-    use leptos_frontend::models::user_state::UserState;
+    use leptos_frontend::models::{
+        auth::Credentials,
+        codebook::{Codebook, CodebookField},
+        user_state::{StateField, UserState},
+    };
+    use std::collections::BTreeMap;
 
     wasm_bindgen_test_configure!(run_in_browser);
 
@@ -16,36 +21,54 @@ mod frontend_tests {
         UserState {
             username: "testuser".to_string(),
             text_entry: "test text".to_string(),
-            category1: "Option 1A".to_string(),
-            category2: "Option 2A".to_string(),
-            category3: "Option 3A".to_string(),
-            category4: "Option 4A".to_string(),
+            categories: BTreeMap::from([("category1".to_string(), "Option 1A".to_string())]),
             is_recording: false,
             last_saved: None,
             last_data: None,
         }
     }
 
+    // Helper function to create a small codebook
+    fn create_test_codebook() -> Codebook {
+        Codebook {
+            fields: vec![CodebookField {
+                key: "category1".to_string(),
+                label: "Category 1".to_string(),
+                options: vec!["Option 1A".to_string(), "Option 1B".to_string()],
+            }],
+        }
+    }
+
     #[wasm_bindgen_test]
     fn test_login_screen() {
         create_scope(create_runtime(), |cx| {
             // Track when the login callback is called
             let login_called = create_rw_signal(cx, false);
             let username_captured = create_rw_signal(cx, String::new());
+            let password_captured = create_rw_signal(cx, String::new());
+            let error = create_rw_signal(cx, None::<String>);
             
             // Create on_login callback that updates our tracking signals
-            let on_login = move |username: String| {
+            let on_login = move |credentials: Credentials| {
                 login_called.set(true);
-                username_captured.set(username);
+                username_captured.set(credentials.username);
+                password_captured.set(credentials.password);
             };
+            let on_register = move |_: Credentials| {};
             
             // Mount the component
-            let _ = mount_to_body(cx, || view! { cx, <LoginScreen on_login=on_login/> });
+            let _ = mount_to_body(cx, || view! { cx,
+                <LoginScreen on_login=on_login on_register=on_register error=error/>
+            });
             
-            // Simulate entering a username
+            // Simulate entering a username and password
             let input = document().query_selector("#username").unwrap().unwrap();
             let input_element = input.dyn_into::<web_sys::HtmlInputElement>().unwrap();
             input_element.set_value("testuser");
+
+            let input = document().query_selector("#password").unwrap().unwrap();
+            let input_element = input.dyn_into::<web_sys::HtmlInputElement>().unwrap();
+            input_element.set_value("correct horse");
             
             // Simulate form submission
             let form = document().query_selector("form").unwrap().unwrap();
@@ -53,9 +76,10 @@ mod frontend_tests {
             event.prevent_default();
             form.dispatch_event(&event).unwrap();
             
-            // Verify that the login callback was called with the correct username
+            // Verify that the login callback was called with the correct credentials
             assert!(login_called.get());
             assert_eq!(username_captured.get(), "testuser");
+            assert_eq!(password_captured.get(), "correct horse");
         });
     }
     
@@ -71,20 +95,21 @@ mod frontend_tests {
             let field_value = create_rw_signal(cx, String::new());
             
             // Create field update callback
-            let on_update_field = move |field: &'static str, value: String| {
+            let on_update_field = move |(field, value): (StateField, String)| {
                 field_updated.set(true);
-                field_name.set(field.to_string());
                 field_value.set(value.clone());
                 
                 // Update the state (mimicking parent component behavior)
                 let mut current_state = state.get();
                 match field {
-                    "text_entry" => current_state.text_entry = value,
-                    "category1" => current_state.category1 = value,
-                    "category2" => current_state.category2 = value,
-                    "category3" => current_state.category3 = value,
-                    "category4" => current_state.category4 = value,
-                    _ => {}
+                    StateField::TextEntry => {
+                        field_name.set("text_entry".to_string());
+                        current_state.text_entry = value;
+                    }
+                    StateField::Category(key) => {
+                        field_name.set(key.clone());
+                        current_state.categories.insert(key, value);
+                    }
                 }
                 state.set(current_state);
             };
@@ -104,13 +129,17 @@ mod frontend_tests {
                 state.set(current_state);
             };
             
+            let on_logout = move |_: ()| {};
+
             // Mount the component
             let _ = mount_to_body(cx, || {
                 view! { cx,
                     <DataEntryScreen
                         state=state
+                        codebook=create_test_codebook()
                         on_update_field=on_update_field
                         on_toggle_recording=on_toggle_recording
+                        on_logout=on_logout
                     />
                 }
             });
@@ -148,7 +177,7 @@ mod frontend_tests {
             };
             
             // Setup test options
-            let options = vec!["Option A", "Option B", "Option C"];
+            let options = vec!["Option A".to_string(), "Option B".to_string(), "Option C".to_string()];
             let current_value = create_rw_signal(cx, "Option A".to_string());
             let value_fn = move || current_value.get();
             
@@ -158,7 +187,7 @@ mod frontend_tests {
                     <DropdownSelect
                         id="test-dropdown"
                         label="Test Dropdown"
-                        options=options
+                        options=options.clone()
                         value=value_fn
                         on_change=on_change
                     />